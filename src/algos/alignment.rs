@@ -0,0 +1,336 @@
+use crate::algos::distances::PEQ_SIZE;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/*
+Seq-lev alignment with traceback
+The distance kernels only keep the score, to trim a barcode off a read we need to know where
+    the barcode ended in the read and which edits got it there
+a is the reference (barcode) and b the query (read), as in SAM:
+    = and X consume both, I is a base of b missing from a, D a base of a missing from b
+Myers bit vectors with traceback (Hyyrö 2004):
+    a is the pattern (rows, split into 64 bit blocks like SequenceLevenshteinDistanceBlocked)
+        and b the text (columns), the Pv/Mv words of every column are kept
+    D[i][j] = j + popcount(Pv_j below row i) - popcount(Mv_j below row i), so any cell of the
+        matrix is recovered from (n/64) words without storing the matrix itself
+    Memory is m * ceil(n / 64) * 2 words instead of n * m cells
+Seq-lev end:
+    The alignment starts at (0, 0) and ends on the last row (all of a used) or the last column
+        (all of b used), whatever is left of the other sequence is the overhang and is free
+    Among the ends with the minimum distance the one with the smallest overhang wins, ties go
+        to the last row
+The traceback prefers =, then X, then D, then I
+*/
+
+const BLOCK_BITS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CigarOp {
+    Match,
+    Mismatch,
+    Insertion,
+    Deletion,
+}
+
+impl CigarOp {
+    pub fn symbol(&self) -> char {
+        match self {
+            CigarOp::Match => '=',
+            CigarOp::Mismatch => 'X',
+            CigarOp::Insertion => 'I',
+            CigarOp::Deletion => 'D',
+        }
+    }
+
+    pub fn consumes_a(&self) -> bool {
+        !matches!(self, CigarOp::Insertion)
+    }
+
+    pub fn consumes_b(&self) -> bool {
+        !matches!(self, CigarOp::Deletion)
+    }
+}
+
+// Run length encoded edit script
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cigar {
+    pub ops: Vec<(CigarOp, usize)>,
+}
+
+impl Cigar {
+    pub fn new() -> Self {
+        Cigar { ops: Vec::new() }
+    }
+
+    pub fn push(&mut self, op: CigarOp) {
+        match self.ops.last_mut() {
+            Some((last, count)) if *last == op => *count += 1,
+            _ => self.ops.push((op, 1)),
+        }
+    }
+
+    // Edits in the script (X, I and D)
+    pub fn edits(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|(op, _)| *op != CigarOp::Match)
+            .map(|&(_, count)| count)
+            .sum()
+    }
+
+    pub fn a_len(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|(op, _)| op.consumes_a())
+            .map(|&(_, count)| count)
+            .sum()
+    }
+
+    pub fn b_len(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|(op, _)| op.consumes_b())
+            .map(|&(_, count)| count)
+            .sum()
+    }
+
+    fn reverse(&mut self) {
+        self.ops.reverse();
+    }
+}
+
+impl fmt::Display for Cigar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (op, count) in &self.ops {
+            write!(f, "{}{}", count, op.symbol())?;
+        }
+        Ok(())
+    }
+}
+
+// Aligned ranges are a[a_start..a_end] and b[b_start..b_end]
+// One of the overhangs is always 0, it is the rest of the sequence that was not reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeqLevAlignment {
+    pub distance: usize,
+    pub cigar: Cigar,
+    pub a_start: usize,
+    pub a_end: usize,
+    pub b_start: usize,
+    pub b_end: usize,
+    pub a_overhang: usize,
+    pub b_overhang: usize,
+}
+
+// Pv/Mv of every column, column j covers the first j bases of b
+struct MyersColumns {
+    blocks: usize,
+    pv: Vec<u64>,
+    mv: Vec<u64>,
+}
+
+impl MyersColumns {
+    fn compute(a: &[u8], b: &[u8]) -> Self {
+        let n = a.len();
+        let blocks = n.div_ceil(BLOCK_BITS).max(1);
+        let mut peq = vec![[0u64; PEQ_SIZE]; blocks];
+        for (i, &base) in a.iter().enumerate() {
+            peq[i / BLOCK_BITS][base as usize] |= 1 << (i % BLOCK_BITS);
+        }
+
+        let hb = 1u64 << (BLOCK_BITS - 1);
+        let mut pv = vec![!0u64; blocks * (b.len() + 1)];
+        let mut mv = vec![0u64; blocks * (b.len() + 1)];
+        for (j, &base) in b.iter().enumerate() {
+            // The top row is D[0][j] = j, block 0 always gets a +1 carry in
+            let mut carry = 1;
+            for block in 0..blocks {
+                let prev = j * blocks + block;
+                let (p, m) = (pv[prev], mv[prev]);
+                let mut eq = peq[block][base as usize];
+                let xv = eq | m;
+                // A -1 carry in acts like a match on the first row of the block
+                if carry < 0 {
+                    eq |= 1;
+                }
+                let xh = ((eq & p).wrapping_add(p) ^ p) | eq;
+                let mut ph = m | !(xh | p);
+                let mut mh = p & xh;
+                let carry_out = if ph & hb != 0 {
+                    1
+                } else if mh & hb != 0 {
+                    -1
+                } else {
+                    0
+                };
+                ph <<= 1;
+                mh <<= 1;
+                if carry < 0 {
+                    mh |= 1;
+                } else if carry > 0 {
+                    ph |= 1;
+                }
+                pv[prev + blocks] = mh | !(xv | ph);
+                mv[prev + blocks] = ph & xv;
+                carry = carry_out;
+            }
+        }
+        MyersColumns { blocks, pv, mv }
+    }
+
+    // D[i][j], the bits above row n of the last block never reach the rows below
+    fn value(&self, i: usize, j: usize) -> usize {
+        let column = j * self.blocks;
+        let mut value = j as isize;
+        for block in 0..i.div_ceil(BLOCK_BITS) {
+            let bits = (i - block * BLOCK_BITS).min(BLOCK_BITS);
+            let mask = if bits == BLOCK_BITS {
+                !0
+            } else {
+                (1u64 << bits) - 1
+            };
+            value += (self.pv[column + block] & mask).count_ones() as isize;
+            value -= (self.mv[column + block] & mask).count_ones() as isize;
+        }
+        value as usize
+    }
+}
+
+// Seq-lev alignment of a (barcode) against b (read), the distance agrees with
+//     SequenceLevenshteinDistanceWagner
+pub fn align_seq_lev(a: &[u8], b: &[u8]) -> SeqLevAlignment {
+    let n = a.len();
+    let m = b.len();
+    let columns = MyersColumns::compute(a, b);
+
+    // (distance, overhang, end), the last row comes first so it wins ties
+    let last_row = (0..=m).map(|j| (columns.value(n, j), m - j, (n, j)));
+    let last_col = (0..=n).map(|i| (columns.value(i, m), n - i, (i, m)));
+    let (distance, _, (a_end, b_end)) = last_row
+        .chain(last_col)
+        .min_by_key(|&(d, overhang, _)| (d, overhang))
+        .unwrap();
+
+    let mut cigar = Cigar::new();
+    let (mut i, mut j) = (a_end, b_end);
+    while i > 0 || j > 0 {
+        let here = columns.value(i, j);
+        let op = if i > 0 && j > 0 && a[i - 1] == b[j - 1] {
+            CigarOp::Match
+        } else if i > 0 && j > 0 && columns.value(i - 1, j - 1) + 1 == here {
+            CigarOp::Mismatch
+        } else if i > 0 && columns.value(i - 1, j) + 1 == here {
+            CigarOp::Deletion
+        } else {
+            CigarOp::Insertion
+        };
+        if op.consumes_a() {
+            i -= 1;
+        }
+        if op.consumes_b() {
+            j -= 1;
+        }
+        cigar.push(op);
+    }
+    cigar.reverse();
+
+    SeqLevAlignment {
+        distance,
+        cigar,
+        a_start: 0,
+        a_end,
+        b_start: 0,
+        b_end,
+        a_overhang: n - a_end,
+        b_overhang: m - b_end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{Distance, SequenceLevenshteinDistanceWagner};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Replays the script and counts its edits, checking every = and X against the bases
+    fn replay(alignment: &SeqLevAlignment, a: &[u8], b: &[u8]) -> usize {
+        let (mut i, mut j) = (alignment.a_start, alignment.b_start);
+        for &(op, count) in &alignment.cigar.ops {
+            for _ in 0..count {
+                match op {
+                    CigarOp::Match => assert_eq!(a[i], b[j]),
+                    CigarOp::Mismatch => assert_ne!(a[i], b[j]),
+                    _ => {}
+                }
+                i += op.consumes_a() as usize;
+                j += op.consumes_b() as usize;
+            }
+        }
+        assert_eq!((i, j), (alignment.a_end, alignment.b_end));
+        alignment.cigar.edits()
+    }
+
+    #[test]
+    fn test_align_seq_lev_examples() {
+        // Barcode followed by the insert, a deleted base shifts the end of the barcode left
+        let alignment = align_seq_lev(b"ACGTACGT", b"ACGACGTTTTT");
+        assert_eq!(alignment.distance, 1);
+        assert_eq!(alignment.cigar.to_string(), "3=1D4=");
+        assert_eq!((alignment.a_end, alignment.b_end), (8, 7));
+        assert_eq!((alignment.a_overhang, alignment.b_overhang), (0, 4));
+
+        // An inserted base pushes the rest of the barcode right
+        let alignment = align_seq_lev(b"ACGTACGT", b"ACGTTACGTGG");
+        assert_eq!(alignment.distance, 1);
+        assert_eq!(alignment.cigar.to_string(), "3=1I5=");
+        assert_eq!(alignment.b_end, 9);
+
+        // The read runs out before the barcode, the rest of the barcode is the overhang
+        let alignment = align_seq_lev(b"ACGTACGT", b"ACGTA");
+        assert_eq!(alignment.distance, 0);
+        assert_eq!(alignment.cigar.to_string(), "5=");
+        assert_eq!((alignment.a_overhang, alignment.b_overhang), (3, 0));
+
+        let alignment = align_seq_lev(b"ACGT", b"AGGT");
+        assert_eq!(alignment.cigar.to_string(), "1=1X2=");
+
+        let alignment = align_seq_lev(b"", b"ACGT");
+        assert_eq!(alignment.distance, 0);
+        assert_eq!(alignment.cigar.to_string(), "");
+        assert_eq!(alignment.b_overhang, 4);
+    }
+
+    #[test]
+    fn test_align_seq_lev_matches_wagner() {
+        let mut rng = StdRng::seed_from_u64(21);
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        for _ in 0..300 {
+            let len = rng.gen_range(0..=150);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let mut b = Vec::with_capacity(len + 8);
+            for &base in &a {
+                match rng.gen_range(0..15) {
+                    0 => {}
+                    1 => b.extend([base, b"ACGT"[rng.gen_range(0..4)]]),
+                    2 => b.push(b"ACGT"[rng.gen_range(0..4)]),
+                    _ => b.push(base),
+                }
+            }
+            let cut = rng.gen_range(0..=b.len() / 4);
+            b.truncate(b.len() - cut);
+            b.extend((0..rng.gen_range(0..8)).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+
+            for (x, y) in [(&a, &b), (&b, &a)] {
+                let alignment = align_seq_lev(x, y);
+                assert_eq!(alignment.distance, wagner.distance(x, y));
+                assert_eq!(replay(&alignment, x, y), alignment.distance);
+                assert_eq!(alignment.cigar.a_len(), alignment.a_end);
+                assert_eq!(alignment.cigar.b_len(), alignment.b_end);
+                assert!(alignment.a_overhang == 0 || alignment.b_overhang == 0);
+                assert_eq!(alignment.a_end + alignment.a_overhang, x.len());
+                assert_eq!(alignment.b_end + alignment.b_overhang, y.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::algos::common::{complement_base, reverse_complement};
+use crate::algos::seq_gen::BarcodeConstraint;
+
+/*
+Biochemical filters for generated barcodes
+Every filter implements BarcodeConstraint and explains itself in the Err when it rejects, so
+    they can be stacked in a Vec<Box<dyn BarcodeConstraint>> and handed to design_barcode_set
+    GcContent           GC fraction inside [min, max], extreme GC synthesizes and amplifies badly
+    MaxHomopolymer      longest run of one base, long runs cause synthesis and phasing errors
+    ForbiddenMotifs     restriction sites, adapter pieces, ... on either strand
+    SelfComplementarity longest hairpin stem (complementary pairs with a loop in between)
+    TwoChannelBalance   Illumina two-channel chemistry (NextSeq, NovaSeq): A is red + green,
+                        C red, T green, G dark. Every cycle needs signal in both channels, this
+                        one looks at the set built so far instead of the candidate alone, it
+                        is checked when a barcode is inserted, the set designer rechecks the
+                        whole set after the removals of its evolutionary search
+*/
+
+// All reasons a candidate fails, empty when it passes every constraint
+pub fn rejection_reasons(
+    constraints: &[Box<dyn BarcodeConstraint>],
+    candidate: &[u8],
+    accepted: &[Vec<u8>],
+) -> Vec<String> {
+    constraints
+        .iter()
+        .filter_map(|c| c.check(candidate, accepted).err())
+        .collect()
+}
+
+pub fn gc_fraction(seq: &[u8]) -> f64 {
+    if seq.is_empty() {
+        return 0.0;
+    }
+    let gc = seq.iter().filter(|&&b| b == b'G' || b == b'C').count();
+    gc as f64 / seq.len() as f64
+}
+
+// Length of the longest run of a single base
+pub fn longest_homopolymer(seq: &[u8]) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for (i, &base) in seq.iter().enumerate() {
+        run = if i > 0 && seq[i - 1] == base {
+            run + 1
+        } else {
+            1
+        };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+// Longest stem the sequence can fold into with at least min_loop unpaired bases in the loop
+// Only Watson-Crick pairs, no bulges, which is plenty for barcode length sequences
+pub fn hairpin_stem(seq: &[u8], min_loop: usize) -> usize {
+    let n = seq.len();
+    let mut best = 0;
+    // i pairs with j, the stem grows inwards (i + t with j - t)
+    for i in 0..n {
+        for j in (i + min_loop + 1)..n {
+            let mut stem = 0;
+            while i + stem < j - stem
+                && (j - stem) - (i + stem) > min_loop
+                && complement_base(seq[i + stem]) == seq[j - stem]
+            {
+                stem += 1;
+            }
+            best = best.max(stem);
+        }
+    }
+    best
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcContent {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl GcContent {
+    pub fn new(min: f64, max: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&min) && (0.0..=1.0).contains(&max) && min <= max,
+            "GC window must satisfy 0 <= min <= max <= 1"
+        );
+        GcContent { min, max }
+    }
+}
+
+impl BarcodeConstraint for GcContent {
+    fn check(&self, candidate: &[u8], _accepted: &[Vec<u8>]) -> Result<(), String> {
+        let gc = gc_fraction(candidate);
+        if gc < self.min || gc > self.max {
+            return Err(format!(
+                "GC content {:.2} outside [{:.2}, {:.2}]",
+                gc, self.min, self.max
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaxHomopolymer(pub usize);
+
+impl BarcodeConstraint for MaxHomopolymer {
+    fn check(&self, candidate: &[u8], _accepted: &[Vec<u8>]) -> Result<(), String> {
+        let run = longest_homopolymer(candidate);
+        if run > self.0 {
+            return Err(format!("Homopolymer run of {} exceeds {}", run, self.0));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForbiddenMotifs {
+    motifs: Vec<Vec<u8>>,
+}
+
+impl ForbiddenMotifs {
+    // Reverse complements are added so a motif is caught on either strand
+    pub fn new(motifs: &[&[u8]]) -> Self {
+        let mut all: Vec<Vec<u8>> = Vec::with_capacity(motifs.len() * 2);
+        for motif in motifs {
+            assert!(!motif.is_empty(), "Empty forbidden motif");
+            let motif = motif.to_ascii_uppercase();
+            let reverse = reverse_complement(&motif);
+            for m in [motif, reverse] {
+                if !all.contains(&m) {
+                    all.push(m);
+                }
+            }
+        }
+        ForbiddenMotifs { motifs: all }
+    }
+
+    pub fn motifs(&self) -> &[Vec<u8>] {
+        &self.motifs
+    }
+}
+
+impl BarcodeConstraint for ForbiddenMotifs {
+    fn check(&self, candidate: &[u8], _accepted: &[Vec<u8>]) -> Result<(), String> {
+        for motif in &self.motifs {
+            if let Some(position) = candidate
+                .windows(motif.len())
+                .position(|w| w == motif.as_slice())
+            {
+                return Err(format!(
+                    "Contains forbidden motif {} at {}",
+                    String::from_utf8_lossy(motif),
+                    position
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfComplementarity {
+    pub max_stem: usize,
+    pub min_loop: usize,
+}
+
+impl BarcodeConstraint for SelfComplementarity {
+    fn check(&self, candidate: &[u8], _accepted: &[Vec<u8>]) -> Result<(), String> {
+        let stem = hairpin_stem(candidate, self.min_loop);
+        if stem > self.max_stem {
+            return Err(format!(
+                "Hairpin stem of {} bp exceeds {}",
+                stem, self.max_stem
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Per cycle fraction of bases with signal in the (red, green) channel
+pub fn two_channel_signal(set: &[Vec<u8>]) -> Vec<(f64, f64)> {
+    let length = set.iter().map(|b| b.len()).max().unwrap_or(0);
+    (0..length)
+        .map(|position| {
+            let bases: Vec<u8> = set
+                .iter()
+                .filter_map(|b| b.get(position).copied())
+                .collect();
+            let red = bases.iter().filter(|&&b| b == b'A' || b == b'C').count();
+            let green = bases.iter().filter(|&&b| b == b'A' || b == b'T').count();
+            (
+                red as f64 / bases.len() as f64,
+                green as f64 / bases.len() as f64,
+            )
+        })
+        .collect()
+}
+
+// Bounds, per cycle, the share of barcodes without red signal (G, T) and without green
+// signal (G, C) to max_missing_fraction of the set
+// Small sets always get one barcode of slack, otherwise no single barcode could start a set
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoChannelBalance {
+    pub max_missing_fraction: f64,
+}
+
+impl TwoChannelBalance {
+    pub fn new(max_missing_fraction: f64) -> Self {
+        assert!(
+            (0.0..1.0).contains(&max_missing_fraction),
+            "Max missing fraction must be in [0, 1)"
+        );
+        TwoChannelBalance {
+            max_missing_fraction,
+        }
+    }
+}
+
+impl BarcodeConstraint for TwoChannelBalance {
+    fn check(&self, candidate: &[u8], accepted: &[Vec<u8>]) -> Result<(), String> {
+        let size = accepted.len() + 1;
+        let allowed = ((self.max_missing_fraction * size as f64).floor() as usize).max(1);
+        for (position, &base) in candidate.iter().enumerate() {
+            let bases_at = || accepted.iter().filter_map(|b| b.get(position));
+            if matches!(base, b'G' | b'T') {
+                let missing = 1 + bases_at().filter(|&&b| matches!(b, b'G' | b'T')).count();
+                if missing > allowed {
+                    return Err(format!(
+                        "Cycle {} would lack red signal in {} of {} barcodes",
+                        position + 1,
+                        missing,
+                        size
+                    ));
+                }
+            }
+            if matches!(base, b'G' | b'C') {
+                let missing = 1 + bases_at().filter(|&&b| matches!(b, b'G' | b'C')).count();
+                if missing > allowed {
+                    return Err(format!(
+                        "Cycle {} would lack green signal in {} of {} barcodes",
+                        position + 1,
+                        missing,
+                        size
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::barcode_matcher::MatchMetric;
+    use crate::algos::seq_gen::design_barcode_set;
+
+    #[test]
+    fn test_single_barcode_filters() {
+        assert!(GcContent::new(0.4, 0.6).check(b"ACGTACGT", &[]).is_ok());
+        let err = GcContent::new(0.4, 0.6)
+            .check(b"AAAATAGC", &[])
+            .unwrap_err();
+        assert!(err.contains("GC content 0.25"), "{}", err);
+
+        assert_eq!(longest_homopolymer(b"ACGGGTTA"), 3);
+        assert!(MaxHomopolymer(3).check(b"ACGGGTTA", &[]).is_ok());
+        assert!(MaxHomopolymer(2).check(b"ACGGGTTA", &[]).is_err());
+
+        // EcoRI is its own reverse complement, the adapter piece is caught on the minus strand
+        let motifs = ForbiddenMotifs::new(&[b"GAATTC", b"AGATCG"]);
+        assert_eq!(motifs.motifs().len(), 3);
+        assert!(motifs.check(b"TTGAATTCAA", &[]).is_err());
+        let err = motifs.check(b"ACGATCTA", &[]).unwrap_err();
+        assert!(err.contains("CGATCT at 1"), "{}", err);
+        assert!(motifs.check(b"ACGTACGT", &[]).is_ok());
+
+        // GGGC pairs with GCCC around a 3 base loop
+        assert_eq!(hairpin_stem(b"GGGCAAAGCCC", 3), 4);
+        assert_eq!(hairpin_stem(b"AAAAAAAA", 3), 0);
+        let hairpin = SelfComplementarity {
+            max_stem: 3,
+            min_loop: 3,
+        };
+        assert!(hairpin.check(b"GGGCAAAGCCC", &[]).is_err());
+        assert!(hairpin.check(b"GGGCAAAGCCA", &[]).is_ok());
+
+        let constraints: Vec<Box<dyn BarcodeConstraint>> = vec![
+            Box::new(GcContent::new(0.4, 0.6)),
+            Box::new(MaxHomopolymer(2)),
+        ];
+        assert_eq!(rejection_reasons(&constraints, b"AAAAAAAA", &[]).len(), 2);
+        assert!(rejection_reasons(&constraints, b"ACGTACGT", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_two_channel_balance() {
+        let balance = TwoChannelBalance::new(0.5);
+        // Any single barcode can start a set
+        assert!(balance.check(b"GGGG", &[]).is_ok());
+        let accepted = vec![b"GGGG".to_vec()];
+        // Two dark barcodes leave every cycle without signal
+        let err = balance.check(b"GGAA", &accepted).unwrap_err();
+        assert!(err.starts_with("Cycle 1 would lack red signal"), "{}", err);
+        assert!(balance.check(b"AAAA", &accepted).is_ok());
+
+        let accepted = vec![b"GGGG".to_vec(), b"AAAA".to_vec()];
+        assert_eq!(two_channel_signal(&accepted), vec![(0.5, 0.5); 4]);
+    }
+
+    #[test]
+    fn test_design_with_constraints() {
+        let constraints: Vec<Box<dyn BarcodeConstraint>> = vec![
+            Box::new(GcContent::new(0.25, 0.75)),
+            Box::new(MaxHomopolymer(2)),
+            Box::new(ForbiddenMotifs::new(&[b"GAATTC"])),
+            Box::new(SelfComplementarity {
+                max_stem: 2,
+                min_loop: 3,
+            }),
+            Box::new(TwoChannelBalance::new(0.75)),
+        ];
+        let set = design_barcode_set(8, 3, MatchMetric::SequenceLevenshtein, 48, &constraints, 5);
+        assert_eq!(set.barcodes.len(), 48);
+        for barcode in &set.barcodes {
+            assert!(rejection_reasons(&constraints[..4], barcode, &[]).is_empty());
+        }
+        for (red, green) in two_channel_signal(&set.barcodes) {
+            assert!(red >= 0.25 && green >= 0.25);
+        }
+    }
+}
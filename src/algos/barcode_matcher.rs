@@ -0,0 +1,592 @@
+use crate::algos::bit_packed_ham::MultiIndexHamming;
+use crate::algos::bktree::BkTree;
+use crate::algos::deletion_index::DeletionIndex;
+use crate::algos::distances::{BoundedDistance, LevenshteinDistance, SequenceLevenshteinDistance};
+use crate::algos::iupac::{self, NPolicy};
+use crate::algos::lev_automaton::{AutomatonMode, BarcodeTrie, MAX_AUTOMATON_DISTANCE};
+use crate::algos::quality::{posteriors_with_background, QualityModel};
+use serde::{Deserialize, Serialize};
+
+/*
+High level "which whitelist barcode is this read" API
+The matcher owns the index, callers only pick a metric and a max distance:
+    Hamming                  -> multi-index hashing on the bit-packed words
+    Seq-lev, k <= 2          -> symmetric deletion index verified with the Myers kernel,
+                                windows cut short by the read end are scanned
+    Seq-lev / Lev, k <= 3    -> Levenshtein automaton walked over a trie of the whitelist
+    Seq-lev, k > 3           -> linear scan with the bounded Myers kernel
+    Lev, k > 3               -> BK-tree
+Seq-lev is not a metric, a BK-tree stays exact only with the much wider window of
+    Distance::lower_bound (see distances.rs), so above the automaton limit a scan is faster
+Every backend returns all barcodes within k, which is what lets us report the runner up
+    and flag ties instead of silently picking one
+Ties are broken on the lowest barcode id so results never depend on hash or thread order
+The quality aware variants rescore the same candidates with Phred qualities (see quality.rs)
+    and report the posterior of the best barcode among them, so a mismatch at Q2 no longer
+    weighs as much as one at Q40
+Barcodes outside max distance (bounded by max distance + 1 errors) and reads that come from
+    no barcode at all (a random sequence) are folded into a background (see quality.rs), so a
+    lone candidate with several high quality mismatches still gets a low posterior
+IUPAC codes follow the NPolicy (see iupac.rs) for Hamming and seq-lev:
+    Hamming packs base sets, so ambiguity codes on either side are handled by the index
+    The deletion index and the automaton compare bytes, a whitelist with anything but ACGT
+        gets the scan instead, and a read window with an ambiguity code (or N under
+        NPolicy::Match) is scanned instead of looked up
+Levenshtein and the quality rescoring still compare bytes, an ambiguity code counts as a
+    mismatch there
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchMetric {
+    Hamming,
+    SequenceLevenshtein,
+    Levenshtein,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Match {
+    pub barcode_id: usize,
+    pub distance: usize,
+    // Distance of the closest other barcode within max distance
+    pub second_best_distance: Option<usize>,
+    // Another barcode is exactly as close, barcode_id is the lowest of the tied ids
+    pub ambiguous: bool,
+    // Offset in the read where the barcode window starts
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QualityMatch {
+    pub barcode_id: usize,
+    // Edit/Hamming distance of the chosen barcode, not necessarily the smallest candidate distance
+    pub distance: usize,
+    pub posterior: f64,
+    // Best posterior among the other candidates, 0.0 when there are none
+    pub second_best_posterior: f64,
+    pub position: usize,
+}
+
+enum Backend {
+    MultiIndex(MultiIndexHamming),
+    Deletion(DeletionIndex),
+    Automaton(BarcodeTrie, AutomatonMode),
+    SequenceLevenshteinScan,
+    LevenshteinTree(BkTree<String, LevenshteinDistance>),
+}
+
+pub struct BarcodeMatcher {
+    whitelist: Vec<Vec<u8>>,
+    barcode_length: usize,
+    metric: MatchMetric,
+    max_distance: usize,
+    n_policy: NPolicy,
+    backend: Backend,
+    // IUPAC aware seq-lev for the scan backend and for windows the index backends can't
+    //     compare bytewise
+    iupac_scan: SequenceLevenshteinDistance,
+}
+
+impl BarcodeMatcher {
+    pub fn new(whitelist: &[Vec<u8>], metric: MatchMetric, max_distance: usize) -> Self {
+        Self::with_n_policy(whitelist, metric, max_distance, NPolicy::Mismatch)
+    }
+
+    pub fn with_n_policy(
+        whitelist: &[Vec<u8>],
+        metric: MatchMetric,
+        max_distance: usize,
+        n_policy: NPolicy,
+    ) -> Self {
+        let barcode_length = whitelist.first().map_or(0, |b| b.len());
+        assert!(
+            whitelist.iter().all(|b| b.len() == barcode_length),
+            "Whitelist barcodes must all have the same length"
+        );
+        let plain_whitelist = whitelist.iter().flatten().all(|b| b"ACGT".contains(b));
+        let iupac_scan = SequenceLevenshteinDistance::new().with_iupac(n_policy);
+
+        let backend = match metric {
+            MatchMetric::Hamming => Backend::MultiIndex(MultiIndexHamming::with_n_policy(
+                whitelist,
+                max_distance,
+                n_policy,
+            )),
+            MatchMetric::SequenceLevenshtein if !plain_whitelist => {
+                Backend::SequenceLevenshteinScan
+            }
+            MatchMetric::SequenceLevenshtein if max_distance <= 2 => {
+                Backend::Deletion(DeletionIndex::build(whitelist, max_distance))
+            }
+            MatchMetric::SequenceLevenshtein if max_distance <= MAX_AUTOMATON_DISTANCE => {
+                Backend::Automaton(
+                    BarcodeTrie::build(whitelist),
+                    AutomatonMode::SequenceLevenshtein,
+                )
+            }
+            MatchMetric::Levenshtein if (1..=MAX_AUTOMATON_DISTANCE).contains(&max_distance) => {
+                Backend::Automaton(BarcodeTrie::build(whitelist), AutomatonMode::Levenshtein)
+            }
+            MatchMetric::SequenceLevenshtein => Backend::SequenceLevenshteinScan,
+            MatchMetric::Levenshtein => Backend::LevenshteinTree(BkTree::build(
+                LevenshteinDistance::new(),
+                whitelist
+                    .iter()
+                    .map(|b| String::from_utf8_lossy(b).into_owned()),
+            )),
+        };
+
+        BarcodeMatcher {
+            whitelist: whitelist.to_vec(),
+            barcode_length,
+            metric,
+            max_distance,
+            n_policy,
+            backend,
+            iupac_scan,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.whitelist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.whitelist.is_empty()
+    }
+
+    pub fn metric(&self) -> MatchMetric {
+        self.metric
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    pub fn n_policy(&self) -> NPolicy {
+        self.n_policy
+    }
+
+    pub fn barcode_length(&self) -> usize {
+        self.barcode_length
+    }
+
+    pub fn barcode(&self, barcode_id: usize) -> Option<&[u8]> {
+        self.whitelist.get(barcode_id).map(|b| b.as_slice())
+    }
+
+    // Every barcode within max distance of the query as (id, distance), sorted by distance then id
+    pub fn candidates(&self, query: &[u8]) -> Vec<(usize, usize)> {
+        match &self.backend {
+            Backend::MultiIndex(index) => index.find_within(query),
+            Backend::Deletion(_) | Backend::Automaton(..) if self.needs_iupac(query) => {
+                self.scan(query)
+            }
+            // Deletion variants only cover queries of the barcode length
+            Backend::Deletion(_) if query.len() != self.barcode_length => self.scan(query),
+            Backend::Deletion(index) => index.find_within(query),
+            Backend::Automaton(trie, mode) => trie.find_within(query, self.max_distance, *mode),
+            Backend::SequenceLevenshteinScan => self.scan(query),
+            Backend::LevenshteinTree(tree) => tree.find_within_ids(
+                &String::from_utf8_lossy(query).into_owned(),
+                self.max_distance,
+            ),
+        }
+    }
+
+    // A byte other than ACGT that still matches some base under the policy
+    // The byte-based index backends would count it as a mismatch
+    fn needs_iupac(&self, query: &[u8]) -> bool {
+        query
+            .iter()
+            .any(|&b| !b"ACGT".contains(&b) && iupac::base_set(b, self.n_policy) != 0)
+    }
+
+    fn scan(&self, query: &[u8]) -> Vec<(usize, usize)> {
+        let mut found: Vec<(usize, usize)> = self
+            .whitelist
+            .iter()
+            .enumerate()
+            .filter_map(|(id, barcode)| {
+                self.iupac_scan
+                    .distance_within(barcode.as_slice(), query, self.max_distance)
+                    .map(|d| (id, d))
+            })
+            .collect();
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+
+    // Match the barcode-length window starting at position
+    pub fn assign_at(&self, read: &[u8], position: usize) -> Option<Match> {
+        let window = self.window(read, position)?;
+        let candidates = self.candidates(window);
+        let &(barcode_id, distance) = candidates.first()?;
+        let second_best_distance = candidates.get(1).map(|&(_, d)| d);
+        Some(Match {
+            barcode_id,
+            distance,
+            second_best_distance,
+            ambiguous: second_best_distance == Some(distance),
+            position,
+        })
+    }
+
+    // Barcode-length window starting at position, None when the metric cannot use it
+    fn window<'a>(&self, read: &'a [u8], position: usize) -> Option<&'a [u8]> {
+        if position >= read.len() {
+            return None;
+        }
+        let end = (position + self.barcode_length).min(read.len());
+        let window = &read[position..end];
+        // Hamming needs the full window, the edit distance metrics can absorb a short read
+        if self.metric == MatchMetric::Hamming && window.len() != self.barcode_length {
+            return None;
+        }
+        Some(window)
+    }
+
+    // Rescore the candidates of the window at position with base qualities
+    // qual must be the quality string of the whole read
+    pub fn assign_quality_at(
+        &self,
+        read: &[u8],
+        qual: &[u8],
+        position: usize,
+        model: &QualityModel,
+    ) -> Option<QualityMatch> {
+        assert_eq!(read.len(), qual.len(), "Read and quality lengths differ");
+        let window = self.window(read, position)?;
+        let window_qual = &qual[position..position + window.len()];
+        let candidates = self.candidates(window);
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let log_likelihoods: Vec<f64> = candidates
+            .iter()
+            .map(|&(id, _)| {
+                let barcode = &self.whitelist[id];
+                match self.metric {
+                    MatchMetric::Hamming => {
+                        model.substitution_log_likelihood(barcode, window, window_qual)
+                    }
+                    MatchMetric::SequenceLevenshtein => {
+                        model.alignment_log_likelihood(barcode, window, window_qual, true)
+                    }
+                    MatchMetric::Levenshtein => {
+                        model.alignment_log_likelihood(barcode, window, window_qual, false)
+                    }
+                }
+            })
+            .collect();
+        let background = model.background_log_likelihood(
+            window_qual,
+            self.whitelist.len() - candidates.len(),
+            self.whitelist.len(),
+            self.max_distance,
+        );
+        let posteriors = posteriors_with_background(&log_likelihoods, background);
+
+        // Candidates come sorted by (distance, id), so ties keep the closer, lower id barcode
+        let mut best = 0;
+        for (i, &p) in posteriors.iter().enumerate() {
+            if p > posteriors[best] {
+                best = i;
+            }
+        }
+        let second_best_posterior = posteriors
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != best)
+            .map(|(_, &p)| p)
+            .fold(0.0, f64::max);
+        let (barcode_id, distance) = candidates[best];
+        Some(QualityMatch {
+            barcode_id,
+            distance,
+            posterior: posteriors[best],
+            second_best_posterior,
+            position,
+        })
+    }
+
+    // Only assign when the best barcode reaches min_posterior
+    pub fn assign_with_posterior(
+        &self,
+        read: &[u8],
+        qual: &[u8],
+        position: usize,
+        model: &QualityModel,
+        min_posterior: f64,
+    ) -> Option<QualityMatch> {
+        self.assign_quality_at(read, qual, position, model)
+            .filter(|m| m.posterior >= min_posterior)
+    }
+
+    // Match a read whose barcode starts at offset 0
+    pub fn assign(&self, read: &[u8]) -> Option<Match> {
+        self.assign_at(read, 0)
+    }
+
+    // Slide the barcode window over offsets 0..=max_position and keep the best match
+    // Lower distance wins, then unambiguous over ambiguous, then the earliest position
+    pub fn search(&self, read: &[u8], max_position: usize) -> Option<Match> {
+        (0..=max_position)
+            .filter_map(|position| self.assign_at(read, position))
+            .min_by_key(|m| (m.distance, m.ambiguous, m.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::Distance;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn whitelist() -> Vec<Vec<u8>> {
+        vec![
+            b"AAAACCCCGGGG".to_vec(),
+            b"AAAACCCCGGGT".to_vec(),
+            b"TTTTGGGGCCCC".to_vec(),
+            b"ACGTACGTACGT".to_vec(),
+            b"CATGCATGCATG".to_vec(),
+        ]
+    }
+
+    #[test]
+    fn test_assign_every_metric() {
+        for metric in [
+            MatchMetric::Hamming,
+            MatchMetric::SequenceLevenshtein,
+            MatchMetric::Levenshtein,
+        ] {
+            for max_distance in [1, 2, 3, 5] {
+                let matcher = BarcodeMatcher::new(&whitelist(), metric, max_distance);
+                let m = matcher.assign(b"TTTTGGGACCCC").unwrap();
+                assert_eq!((m.barcode_id, m.distance), (2, 1), "{:?}", metric);
+                assert!(!m.ambiguous);
+
+                // An N in the read is a mismatch, not a crash
+                let m = matcher.assign(b"TTTTGGGNCCCC").unwrap();
+                assert_eq!((m.barcode_id, m.distance), (2, 1), "{:?}", metric);
+
+                // Equidistant from the first two barcodes, lowest id wins and is flagged
+                let m = matcher.assign(b"AAAACCCCGGGA").unwrap();
+                assert_eq!((m.barcode_id, m.distance), (0, 1), "{:?}", metric);
+                assert_eq!(m.second_best_distance, Some(1));
+                assert!(m.ambiguous);
+            }
+        }
+    }
+
+    #[test]
+    fn test_backends_agree_with_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(53);
+        let whitelist: Vec<Vec<u8>> = (0..200)
+            .map(|_| (0..10).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+        let metric = SequenceLevenshteinDistance::new();
+
+        for max_distance in 1..=5 {
+            let matcher =
+                BarcodeMatcher::new(&whitelist, MatchMetric::SequenceLevenshtein, max_distance);
+            for q in 0..40 {
+                let mut read = whitelist[q].clone();
+                read.remove(rng.gen_range(0..10));
+                read.push(b'T');
+
+                let mut expected: Vec<(usize, usize)> = whitelist
+                    .iter()
+                    .enumerate()
+                    .map(|(id, b)| (id, metric.distance(b.as_slice(), read.as_slice())))
+                    .filter(|&(_, d)| d <= max_distance)
+                    .collect();
+                expected.sort_unstable_by_key(|&(id, d)| (d, id));
+                assert_eq!(matcher.candidates(&read), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_windows_agree_with_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let mut whitelist = vec![b"ACGTACGTAC".to_vec(), b"TTTTGGGGCC".to_vec()];
+        whitelist.extend((0..100).map(|_| {
+            (0..10)
+                .map(|_| b"ACGT"[rng.gen_range(0..4)])
+                .collect::<Vec<u8>>()
+        }));
+        let metric = SequenceLevenshteinDistance::new();
+
+        let matcher = BarcodeMatcher::new(&whitelist, MatchMetric::SequenceLevenshtein, 1);
+        assert_eq!(matcher.candidates(b"ACGTACG"), vec![(0, 0)]);
+
+        // Deletion index, automaton and scan
+        for max_distance in 1..=5 {
+            let matcher =
+                BarcodeMatcher::new(&whitelist, MatchMetric::SequenceLevenshtein, max_distance);
+            for q in 0..40 {
+                let read = &whitelist[q][..rng.gen_range(1..10)];
+                let mut expected: Vec<(usize, usize)> = whitelist
+                    .iter()
+                    .enumerate()
+                    .map(|(id, b)| (id, metric.distance(b.as_slice(), read)))
+                    .filter(|&(_, d)| d <= max_distance)
+                    .collect();
+                expected.sort_unstable_by_key(|&(id, d)| (d, id));
+                assert_eq!(matcher.candidates(read), expected, "k = {}", max_distance);
+                // Read ends inside the barcode window
+                let m = matcher.assign_at(read, 0).unwrap();
+                assert_eq!((m.barcode_id, m.distance), expected[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_reports_position() {
+        let matcher = BarcodeMatcher::new(&whitelist(), MatchMetric::Hamming, 1);
+        let read = b"GGACGTACGTACGTTTTT";
+        assert!(matcher.assign(read).is_none());
+
+        let m = matcher.search(read, 4).unwrap();
+        assert_eq!((m.barcode_id, m.distance, m.position), (3, 0, 2));
+        assert_eq!(m.second_best_distance, None);
+    }
+
+    #[test]
+    fn test_quality_breaks_distance_ties() {
+        let whitelist = vec![b"AAAACCCCGGGG".to_vec(), b"AAAACCCCGGGT".to_vec()];
+        // Last base is neither G nor T, distance 1 from both barcodes
+        let read = b"AAAACCCCGGGA";
+        let mut qual = vec![b'I'; 12];
+        for metric in [
+            MatchMetric::Hamming,
+            MatchMetric::SequenceLevenshtein,
+            MatchMetric::Levenshtein,
+        ] {
+            let matcher = BarcodeMatcher::new(&whitelist, metric, 1);
+            assert!(matcher.assign(read).unwrap().ambiguous);
+
+            let model = QualityModel::new();
+            let m = matcher.assign_quality_at(read, &qual, 0, &model).unwrap();
+            assert!(m.posterior < 0.5 && m.posterior > 0.49, "{:?}", metric);
+            assert!(
+                (m.posterior - m.second_best_posterior).abs() < 1e-9,
+                "{:?}",
+                metric
+            );
+            assert!(matcher
+                .assign_with_posterior(read, &qual, 0, &model, 0.9)
+                .is_none());
+        }
+
+        // A read that is one low quality base away from barcode 1 and one high quality base
+        // away from barcode 0
+        let whitelist = vec![b"AAAACCCCGGGG".to_vec(), b"TAAACCCCGGGT".to_vec()];
+        let read = b"TAAACCCCGGGG";
+        qual[0] = b'#'; // Q2
+        let matcher = BarcodeMatcher::new(&whitelist, MatchMetric::Hamming, 1);
+        let model = QualityModel::new();
+        let m = matcher
+            .assign_with_posterior(read, &qual, 0, &model, 0.99)
+            .unwrap();
+        assert_eq!(m.barcode_id, 0);
+        assert!(m.second_best_posterior < 0.01);
+
+        qual[0] = b'I';
+        qual[11] = b'#';
+        let m = matcher.assign_quality_at(read, &qual, 0, &model).unwrap();
+        assert_eq!((m.barcode_id, m.distance), (1, 1));
+    }
+
+    #[test]
+    fn test_lone_distant_candidate_is_rejected() {
+        let whitelist = vec![b"AAAACCCCGGGG".to_vec()];
+        let qual = vec![b'I'; 12];
+        let model = QualityModel::new();
+        let matcher = BarcodeMatcher::new(&whitelist, MatchMetric::Hamming, 3);
+
+        // Three high quality mismatches, closer to random sequence than to the barcode
+        let read = b"TTAACCCCGGGA";
+        let m = matcher.assign_quality_at(read, &qual, 0, &model).unwrap();
+        assert_eq!((m.barcode_id, m.distance), (0, 3));
+        assert!(m.posterior < 0.5, "{}", m.posterior);
+        assert!(matcher
+            .assign_with_posterior(read, &qual, 0, &model, 0.9)
+            .is_none());
+
+        // One mismatch is still a confident assignment
+        let m = matcher
+            .assign_with_posterior(b"AAAACCCCGGGA", &qual, 0, &model, 0.9)
+            .unwrap();
+        assert_eq!(m.barcode_id, 0);
+    }
+
+    #[test]
+    fn test_posteriors_on_full_plate() {
+        // 96 barcodes of 8 bases, pairwise Hamming distance >= 3, like a plate sample sheet
+        let mut rng = StdRng::seed_from_u64(96);
+        let hamming = crate::algos::distances::HammingDistanceSimd::new();
+        let mut whitelist: Vec<Vec<u8>> = Vec::new();
+        while whitelist.len() < 96 {
+            let barcode: Vec<u8> = (0..8).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            if whitelist
+                .iter()
+                .all(|b| hamming.distance(b.as_slice(), barcode.as_slice()) >= 3)
+            {
+                whitelist.push(barcode);
+            }
+        }
+        let matcher = BarcodeMatcher::new(&whitelist, MatchMetric::Hamming, 1);
+        let model = QualityModel::new();
+
+        for (id, barcode) in whitelist.iter().enumerate().take(20) {
+            let q = vec![b'I'; 8];
+            let m = matcher.assign_quality_at(barcode, &q, 0, &model).unwrap();
+            assert_eq!(m.barcode_id, id);
+            assert!(m.posterior > 0.999, "{}", m.posterior);
+
+            // One Q30 mismatch, the other 95 barcodes are at least two errors away
+            let mut read = barcode.clone();
+            read[3] = if read[3] == b'A' { b'C' } else { b'A' };
+            let mut q = vec![b'I'; 8];
+            q[3] = b'?';
+            let m = matcher
+                .assign_with_posterior(&read, &q, 0, &model, 0.9)
+                .unwrap();
+            assert_eq!((m.barcode_id, m.distance), (id, 1));
+        }
+    }
+
+    #[test]
+    fn test_n_policy_reaches_hamming_and_seq_lev_backends() {
+        let read = b"TTTTGGGNCCCC";
+        for metric in [MatchMetric::Hamming, MatchMetric::SequenceLevenshtein] {
+            // Deletion index, automaton and scan
+            for max_distance in [1, 3, 5] {
+                let matcher = BarcodeMatcher::new(&whitelist(), metric, max_distance);
+                assert_eq!(matcher.assign(read).unwrap().distance, 1, "{:?}", metric);
+
+                let matcher = BarcodeMatcher::with_n_policy(
+                    &whitelist(),
+                    metric,
+                    max_distance,
+                    NPolicy::Match,
+                );
+                let m = matcher.assign(read).unwrap();
+                assert_eq!((m.barcode_id, m.distance), (2, 0), "{:?}", metric);
+                // R is A or G
+                let m = matcher.assign(b"AAAACCCCGGGR").unwrap();
+                assert_eq!((m.barcode_id, m.distance), (0, 0), "{:?}", metric);
+                assert_eq!(m.second_best_distance, Some(1));
+            }
+
+            // Ambiguity codes in the whitelist
+            let whitelist = vec![b"AAAACCCCGGGN".to_vec(), b"TTTTGGGGCCCC".to_vec()];
+            let matcher = BarcodeMatcher::with_n_policy(&whitelist, metric, 1, NPolicy::Match);
+            let m = matcher.assign(b"AAAACCCCGGGT").unwrap();
+            assert_eq!((m.barcode_id, m.distance), (0, 0), "{:?}", metric);
+        }
+    }
+}
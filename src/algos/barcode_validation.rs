@@ -0,0 +1,251 @@
+use crate::algos::barcode_constraints::{gc_fraction, longest_homopolymer, two_channel_signal};
+use crate::algos::bit_packed_ham::BitHamProcessor;
+use crate::algos::distances::{Distance, SequenceLevenshteinDistance};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/*
+Validation report for an existing barcode whitelist
+All pairwise Hamming distances come from BitHamProcessor in one pass, seq-lev distances are
+    computed row by row in parallel with SequenceLevenshteinDistance (Myers up to 128 bases,
+    the blocked kernel past that), so any barcode length gets both metrics
+For each metric we keep the minimum, the full histogram (index = distance, value = pairs) and
+    the guaranteed error correction radius floor((min - 1) / 2)
+Pairs closer than the conflict threshold in either metric are listed with both distances
+The per barcode stats (GC, homopolymers) and per position base balance, including the
+    two-channel signal, are the same quantities the barcode constraints filter on
+Pairwise work is quadratic, this is meant for designed sets of up to tens of thousands of
+    barcodes, not for million entry cell barcode whitelists
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DistanceStats {
+    // None for sets with fewer than two barcodes
+    pub min: Option<usize>,
+    pub histogram: Vec<usize>,
+    // Errors that can always be corrected to the right barcode
+    pub correction_radius: Option<usize>,
+}
+
+impl DistanceStats {
+    fn from_histogram(histogram: Vec<usize>) -> Self {
+        let min = histogram.iter().position(|&pairs| pairs > 0);
+        DistanceStats {
+            min,
+            correction_radius: min.map(|d| d.saturating_sub(1) / 2),
+            histogram,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictPair {
+    pub first: usize,
+    pub second: usize,
+    pub first_barcode: String,
+    pub second_barcode: String,
+    pub hamming: usize,
+    pub sequence_levenshtein: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PositionBalance {
+    pub position: usize,
+    pub a: f64,
+    pub c: f64,
+    pub g: f64,
+    pub t: f64,
+    // Two-channel chemistry, A and C light up red, A and T green
+    pub red: f64,
+    pub green: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub barcodes: usize,
+    pub length: usize,
+    pub hamming: DistanceStats,
+    pub sequence_levenshtein: DistanceStats,
+    pub conflict_threshold: usize,
+    pub conflicts: Vec<ConflictPair>,
+    pub gc_min: f64,
+    pub gc_mean: f64,
+    pub gc_max: f64,
+    pub max_homopolymer: usize,
+    // Index = longest homopolymer run, value = barcodes
+    pub homopolymer_histogram: Vec<usize>,
+    pub position_balance: Vec<PositionBalance>,
+}
+
+// Barcodes must be non-empty, ACGT only and all the same length
+pub fn validate_barcode_set(barcodes: &[Vec<u8>], conflict_threshold: usize) -> ValidationReport {
+    assert!(!barcodes.is_empty(), "No barcodes to validate");
+    let length = barcodes[0].len();
+    assert!(length > 0, "Barcodes must not be empty");
+    for barcode in barcodes {
+        assert_eq!(
+            barcode.len(),
+            length,
+            "Barcodes must all have the same length"
+        );
+        assert!(
+            barcode.iter().all(|b| b"ACGT".contains(b)),
+            "Barcodes must only contain ACGT"
+        );
+    }
+
+    let n = barcodes.len();
+    let hamming = if n >= 2 {
+        let processor = BitHamProcessor::new();
+        processor.initialize(barcodes);
+        processor.process_sequences()
+    } else {
+        Vec::new()
+    };
+    // Same pair order as CompactDNA, (0, 1), (0, 2), ..., (1, 2), ...
+    let pair_index = |i: usize, j: usize| i * (n - 1) - i * (i + 1) / 2 + j - 1;
+
+    let rows: Vec<(Vec<usize>, Vec<usize>, Vec<ConflictPair>)> = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            let mut hamming_histogram = vec![0; length + 1];
+            let mut seq_lev_histogram = vec![0; length + 1];
+            let mut conflicts = Vec::new();
+            for j in (i + 1)..n {
+                let h = hamming[pair_index(i, j)];
+                hamming_histogram[h] += 1;
+                let s = SequenceLevenshteinDistance::new().distance(&barcodes[i], &barcodes[j]);
+                seq_lev_histogram[s] += 1;
+                if h < conflict_threshold || s < conflict_threshold {
+                    conflicts.push(ConflictPair {
+                        first: i,
+                        second: j,
+                        first_barcode: String::from_utf8_lossy(&barcodes[i]).into_owned(),
+                        second_barcode: String::from_utf8_lossy(&barcodes[j]).into_owned(),
+                        hamming: h,
+                        sequence_levenshtein: s,
+                    });
+                }
+            }
+            (hamming_histogram, seq_lev_histogram, conflicts)
+        })
+        .collect();
+
+    let mut hamming_histogram = vec![0; length + 1];
+    let mut seq_lev_histogram = vec![0; length + 1];
+    let mut conflicts = Vec::new();
+    for (h, s, c) in rows {
+        for d in 0..=length {
+            hamming_histogram[d] += h[d];
+            seq_lev_histogram[d] += s[d];
+        }
+        conflicts.extend(c);
+    }
+
+    let gc: Vec<f64> = barcodes.iter().map(|b| gc_fraction(b)).collect();
+    let mut homopolymer_histogram = vec![0; length + 1];
+    for barcode in barcodes {
+        homopolymer_histogram[longest_homopolymer(barcode)] += 1;
+    }
+    let position_balance = two_channel_signal(barcodes)
+        .into_iter()
+        .enumerate()
+        .map(|(position, (red, green))| {
+            let fraction = |base: u8| {
+                barcodes.iter().filter(|b| b[position] == base).count() as f64 / n as f64
+            };
+            PositionBalance {
+                position,
+                a: fraction(b'A'),
+                c: fraction(b'C'),
+                g: fraction(b'G'),
+                t: fraction(b'T'),
+                red,
+                green,
+            }
+        })
+        .collect();
+
+    ValidationReport {
+        barcodes: n,
+        length,
+        hamming: DistanceStats::from_histogram(hamming_histogram),
+        sequence_levenshtein: DistanceStats::from_histogram(seq_lev_histogram),
+        conflict_threshold,
+        conflicts,
+        gc_min: gc.iter().copied().fold(f64::INFINITY, f64::min),
+        gc_mean: gc.iter().sum::<f64>() / n as f64,
+        gc_max: gc.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        max_homopolymer: homopolymer_histogram
+            .iter()
+            .rposition(|&c| c > 0)
+            .unwrap_or(0),
+        homopolymer_histogram,
+        position_balance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::HammingDistanceSimd;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_validate_barcode_set() {
+        let barcodes: Vec<Vec<u8>> = [&b"AACCGGTT"[..], b"AACCGGTA", b"TTGGCCAA", b"ACGTACGT"]
+            .iter()
+            .map(|b| b.to_vec())
+            .collect();
+        let report = validate_barcode_set(&barcodes, 3);
+
+        assert_eq!(report.barcodes, 4);
+        assert_eq!(report.hamming.min, Some(1));
+        assert_eq!(report.hamming.correction_radius, Some(0));
+        assert_eq!(report.hamming.histogram.iter().sum::<usize>(), 6);
+        assert_eq!(report.sequence_levenshtein.min, Some(1));
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(
+            (report.conflicts[0].first, report.conflicts[0].second),
+            (0, 1)
+        );
+        assert_eq!(report.conflicts[0].hamming, 1);
+
+        assert_eq!(report.gc_min, 0.5);
+        assert_eq!(report.max_homopolymer, 2);
+        assert_eq!(report.homopolymer_histogram[1], 1);
+        assert_eq!(report.homopolymer_histogram[2], 3);
+        let first = &report.position_balance[0];
+        assert_eq!((first.a, first.t), (0.75, 0.25));
+        assert_eq!((first.red, first.green), (0.75, 1.0));
+    }
+
+    #[test]
+    fn test_distance_histograms_match_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(3);
+        // 150 bases spans two u64x4 in BitHamProcessor and the blocked seq-lev kernel
+        for (count, length) in [(60, 10), (20, 150)] {
+            let barcodes: Vec<Vec<u8>> = (0..count)
+                .map(|_| (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+                .collect();
+            let report = validate_barcode_set(&barcodes, 4);
+
+            let mut hamming = vec![0; length + 1];
+            let mut seq_lev = vec![0; length + 1];
+            let mut conflicts = 0;
+            for i in 0..barcodes.len() {
+                for j in (i + 1)..barcodes.len() {
+                    let h = HammingDistanceSimd::new().distance(&barcodes[i][..], &barcodes[j][..]);
+                    let s = SequenceLevenshteinDistance::new().distance(&barcodes[i], &barcodes[j]);
+                    hamming[h] += 1;
+                    seq_lev[s] += 1;
+                    conflicts += (h < 4 || s < 4) as usize;
+                }
+            }
+            assert_eq!(report.hamming.histogram, hamming);
+            assert_eq!(report.sequence_levenshtein.histogram, seq_lev);
+            assert_eq!(report.conflicts.len(), conflicts);
+        }
+    }
+}
@@ -1,56 +1,109 @@
 use safe_arch::*;
 
-use crate::algos::common::*;
+use crate::algos::iupac::{self, NPolicy, BASE_A, BASE_C, BASE_G, BASE_T};
+use fxhash::FxHashMap;
 use once_cell::sync::OnceCell;
 use std::simd::u64x4;
 use std::sync::Arc;
 
-const BITS_PER_BASE: usize = 3;
-const BASES_PER_U64: usize = 21; // 63 bits for bases, 1 bit unused
-
 /*
-We are bit packing 3bit encoded DNA bases
-Our words our fed in ascii, when we re-encode, we pack those bits into u64s
-A single u64 could contain bits from multiple words
-We use SIMD to XOR all the data, this will give us a result of all base2base comparisons
-Since our word length is fixed, we can easily extract a words edit distance from the XOR result by index
-Our hamming score is the sum of 1s in the XOR result (sum'ed on word range)
+We are bit packing DNA bases as 4 bit IUPAC base sets (see iupac.rs), one bit per A, C, G, T
+Our words our fed in ascii, when we re-encode, we pack those bits into u64s, 16 bases to a u64
+Every word starts on its own u64x4, words longer than 64 bases span several
+We use SIMD to AND all the data, a base slot with any bit left set is a match (the sets
+    intersect), so N, R, Y, lowercase bases etc. follow the same rules as HammingDistanceSimd
+    and the NPolicy decides whether N matches everything or nothing
+Our hamming score is the word length minus the matching slots (sum'ed on word range), the
+    padding slots are empty on both sides and never match
 */
 
+const BITS_PER_SET: usize = 4;
+const SETS_PER_U64: usize = 16;
+
+// Lowest bit of every 4 bit base set slot
+const SET_SLOTS: u64 = 0x1111111111111111;
+
+// Number of u64x4 needed to hold a word of this length
+#[inline(always)]
+fn packed_set_len(word_length: usize) -> usize {
+    word_length.div_ceil(SETS_PER_U64).div_ceil(4)
+}
+
+// Pack base sets into u64x4 slots, unused slots stay empty
+#[inline(always)]
+fn pack_sets_into(sets: &[u8], packed_sequence: &mut [u64x4]) {
+    for (j, &set) in sets.iter().enumerate() {
+        let shift = (j % SETS_PER_U64) * BITS_PER_SET;
+        let u64_idx = j / SETS_PER_U64;
+        let simd_idx = u64_idx / 4;
+        let simd_offset = u64_idx % 4;
+        let mut arr = packed_sequence[simd_idx].to_array();
+        arr[simd_offset] |= (set as u64) << shift;
+        packed_sequence[simd_idx] = u64x4::from_array(arr);
+    }
+}
+
+// Slots with a non-empty AND, padding is empty on both sides and never counts
+#[inline(always)]
+fn matching_bases(and: u64) -> usize {
+    ((and | (and >> 1) | (and >> 2) | (and >> 3)) & SET_SLOTS).count_ones() as usize
+}
+
+#[inline(always)]
+fn packed_set_hamming(a: &[u64x4], b: &[u64x4], word_length: usize) -> usize {
+    let matches = a
+        .iter()
+        .zip(b.iter())
+        .map(|(&x, &y)| {
+            (x & y)
+                .to_array()
+                .iter()
+                .map(|&v| matching_bases(v))
+                .sum::<usize>()
+        })
+        .sum::<usize>();
+    word_length - matches
+}
+
 pub struct CompactDNA {
     packed_data: Box<[u64x4]>,
+    // u64x4 per word
+    packed_len: usize,
+    num_words: usize,
     word_length: usize,
 }
 
 impl CompactDNA {
-    fn new(sequences: &[Vec<u8>]) -> Self {
+    fn new(sequences: &[Vec<u8>], n_policy: NPolicy) -> Self {
         let word_length = sequences[0].len();
-        let num_u64s = (word_length + BASES_PER_U64 - 1) / BASES_PER_U64;
-        let num_u64x4 = (num_u64s + 3) / 4; // Round up to nearest multiple of 4
+        assert!(
+            sequences.iter().all(|s| s.len() == word_length),
+            "All sequences must have the same length"
+        );
+        let num_u64x4 = packed_set_len(word_length);
         let mut packed_data = vec![u64x4::splat(0); sequences.len() * num_u64x4];
 
         for (i, seq) in sequences.iter().enumerate() {
-            let packed_sequence = &mut packed_data[i * num_u64x4..(i + 1) * num_u64x4];
-            for (j, &base) in seq.iter().enumerate() {
-                let shift = (j % BASES_PER_U64) * BITS_PER_BASE;
-                let u64_idx = j / BASES_PER_U64;
-                let simd_idx = u64_idx / 4;
-                let simd_offset = u64_idx % 4;
-                let mut arr = packed_sequence[simd_idx].to_array();
-                arr[simd_offset] |= (encode_dna(base) as u64) << shift;
-                packed_sequence[simd_idx] = u64x4::from_array(arr);
-            }
+            let sets = iupac::encode_base_sets(seq, n_policy);
+            pack_sets_into(&sets, &mut packed_data[i * num_u64x4..(i + 1) * num_u64x4]);
         }
 
         CompactDNA {
             packed_data: packed_data.into_boxed_slice(),
+            packed_len: num_u64x4,
+            num_words: sequences.len(),
             word_length,
         }
     }
 
+    #[inline(always)]
+    fn word(&self, i: usize) -> &[u64x4] {
+        &self.packed_data[i * self.packed_len..(i + 1) * self.packed_len]
+    }
+
     fn calculate_hamming_distance(&self) -> Vec<usize> {
-        let num_words = self.packed_data.len();
-        let num_pairs = num_words * (num_words - 1) / 2;
+        let num_words = self.num_words;
+        let num_pairs = num_words * num_words.saturating_sub(1) / 2;
         let mut results = vec![0; num_pairs];
 
         if is_x86_feature_detected!("avx2") {
@@ -64,51 +117,38 @@ impl CompactDNA {
 
     #[inline(always)]
     fn calculate_hamming_distance_avx2(&self, results: &mut [usize]) {
-        let num_words = self.packed_data.len();
+        let num_words = self.num_words;
         let word_length = self.word_length;
-        let used_bits = word_length * BITS_PER_BASE;
-        let full_u64_count = used_bits / 64;
-        let remaining_bits = used_bits % 64;
+        // Only the u64s that hold bases, the rest of the last u64x4 is padding
+        let u64_count = word_length.div_ceil(SETS_PER_U64);
+        // u64 k of a word lives in lane k % 4 of its u64x4 k / 4
+        let u64_at = |word: &[u64x4], k: usize| word[k / 4].to_array()[k % 4];
 
         for i in 0..num_words {
+            let word_i = self.word(i);
             for j in (i + 1)..num_words {
-                let mut total_diff = 0;
-
-                // Process full u64s
-                for k in 0..full_u64_count {
-                    let xor_result = self.packed_data[i].to_array()[k] ^ self.packed_data[j].to_array()[k];
-                    total_diff += xor_result.count_ones();
-                }
-
-                // Process remaining bits
-                if remaining_bits > 0 {
-                    let mask = (1u64 << remaining_bits) - 1;
-                    let xor_result = self.packed_data[i].to_array()[full_u64_count] ^ self.packed_data[j].to_array()[full_u64_count];
-                    let masked_xor = xor_result & mask;
-                    total_diff += masked_xor.count_ones();
-                }
-
-                let pair_diff = total_diff as usize / 2;
+                let word_j = self.word(j);
+                let matches: usize = (0..u64_count)
+                    .map(|k| matching_bases(u64_at(word_i, k) & u64_at(word_j, k)))
+                    .sum();
 
                 let pair_index = i * (num_words - 1) - (i * (i + 1) / 2) + j - 1;
-                results[pair_index] = pair_diff;
+                results[pair_index] = word_length - matches;
             }
         }
     }
+
     #[inline(always)]
     fn calculate_hamming_distance_fallback(&self, results: &mut [usize]) {
-        let num_words = self.packed_data.len();
+        let num_words = self.num_words;
 
         for i in 0..num_words {
             for j in (i + 1)..num_words {
                 if j + 2 < num_words {
-                    prefetch_t2(&self.packed_data[j + 2]);
+                    prefetch_t2(&self.word(j + 2)[0]);
                 }
 
-                let xor_result = self.packed_data[i] ^ self.packed_data[j];
-                let pair_diff = xor_result.to_array().iter()
-                    .map(|&v| v.count_ones() as usize) // Use count_ones here
-                    .sum::<usize>() / 2;
+                let pair_diff = packed_set_hamming(self.word(i), self.word(j), self.word_length);
 
                 let pair_index = i * (num_words - 1) - (i * (i + 1) / 2) + j - 1;
                 results[pair_index] = pair_diff;
@@ -119,17 +159,26 @@ impl CompactDNA {
 
 pub struct BitHamProcessor {
     compact_dna: Arc<OnceCell<CompactDNA>>,
+    n_policy: NPolicy,
 }
 
 impl BitHamProcessor {
     pub fn new() -> Self {
         BitHamProcessor {
             compact_dna: Arc::new(OnceCell::new()),
+            n_policy: NPolicy::Mismatch,
         }
     }
 
+    // Only affects words packed after this, set it before initialize
+    pub fn with_n_policy(mut self, n_policy: NPolicy) -> Self {
+        self.n_policy = n_policy;
+        self
+    }
+
     pub fn initialize(&self, sequences: &[Vec<u8>]) {
-        self.compact_dna.get_or_init(|| CompactDNA::new(sequences));
+        self.compact_dna
+            .get_or_init(|| CompactDNA::new(sequences, self.n_policy));
     }
 
     pub fn process_sequences(&self) -> Vec<usize> {
@@ -138,6 +187,201 @@ impl BitHamProcessor {
     }
 }
 
+/*
+Multi-index hashing for Hamming range search
+Split every word into k + 1 contiguous segments, by the pigeonhole principle any word within
+    Hamming distance k of the query matches it exactly on at least one segment
+Each segment position gets its own hash table (packed segment -> word ids), so a query only
+    verifies words that share a segment instead of scanning the whole whitelist
+Words are packed as base sets like CompactDNA above, so R matches A and G and the NPolicy
+    decides what N does
+A segment matches when every position intersects, which means both sides share at least one
+    plain ACGT reading of it, segments are keyed on every such reading of the stored word and
+    looked up with every reading of the query, an empty set (N under NPolicy::Mismatch) has
+    no readings and never matches
+Segments with more than MAX_SEGMENT_KEYS readings (runs of N under NPolicy::Match) are not
+    expanded, such words are verified against every query and such queries verify every word
+*/
+const MAX_SEGMENT_KEYS: usize = 64;
+
+// Every plain ACGT reading of a segment, packed, None past MAX_SEGMENT_KEYS
+fn segment_keys(sets: &[u8]) -> Option<Vec<Box<[u64x4]>>> {
+    let mut readings: Vec<Vec<u8>> = vec![Vec::with_capacity(sets.len())];
+    for &set in sets {
+        if readings.len() * set.count_ones() as usize > MAX_SEGMENT_KEYS {
+            return None;
+        }
+        readings = readings
+            .iter()
+            .flat_map(|reading| {
+                [BASE_A, BASE_C, BASE_G, BASE_T]
+                    .into_iter()
+                    .filter(move |&base| set & base != 0)
+                    .map(move |base| {
+                        let mut reading = reading.clone();
+                        reading.push(base);
+                        reading
+                    })
+            })
+            .collect();
+    }
+    Some(
+        readings
+            .iter()
+            .map(|reading| {
+                let mut key = vec![u64x4::splat(0); packed_set_len(reading.len())];
+                pack_sets_into(reading, &mut key);
+                key.into_boxed_slice()
+            })
+            .collect(),
+    )
+}
+
+pub struct MultiIndexHamming {
+    packed_data: Box<[u64x4]>,
+    packed_len: usize,
+    word_length: usize,
+    max_distance: usize,
+    n_policy: NPolicy,
+    // (start, end) base offsets of each segment
+    segments: Vec<(usize, usize)>,
+    tables: Vec<FxHashMap<Box<[u64x4]>, Vec<u32>>>,
+    // Words with a segment too ambiguous to expand, candidates for every query
+    unindexed: Vec<u32>,
+}
+
+impl MultiIndexHamming {
+    pub fn new(sequences: &[Vec<u8>], max_distance: usize) -> Self {
+        Self::with_n_policy(sequences, max_distance, NPolicy::Mismatch)
+    }
+
+    pub fn with_n_policy(sequences: &[Vec<u8>], max_distance: usize, n_policy: NPolicy) -> Self {
+        let word_length = sequences.first().map_or(0, |s| s.len());
+        assert!(
+            sequences.iter().all(|s| s.len() == word_length),
+            "All sequences must have the same length"
+        );
+
+        // Segment lengths differ by at most one base
+        // With k >= word length every word is a hit and k + 1 non-empty segments don't exist,
+        //     so no segments are built and queries verify the whole whitelist
+        let num_segments = if max_distance < word_length {
+            max_distance + 1
+        } else {
+            0
+        };
+        let mut segments = Vec::with_capacity(num_segments);
+        let mut start = 0;
+        for s in 0..num_segments {
+            let len = word_length / num_segments + usize::from(s < word_length % num_segments);
+            segments.push((start, start + len));
+            start += len;
+        }
+
+        let packed_len = packed_set_len(word_length);
+        let mut packed_data = vec![u64x4::splat(0); sequences.len() * packed_len];
+        let mut tables: Vec<FxHashMap<Box<[u64x4]>, Vec<u32>>> =
+            vec![FxHashMap::default(); segments.len()];
+        let mut unindexed = Vec::new();
+
+        for (i, seq) in sequences.iter().enumerate() {
+            let sets = iupac::encode_base_sets(seq, n_policy);
+            let packed = &mut packed_data[i * packed_len..(i + 1) * packed_len];
+            pack_sets_into(&sets, packed);
+            let keys: Option<Vec<Vec<Box<[u64x4]>>>> = segments
+                .iter()
+                .map(|&(start, end)| segment_keys(&sets[start..end]))
+                .collect();
+            match keys {
+                Some(keys) => {
+                    for (table, segment_keys) in tables.iter_mut().zip(keys) {
+                        for key in segment_keys {
+                            table.entry(key).or_default().push(i as u32);
+                        }
+                    }
+                }
+                None => unindexed.push(i as u32),
+            }
+        }
+
+        MultiIndexHamming {
+            packed_data: packed_data.into_boxed_slice(),
+            packed_len,
+            word_length,
+            max_distance,
+            n_policy,
+            segments,
+            tables,
+            unindexed,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.packed_data.len() / self.packed_len.max(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packed_data.is_empty()
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    pub fn n_policy(&self) -> NPolicy {
+        self.n_policy
+    }
+
+    // Ids sharing a segment reading with the query, None when every word has to be verified
+    fn candidates(&self, query_sets: &[u8]) -> Option<Vec<u32>> {
+        if self.segments.is_empty() {
+            return None;
+        }
+        let mut candidates = self.unindexed.clone();
+        for (table, &(start, end)) in self.tables.iter().zip(self.segments.iter()) {
+            for key in segment_keys(&query_sets[start..end])? {
+                if let Some(ids) = table.get(&key) {
+                    candidates.extend_from_slice(ids);
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        Some(candidates)
+    }
+
+    // Every stored word within max_distance of the query as (id, distance)
+    // Sorted by distance then id
+    pub fn find_within(&self, query: &[u8]) -> Vec<(usize, usize)> {
+        if query.len() != self.word_length || self.is_empty() {
+            return Vec::new();
+        }
+
+        let query_sets = iupac::encode_base_sets(query, self.n_policy);
+        let mut packed_query = vec![u64x4::splat(0); self.packed_len];
+        pack_sets_into(&query_sets, &mut packed_query);
+
+        let candidates = self
+            .candidates(&query_sets)
+            .unwrap_or_else(|| (0..self.len() as u32).collect());
+
+        let mut found: Vec<(usize, usize)> = candidates
+            .into_iter()
+            .map(|id| {
+                let id = id as usize;
+                let packed = &self.packed_data[id * self.packed_len..(id + 1) * self.packed_len];
+                (
+                    id,
+                    packed_set_hamming(packed, &packed_query, self.word_length),
+                )
+            })
+            .filter(|&(_, d)| d <= self.max_distance)
+            .collect();
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,4 +451,177 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_multi_index_matches_brute_force() {
+        use crate::algos::distances::{Distance, HammingDistanceSimd};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(31);
+        // 30 bases spans two u64s so segment keys and verification cross word boundaries
+        let sequences: Vec<Vec<u8>> = (0..500)
+            .map(|_| (0..30).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+        let metric = HammingDistanceSimd::new();
+
+        for k in [0, 2, 5] {
+            let index = MultiIndexHamming::new(&sequences, k);
+            assert_eq!(index.len(), 500);
+            for q in 0..50 {
+                let mut query = sequences[q * 7].clone();
+                for _ in 0..rng.gen_range(0..=k + 1) {
+                    query[rng.gen_range(0..30)] = b"ACGT"[rng.gen_range(0..4)];
+                }
+                let expected: Vec<(usize, usize)> = sequences
+                    .iter()
+                    .enumerate()
+                    .map(|(id, s)| (id, metric.distance(s.as_slice(), query.as_slice())))
+                    .filter(|&(_, d)| d <= k)
+                    .collect();
+                assert_eq!(index.find_within(&query), expected, "k = {}", k);
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_index_distance_at_least_length() {
+        let sequences = vec![b"AAAA".to_vec(), b"CCCC".to_vec(), b"ACGT".to_vec()];
+        for k in [4, 5] {
+            let index = MultiIndexHamming::new(&sequences, k);
+            assert_eq!(
+                index.find_within(b"TTTT"),
+                vec![(2, 3), (0, 4), (1, 4)],
+                "k = {}",
+                k
+            );
+        }
+        assert_eq!(
+            MultiIndexHamming::new(&sequences, 3).find_within(b"TTTT"),
+            vec![(2, 3)]
+        );
+    }
+
+    #[test]
+    fn test_multi_index_iupac_matches_brute_force() {
+        use crate::algos::distances::{Distance, HammingDistanceSimd};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(24);
+        // Mostly plain bases with the odd ambiguity code, runs of N push words and queries past
+        //     MAX_SEGMENT_KEYS under NPolicy::Match
+        let random_base = |rng: &mut StdRng| match rng.gen_range(0..20) {
+            0 => b"RYSWKMBDHVN"[rng.gen_range(0..11)],
+            1 => b'N',
+            _ => b"ACGT"[rng.gen_range(0..4)],
+        };
+        let mut sequences: Vec<Vec<u8>> = (0..300)
+            .map(|_| (0..20).map(|_| random_base(&mut rng)).collect())
+            .collect();
+        sequences.push(b"NNNNNNACGTACGTACGTAC".to_vec());
+
+        for n_policy in [NPolicy::Mismatch, NPolicy::Match] {
+            let metric = HammingDistanceSimd::new().with_n_policy(n_policy);
+            for k in [0, 1, 3] {
+                let index = MultiIndexHamming::with_n_policy(&sequences, k, n_policy);
+                for q in 0..60 {
+                    let mut query = sequences[q * 5].clone();
+                    for _ in 0..rng.gen_range(0..=k + 1) {
+                        query[rng.gen_range(0..20)] = random_base(&mut rng);
+                    }
+                    if q % 10 == 0 {
+                        query[..6].copy_from_slice(b"NNNNNN");
+                    }
+                    let mut expected: Vec<(usize, usize)> = sequences
+                        .iter()
+                        .enumerate()
+                        .map(|(id, s)| (id, metric.distance(s.as_slice(), query.as_slice())))
+                        .filter(|&(_, d)| d <= k)
+                        .collect();
+                    expected.sort_unstable_by_key(|&(id, d)| (d, id));
+                    assert_eq!(
+                        index.find_within(&query),
+                        expected,
+                        "{:?} k = {}",
+                        n_policy,
+                        k
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bit_ham_matches_simd_hamming() {
+        use crate::algos::distances::{Distance, HammingDistanceSimd};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(11);
+        let metric = HammingDistanceSimd::new();
+        // Every base count left in the last u64, including a full one
+        // Past 84 bases a word spans several u64x4
+        for length in [10, 21, 42, 50, 63, 84, 85, 105, 200] {
+            let sequences: Vec<Vec<u8>> = (0..50)
+                .map(|_| (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+                .collect();
+            let processor = BitHamProcessor::new();
+            processor.initialize(&sequences);
+
+            let expected: Vec<usize> = (0..sequences.len())
+                .flat_map(|i| ((i + 1)..sequences.len()).map(move |j| (i, j)))
+                .map(|(i, j)| metric.distance(sequences[i].as_slice(), sequences[j].as_slice()))
+                .collect();
+            assert_eq!(
+                processor.process_sequences(),
+                expected,
+                "length = {}",
+                length
+            );
+
+            // Without AVX2 the whole u64x4 XOR path runs instead
+            let compact = CompactDNA::new(&sequences, NPolicy::Mismatch);
+            let mut fallback = vec![0; expected.len()];
+            compact.calculate_hamming_distance_fallback(&mut fallback);
+            assert_eq!(fallback, expected, "length = {}", length);
+        }
+    }
+
+    #[test]
+    fn test_bit_ham_iupac_matches_simd_hamming() {
+        use crate::algos::distances::{Distance, HammingDistanceSimd};
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(24);
+        let sequences: Vec<Vec<u8>> = (0..40)
+            .map(|_| {
+                (0..70)
+                    .map(|_| b"ACGTACGTacgtNNRYSWKMBDHV."[rng.gen_range(0..25)])
+                    .collect()
+            })
+            .collect();
+        for n_policy in [NPolicy::Mismatch, NPolicy::Match] {
+            let metric = HammingDistanceSimd::new().with_n_policy(n_policy);
+            let expected: Vec<usize> = (0..sequences.len())
+                .flat_map(|i| ((i + 1)..sequences.len()).map(move |j| (i, j)))
+                .map(|(i, j)| metric.distance(sequences[i].as_slice(), sequences[j].as_slice()))
+                .collect();
+
+            let processor = BitHamProcessor::new().with_n_policy(n_policy);
+            processor.initialize(&sequences);
+            assert_eq!(processor.process_sequences(), expected, "{:?}", n_policy);
+
+            let compact = CompactDNA::new(&sequences, n_policy);
+            let mut fallback = vec![0; expected.len()];
+            compact.calculate_hamming_distance_fallback(&mut fallback);
+            assert_eq!(fallback, expected, "{:?}", n_policy);
+        }
+
+        // N never matches N under NPolicy::Mismatch, lowercase is the same base
+        let processor = BitHamProcessor::new();
+        processor.initialize(&[b"NNAc".to_vec(), b"NRAC".to_vec()]);
+        assert_eq!(processor.process_sequences(), vec![2]);
+    }
 }
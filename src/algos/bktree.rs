@@ -0,0 +1,310 @@
+use crate::algos::distances::{Distance, LengthRange};
+use fxhash::FxHashMap;
+use std::borrow::Borrow;
+
+/*
+Burkhard-Keller tree over any metric implementing `Distance`
+Every node stores one item and its children are keyed by their distance to that item
+When searching with radius k, the triangle inequality lets us skip every child whose
+    key is outside [d - k, d + k], where d is the distance from the query to the node
+Pruning goes through Distance::lower_bound, so seq-lev gets its own wider (exact) window
+Nodes live in a flat arena (index == insertion order) instead of boxed children,
+    this keeps the tree cache friendly and gives every item a stable id
+The tree is generic over the borrowed item type so slices and strings both work:
+    BkTree<[u8], HammingDistanceSimd> stores Vec<u8>
+    BkTree<String, LevenshteinDistance> stores String
+*/
+
+struct BkNode<O> {
+    item: O,
+    children: FxHashMap<usize, usize>,
+}
+
+pub struct BkTree<T: ?Sized + ToOwned, D: Distance<T>> {
+    nodes: Vec<BkNode<T::Owned>>,
+    lengths: LengthRange,
+    metric: D,
+}
+
+impl<T: ?Sized + ToOwned, D: Distance<T>> BkTree<T, D> {
+    pub fn new(metric: D) -> Self {
+        BkTree {
+            nodes: Vec::new(),
+            lengths: LengthRange::new(),
+            metric,
+        }
+    }
+
+    // Bulk build, items keep their iteration order as ids
+    pub fn build<I>(metric: D, items: I) -> Self
+    where
+        I: IntoIterator<Item = T::Owned>,
+    {
+        let items = items.into_iter();
+        let mut tree = BkTree {
+            nodes: Vec::with_capacity(items.size_hint().0),
+            lengths: LengthRange::new(),
+            metric,
+        };
+        for item in items {
+            tree.insert(item);
+        }
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.nodes.get(id).map(|node| node.item.borrow())
+    }
+
+    pub fn metric(&self) -> &D {
+        &self.metric
+    }
+
+    // Returns the id of the inserted item
+    // Distance 0 does not mean equality for seq-lev (trailing overhang is free),
+    //     so zero-distance items are kept as children instead of being dropped
+    pub fn insert(&mut self, item: T::Owned) -> usize {
+        let id = self.nodes.len();
+        self.lengths.insert(self.metric.length(item.borrow()));
+        if id > 0 {
+            let mut current = 0;
+            loop {
+                let d = self
+                    .metric
+                    .distance(self.nodes[current].item.borrow(), item.borrow());
+                match self.nodes[current].children.get(&d) {
+                    Some(&child) => current = child,
+                    None => {
+                        self.nodes[current].children.insert(d, id);
+                        break;
+                    }
+                }
+            }
+        }
+        self.nodes.push(BkNode {
+            item,
+            children: FxHashMap::default(),
+        });
+        id
+    }
+
+    // All items within distance k of the query as (id, distance)
+    // Sorted by distance, ties broken by insertion order
+    pub fn find_within_ids(&self, query: &T, k: usize) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let spread = self.lengths.spread(self.metric.length(query));
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let d = self.metric.distance(node.item.borrow(), query);
+            if d <= k {
+                found.push((current, d));
+            }
+            for (&key, &child) in node.children.iter() {
+                if self.metric.lower_bound(d, key..=key, spread) <= k {
+                    stack.push(child);
+                }
+            }
+        }
+
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+
+    // All items within distance k of the query as (distance, item)
+    pub fn find_within(&self, query: &T, k: usize) -> Vec<(usize, &T)> {
+        self.find_within_ids(query, k)
+            .into_iter()
+            .map(|(id, d)| (d, self.nodes[id].item.borrow()))
+            .collect()
+    }
+
+    // Closest item as (id, distance), the search radius shrinks as better items are found
+    pub fn nearest_id(&self, query: &T) -> Option<(usize, usize)> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let spread = self.lengths.spread(self.metric.length(query));
+        let mut best: Option<(usize, usize)> = None;
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let d = self.metric.distance(node.item.borrow(), query);
+            best = match best {
+                Some((best_id, best_d)) if (best_d, best_id) <= (d, current) => best,
+                _ => Some((current, d)),
+            };
+            let radius = best.map_or(usize::MAX, |(_, best_d)| best_d);
+            for (&key, &child) in node.children.iter() {
+                if self.metric.lower_bound(d, key..=key, spread) <= radius {
+                    stack.push(child);
+                }
+            }
+        }
+
+        best
+    }
+
+    // Closest item as (distance, item)
+    pub fn nearest(&self, query: &T) -> Option<(usize, &T)> {
+        self.nearest_id(query)
+            .map(|(id, d)| (d, self.nodes[id].item.borrow()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{
+        HammingDistanceSimd, LevenshteinDistance, SequenceLevenshteinDistance,
+    };
+
+    fn whitelist() -> Vec<Vec<u8>> {
+        vec![
+            b"ACGTACGT".to_vec(),
+            b"TTGCAAGC".to_vec(),
+            b"ACGTACGA".to_vec(),
+            b"GGGCCCAA".to_vec(),
+            b"CATGCATG".to_vec(),
+            b"ACGAACGT".to_vec(),
+        ]
+    }
+
+    fn linear_scan<T: ?Sized, O: Borrow<T>, D: Distance<T>>(
+        metric: &D,
+        items: &[O],
+        query: &T,
+        k: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut expected: Vec<(usize, usize)> = items
+            .iter()
+            .enumerate()
+            .map(|(id, item)| (id, metric.distance(item.borrow(), query)))
+            .filter(|&(_, d)| d <= k)
+            .collect();
+        expected.sort_unstable_by_key(|&(id, d)| (d, id));
+        expected
+    }
+
+    #[test]
+    fn test_find_within_matches_linear_scan() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let items: Vec<Vec<u8>> = (0..300)
+            .map(|_| (0..10).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+        let strings: Vec<String> = items
+            .iter()
+            .map(|item| String::from_utf8(item.clone()).unwrap())
+            .collect();
+        let hamming: BkTree<[u8], _> = BkTree::build(HammingDistanceSimd::new(), items.clone());
+        let levenshtein: BkTree<String, _> =
+            BkTree::build(LevenshteinDistance::new(), strings.clone());
+        assert_eq!(hamming.len(), 300);
+        assert_eq!(levenshtein.len(), 300);
+
+        for _ in 0..50 {
+            let query: Vec<u8> = (0..10).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let query_string = String::from_utf8(query.clone()).unwrap();
+            for k in 0..5 {
+                assert_eq!(
+                    hamming.find_within_ids(query.as_slice(), k),
+                    linear_scan(hamming.metric(), &items, query.as_slice(), k),
+                    "hamming k = {}",
+                    k
+                );
+                assert_eq!(
+                    levenshtein.find_within_ids(&query_string, k),
+                    linear_scan(levenshtein.metric(), &strings, &query_string, k),
+                    "levenshtein k = {}",
+                    k
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_seq_lev_matches_linear_scan() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        // Plain triangle pruning drops ACGTTTTT here, d(q, root) = 0 and its key is 4
+        let triangle: Vec<Vec<u8>> = vec![b"ACGTAAAA".to_vec(), b"ACGTTTTT".to_vec()];
+        let tree: BkTree<[u8], _> =
+            BkTree::build(SequenceLevenshteinDistance::new(), triangle.clone());
+        assert_eq!(
+            tree.find_within_ids(b"ACGT".as_slice(), 0),
+            vec![(0, 0), (1, 0)]
+        );
+
+        // Mixed lengths and short queries on a small alphabet hit the overhang a lot
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut random_sequence = |min: usize, max: usize| -> Vec<u8> {
+            let length = rng.gen_range(min..=max);
+            (0..length).map(|_| b"ACGT"[rng.gen_range(0..2)]).collect()
+        };
+        let items: Vec<Vec<u8>> = (0..300).map(|_| random_sequence(6, 10)).collect();
+        let queries: Vec<Vec<u8>> = (0..100).map(|_| random_sequence(3, 12)).collect();
+        let tree: BkTree<[u8], _> =
+            BkTree::build(SequenceLevenshteinDistance::new(), items.clone());
+
+        for query in queries {
+            for k in 0..4 {
+                assert_eq!(
+                    tree.find_within_ids(query.as_slice(), k),
+                    linear_scan(tree.metric(), &items, query.as_slice(), k),
+                    "seq-lev k = {}",
+                    k
+                );
+            }
+            let nearest = linear_scan(tree.metric(), &items, query.as_slice(), usize::MAX);
+            assert_eq!(tree.nearest_id(query.as_slice()), nearest.first().copied());
+        }
+    }
+
+    #[test]
+    fn test_nearest_hamming() {
+        let tree: BkTree<[u8], _> = BkTree::build(HammingDistanceSimd::new(), whitelist());
+
+        // Equidistant to ids 0 and 2, lowest id wins
+        let (d, item) = tree.nearest(b"ACGTACGC".as_slice()).unwrap();
+        assert_eq!(d, 1);
+        assert_eq!(item, b"ACGTACGT");
+
+        let (id, d) = tree.nearest_id(b"CATGCATT".as_slice()).unwrap();
+        assert_eq!((id, d), (4, 1));
+    }
+
+    #[test]
+    fn test_levenshtein_strings() {
+        let words = ["book", "books", "cake", "boo", "cape", "cart"];
+        let mut tree: BkTree<String, _> = BkTree::new(LevenshteinDistance::new());
+        assert!(tree.nearest(&"boo".to_string()).is_none());
+        for word in words {
+            tree.insert(word.to_string());
+        }
+
+        let found: Vec<(usize, &str)> = tree
+            .find_within(&"bo".to_string(), 2)
+            .into_iter()
+            .map(|(d, s)| (d, s.as_str()))
+            .collect();
+        assert_eq!(found, vec![(1, "boo"), (2, "book")]);
+        assert_eq!(tree.nearest(&"capes".to_string()).unwrap().1, "cape");
+    }
+}
@@ -4,13 +4,18 @@ const C: u8 = 0b110;
 const G: u8 = 0b101;
 const T: u8 = 0b000;
 
+// Plain ACGT only, 3 bits can't hold a base set, N and the other IUPAC codes go through
+//     iupac::base_set instead
 pub fn encode_dna(base: u8) -> u8 {
     match base {
         b'A' => A,
         b'C' => C,
         b'G' => G,
         b'T' => T,
-        _ => panic!("Invalid DNA base"),
+        _ => panic!(
+            "Invalid DNA base {:?}, use iupac::base_set for IUPAC codes",
+            base as char
+        ),
     }
 }
 
@@ -24,7 +29,22 @@ pub fn decode_dna(base: u8) -> u8 {
     }
 }
 
-/* 
+// Watson-Crick complement, anything else (N, IUPAC codes) is kept as is
+pub fn complement_base(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        other => other,
+    }
+}
+
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&b| complement_base(b)).collect()
+}
+
+/*
 #[inline(always)]
 fn popcount_u64x4(v: u64x4) -> u32 {
     //let masked_v = v & u64x4::splat(mask);
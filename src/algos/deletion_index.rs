@@ -0,0 +1,180 @@
+use crate::algos::distances::{BoundedDistance, SequenceLevenshteinDistance};
+use fxhash::{FxHashMap, FxHashSet};
+use rayon::prelude::*;
+
+/*
+Symmetric deletion (SymSpell style) index
+Instead of expanding every barcode into its full neighborhood (substitutions and insertions
+    are what blow up), we only store the deletion variants of each barcode: every subsequence
+    obtained by removing up to k bases
+At query time we generate the deletion variants of the read, anything sharing a variant with
+    the read is a candidate, and candidates are verified with the seq-lev Myers kernel
+For two sequences of the same length L with seq-lev distance <= k, deleting the substituted
+    and unmatched bases from each side leaves a common subsequence with at most k deletions
+    on either side (a trailing overhang is paid for by the deletions it replaces),
+    so no hit is ever missed for fixed length reads
+A 16-mer has 137 variants at k = 2 versus hundreds of thousands of full neighbors
+*/
+
+pub struct DeletionIndex {
+    whitelist: Vec<Vec<u8>>,
+    variants: FxHashMap<Vec<u8>, Vec<u32>>,
+    max_distance: usize,
+    metric: SequenceLevenshteinDistance,
+}
+
+impl DeletionIndex {
+    pub fn build(whitelist: &[Vec<u8>], max_distance: usize) -> Self {
+        let expanded: Vec<FxHashSet<Vec<u8>>> = whitelist
+            .par_iter()
+            .map(|barcode| deletion_variants(barcode, max_distance))
+            .collect();
+
+        let mut variants: FxHashMap<Vec<u8>, Vec<u32>> = FxHashMap::default();
+        for (barcode_id, barcode_variants) in expanded.into_iter().enumerate() {
+            for variant in barcode_variants {
+                variants.entry(variant).or_default().push(barcode_id as u32);
+            }
+        }
+
+        DeletionIndex {
+            whitelist: whitelist.to_vec(),
+            variants,
+            max_distance,
+            metric: SequenceLevenshteinDistance::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.whitelist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.whitelist.is_empty()
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+
+    pub fn get(&self, barcode_id: usize) -> Option<&[u8]> {
+        self.whitelist.get(barcode_id).map(|b| b.as_slice())
+    }
+
+    // Whitelist ids sharing at least one deletion variant with the query, unverified
+    pub fn candidates(&self, query: &[u8]) -> Vec<usize> {
+        let mut candidates: Vec<usize> = deletion_variants(query, self.max_distance)
+            .iter()
+            .filter_map(|variant| self.variants.get(variant))
+            .flatten()
+            .map(|&id| id as usize)
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+
+    // Every barcode within max_distance of the query as (id, seq-lev distance)
+    // Sorted by distance then id
+    pub fn find_within(&self, query: &[u8]) -> Vec<(usize, usize)> {
+        let mut found: Vec<(usize, usize)> = self
+            .candidates(query)
+            .into_iter()
+            .filter_map(|id| {
+                self.metric
+                    .distance_within(self.whitelist[id].as_slice(), query, self.max_distance)
+                    .map(|d| (id, d))
+            })
+            .collect();
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+}
+
+// The sequence itself plus every subsequence with 1..=k bases removed
+pub fn deletion_variants(sequence: &[u8], k: usize) -> FxHashSet<Vec<u8>> {
+    let mut variants = FxHashSet::default();
+    variants.insert(sequence.to_vec());
+    let mut frontier = vec![sequence.to_vec()];
+
+    for _ in 0..k {
+        let mut next = Vec::new();
+        for current in frontier.iter() {
+            for i in 0..current.len() {
+                let mut variant = Vec::with_capacity(current.len() - 1);
+                variant.extend_from_slice(&current[..i]);
+                variant.extend_from_slice(&current[i + 1..]);
+                if variants.insert(variant.clone()) {
+                    next.push(variant);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::Distance;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_deletion_variants() {
+        let variants = deletion_variants(b"ACGT", 1);
+        assert_eq!(variants.len(), 5);
+        assert!(variants.contains(b"AGT".as_slice()));
+
+        // Repeated bases collapse, AAAA only has AAA and AA below it
+        assert_eq!(deletion_variants(b"AAAA", 2).len(), 3);
+    }
+
+    #[test]
+    fn test_find_within_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let whitelist: Vec<Vec<u8>> = (0..400)
+            .map(|_| (0..12).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+        let index = DeletionIndex::build(&whitelist, 2);
+        let metric = SequenceLevenshteinDistance::new();
+
+        for q in 0..200 {
+            // Half the queries are mutated whitelist barcodes so there are hits to find
+            let mut query = whitelist[q].clone();
+            if q % 2 == 0 {
+                for _ in 0..rng.gen_range(1..=2) {
+                    let i = rng.gen_range(0..12);
+                    match rng.gen_range(0..3) {
+                        0 => query[i] = b"ACGT"[rng.gen_range(0..4)],
+                        1 => {
+                            query.insert(i, b"ACGT"[rng.gen_range(0..4)]);
+                            query.pop();
+                        }
+                        _ => {
+                            query.remove(i);
+                            query.push(b"ACGT"[rng.gen_range(0..4)]);
+                        }
+                    }
+                }
+            } else {
+                query = (0..12).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            }
+
+            let mut expected: Vec<(usize, usize)> = whitelist
+                .iter()
+                .enumerate()
+                .map(|(id, b)| (id, metric.distance(b.as_slice(), query.as_slice())))
+                .filter(|&(_, d)| d <= 2)
+                .collect();
+            expected.sort_unstable_by_key(|&(id, d)| (d, id));
+            assert_eq!(index.find_within(&query), expected);
+        }
+    }
+}
@@ -0,0 +1,798 @@
+use crate::algos::barcode_matcher::{BarcodeMatcher, MatchMetric};
+use crate::algos::fastq::FastqRecord;
+use crate::algos::iupac::NPolicy;
+use crate::algos::quality::{QualityModel, DEFAULT_INDEL_PROBABILITY};
+use fxhash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::io::{self, BufRead};
+
+/*
+Sample demultiplexing on top of BarcodeMatcher
+The barcode is read from a fixed offset in each read and corrected against the sample sheet
+Reads that match a sample exactly or within max distance are assigned, reads equally close
+    to two samples are ambiguous, everything else is undetermined
+Dual indexed runs (i7 + i5) correct each index on its own whitelist and only assign a sample
+    when the corrected pair is on the sample sheet
+A valid i7 and a valid i5 that belong to different pairs is an index hop (free adapters
+    swapping indexes on patterned flow cells), those are counted per combination so a run with
+    unusually high hopping stands out
+With min_posterior set, single index reads are scored with their base qualities instead and
+    only assigned when the posterior of the best sample reaches the threshold, reads below it
+    count as ambiguous
+N and the other IUPAC codes in reads and sheets follow n_policy (see barcode_matcher.rs for
+    what the quality scoring still compares bytewise)
+File handling lives in the binary (`algos_n_stuff demux`), this module only classifies reads
+    and keeps the counts that end up in the JSON summary
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sample {
+    pub name: String,
+    pub barcode: Vec<u8>,
+}
+
+fn is_barcode(field: &str) -> bool {
+    !field.is_empty() && field.bytes().all(|b| b"ACGTNacgtn".contains(&b))
+}
+
+// Name followed by `columns` index sequences per line, comma or tab separated
+// '#' comments and a header line are skipped
+fn parse_sheet_rows<R: BufRead>(
+    reader: R,
+    columns: usize,
+) -> io::Result<Vec<(String, Vec<Vec<u8>>)>> {
+    let mut rows: Vec<(String, Vec<Vec<u8>>)> = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split([',', '\t']).map(|f| f.trim()).collect();
+        if fields.len() < columns + 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample sheet line {}: expected a name and {} index column(s)",
+                    line_number + 1,
+                    columns
+                ),
+            ));
+        }
+        if let Some(invalid) = fields[1..=columns].iter().find(|f| !is_barcode(f)) {
+            if rows.is_empty() {
+                // Header row
+                continue;
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample sheet line {}: invalid barcode {:?}",
+                    line_number + 1,
+                    invalid
+                ),
+            ));
+        }
+        let barcodes: Vec<Vec<u8>> = fields[1..=columns]
+            .iter()
+            .map(|f| f.to_ascii_uppercase().into_bytes())
+            .collect();
+        // Every index column is matched against one whitelist, which needs a single length
+        if let Some((_, first)) = rows.first() {
+            if let Some((expected, barcode)) = first
+                .iter()
+                .zip(barcodes.iter())
+                .find(|(expected, barcode)| expected.len() != barcode.len())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample sheet line {}: barcode {} has length {}, expected {}",
+                        line_number + 1,
+                        String::from_utf8_lossy(barcode),
+                        barcode.len(),
+                        expected.len()
+                    ),
+                ));
+            }
+        }
+        rows.push((fields[0].to_string(), barcodes));
+    }
+    Ok(rows)
+}
+
+// "name,barcode" or "name<TAB>barcode" per line
+pub fn parse_sample_sheet<R: BufRead>(reader: R) -> io::Result<Vec<Sample>> {
+    let samples: Vec<Sample> = parse_sheet_rows(reader, 1)?
+        .into_iter()
+        .map(|(name, mut barcodes)| Sample {
+            name,
+            barcode: barcodes.remove(0),
+        })
+        .collect();
+
+    let mut seen: FxHashMap<&[u8], &str> = FxHashMap::default();
+    for sample in &samples {
+        if let Some(other) = seen.insert(&sample.barcode, &sample.name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Samples {} and {} share barcode {}",
+                    other,
+                    sample.name,
+                    String::from_utf8_lossy(&sample.barcode)
+                ),
+            ));
+        }
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DemuxConfig {
+    // Offset of the barcode in the read sequence
+    pub barcode_start: usize,
+    pub metric: MatchMetric,
+    pub max_distance: usize,
+    pub n_policy: NPolicy,
+    // Quality aware assignment, None keeps the plain distance based assignment
+    pub min_posterior: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    Sample { sample_id: usize, distance: usize },
+    Ambiguous,
+    Undetermined,
+}
+
+pub struct Demultiplexer {
+    samples: Vec<Sample>,
+    matcher: BarcodeMatcher,
+    config: DemuxConfig,
+    quality_model: QualityModel,
+}
+
+impl Demultiplexer {
+    pub fn new(samples: Vec<Sample>, config: DemuxConfig) -> Self {
+        if let Some(p) = config.min_posterior {
+            assert!(
+                (0.0..=1.0).contains(&p),
+                "Minimum posterior must be in [0, 1]"
+            );
+        }
+        let barcodes: Vec<Vec<u8>> = samples.iter().map(|s| s.barcode.clone()).collect();
+        let matcher = BarcodeMatcher::with_n_policy(
+            &barcodes,
+            config.metric,
+            config.max_distance,
+            config.n_policy,
+        );
+        let quality_model = match config.metric {
+            MatchMetric::Hamming => QualityModel::new(),
+            _ => QualityModel::with_indels(DEFAULT_INDEL_PROBABILITY),
+        };
+        Demultiplexer {
+            samples,
+            matcher,
+            config,
+            quality_model,
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn config(&self) -> &DemuxConfig {
+        &self.config
+    }
+
+    pub fn assign(&self, seq: &[u8]) -> Assignment {
+        match self.matcher.assign_at(seq, self.config.barcode_start) {
+            Some(m) if m.ambiguous => Assignment::Ambiguous,
+            Some(m) => Assignment::Sample {
+                sample_id: m.barcode_id,
+                distance: m.distance,
+            },
+            None => Assignment::Undetermined,
+        }
+    }
+
+    // Uses the base qualities when min_posterior is set, otherwise the same as assign
+    pub fn assign_record(&self, record: &FastqRecord) -> Assignment {
+        let min_posterior = match self.config.min_posterior {
+            Some(p) => p,
+            None => return self.assign(&record.seq),
+        };
+        match self.matcher.assign_quality_at(
+            &record.seq,
+            &record.qual,
+            self.config.barcode_start,
+            &self.quality_model,
+        ) {
+            Some(m) if m.posterior < min_posterior => Assignment::Ambiguous,
+            Some(m) => Assignment::Sample {
+                sample_id: m.barcode_id,
+                distance: m.distance,
+            },
+            None => Assignment::Undetermined,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleSummary {
+    pub name: String,
+    pub barcode: String,
+    pub reads: usize,
+    pub corrected_reads: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DemuxSummary {
+    pub total_reads: usize,
+    pub assigned_reads: usize,
+    pub exact_reads: usize,
+    pub corrected_reads: usize,
+    pub ambiguous_reads: usize,
+    pub undetermined_reads: usize,
+    pub samples: Vec<SampleSummary>,
+}
+
+impl DemuxSummary {
+    pub fn new(samples: &[Sample]) -> Self {
+        DemuxSummary {
+            total_reads: 0,
+            assigned_reads: 0,
+            exact_reads: 0,
+            corrected_reads: 0,
+            ambiguous_reads: 0,
+            undetermined_reads: 0,
+            samples: samples
+                .iter()
+                .map(|s| SampleSummary {
+                    name: s.name.clone(),
+                    barcode: String::from_utf8_lossy(&s.barcode).into_owned(),
+                    reads: 0,
+                    corrected_reads: 0,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn record(&mut self, assignment: Assignment) {
+        self.total_reads += 1;
+        match assignment {
+            Assignment::Sample {
+                sample_id,
+                distance,
+            } => {
+                self.assigned_reads += 1;
+                self.samples[sample_id].reads += 1;
+                if distance == 0 {
+                    self.exact_reads += 1;
+                } else {
+                    self.corrected_reads += 1;
+                    self.samples[sample_id].corrected_reads += 1;
+                }
+            }
+            Assignment::Ambiguous => self.ambiguous_reads += 1,
+            Assignment::Undetermined => self.undetermined_reads += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DualIndexSample {
+    pub name: String,
+    pub i7: Vec<u8>,
+    pub i5: Vec<u8>,
+}
+
+// "name,i7,i5" or tab separated per line
+// Index sequences can be shared across samples, the (i7, i5) pair can't
+pub fn parse_dual_index_sheet<R: BufRead>(reader: R) -> io::Result<Vec<DualIndexSample>> {
+    let samples: Vec<DualIndexSample> = parse_sheet_rows(reader, 2)?
+        .into_iter()
+        .map(|(name, mut barcodes)| {
+            let i5 = barcodes.pop().unwrap();
+            let i7 = barcodes.pop().unwrap();
+            DualIndexSample { name, i7, i5 }
+        })
+        .collect();
+
+    let mut seen: FxHashMap<(&[u8], &[u8]), &str> = FxHashMap::default();
+    for sample in &samples {
+        if let Some(other) = seen.insert((&sample.i7, &sample.i5), &sample.name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Samples {} and {} share the index pair {}+{}",
+                    other,
+                    sample.name,
+                    String::from_utf8_lossy(&sample.i7),
+                    String::from_utf8_lossy(&sample.i5)
+                ),
+            ));
+        }
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DualAssignment {
+    Sample {
+        sample_id: usize,
+        i7_distance: usize,
+        i5_distance: usize,
+    },
+    // Both indexes corrected, but the pair is not on the sample sheet
+    // Ids point into i7_indices()/i5_indices()
+    IndexHopped {
+        i7_id: usize,
+        i5_id: usize,
+    },
+    Ambiguous,
+    Undetermined,
+}
+
+pub struct DualIndexDemultiplexer {
+    samples: Vec<DualIndexSample>,
+    // Distinct index sequences, a combinatorial sheet reuses them across samples
+    i7_indices: Vec<Vec<u8>>,
+    i5_indices: Vec<Vec<u8>>,
+    i7_matcher: BarcodeMatcher,
+    i5_matcher: BarcodeMatcher,
+    // (i7 id, i5 id) -> sample id
+    pairs: FxHashMap<(usize, usize), usize>,
+    metric: MatchMetric,
+    max_distance: usize,
+    n_policy: NPolicy,
+}
+
+fn distinct_index(indices: &mut Vec<Vec<u8>>, index: &[u8]) -> usize {
+    match indices.iter().position(|i| i == index) {
+        Some(id) => id,
+        None => {
+            indices.push(index.to_vec());
+            indices.len() - 1
+        }
+    }
+}
+
+impl DualIndexDemultiplexer {
+    pub fn new(samples: Vec<DualIndexSample>, metric: MatchMetric, max_distance: usize) -> Self {
+        Self::with_n_policy(samples, metric, max_distance, NPolicy::Mismatch)
+    }
+
+    // Panics on a reused index pair or mixed index lengths, parse_dual_index_sheet rejects both
+    pub fn with_n_policy(
+        samples: Vec<DualIndexSample>,
+        metric: MatchMetric,
+        max_distance: usize,
+        n_policy: NPolicy,
+    ) -> Self {
+        let mut i7_indices = Vec::new();
+        let mut i5_indices = Vec::new();
+        let mut pairs = FxHashMap::default();
+        for (sample_id, sample) in samples.iter().enumerate() {
+            let i7_id = distinct_index(&mut i7_indices, &sample.i7);
+            let i5_id = distinct_index(&mut i5_indices, &sample.i5);
+            let previous = pairs.insert((i7_id, i5_id), sample_id);
+            assert!(
+                previous.is_none(),
+                "Sample {} reuses the index pair of another sample",
+                sample.name
+            );
+        }
+
+        DualIndexDemultiplexer {
+            i7_matcher: BarcodeMatcher::with_n_policy(&i7_indices, metric, max_distance, n_policy),
+            i5_matcher: BarcodeMatcher::with_n_policy(&i5_indices, metric, max_distance, n_policy),
+            samples,
+            i7_indices,
+            i5_indices,
+            pairs,
+            metric,
+            max_distance,
+            n_policy,
+        }
+    }
+
+    pub fn samples(&self) -> &[DualIndexSample] {
+        &self.samples
+    }
+
+    pub fn i7_indices(&self) -> &[Vec<u8>] {
+        &self.i7_indices
+    }
+
+    pub fn i5_indices(&self) -> &[Vec<u8>] {
+        &self.i5_indices
+    }
+
+    pub fn metric(&self) -> MatchMetric {
+        self.metric
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    pub fn n_policy(&self) -> NPolicy {
+        self.n_policy
+    }
+
+    pub fn assign(&self, i7: &[u8], i5: &[u8]) -> DualAssignment {
+        let (Some(i7_match), Some(i5_match)) =
+            (self.i7_matcher.assign(i7), self.i5_matcher.assign(i5))
+        else {
+            return DualAssignment::Undetermined;
+        };
+        if i7_match.ambiguous || i5_match.ambiguous {
+            return DualAssignment::Ambiguous;
+        }
+
+        match self.pairs.get(&(i7_match.barcode_id, i5_match.barcode_id)) {
+            Some(&sample_id) => DualAssignment::Sample {
+                sample_id,
+                i7_distance: i7_match.distance,
+                i5_distance: i5_match.distance,
+            },
+            None => DualAssignment::IndexHopped {
+                i7_id: i7_match.barcode_id,
+                i5_id: i5_match.barcode_id,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexHopCount {
+    pub i7: String,
+    pub i5: String,
+    // Samples that carry each index, one entry for unique dual indexes
+    pub i7_samples: Vec<String>,
+    pub i5_samples: Vec<String>,
+    pub reads: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DualIndexSummary {
+    pub total_reads: usize,
+    pub assigned_reads: usize,
+    pub exact_reads: usize,
+    // Either index needed correction
+    pub corrected_reads: usize,
+    pub ambiguous_reads: usize,
+    pub index_hopped_reads: usize,
+    pub undetermined_reads: usize,
+    pub samples: Vec<SampleSummary>,
+    // One row per hopped (i7, i5) combination, in order of first appearance
+    pub index_hopping: Vec<IndexHopCount>,
+    #[serde(skip)]
+    hop_rows: FxHashMap<(usize, usize), usize>,
+    #[serde(skip)]
+    i7_labels: Vec<(String, Vec<String>)>,
+    #[serde(skip)]
+    i5_labels: Vec<(String, Vec<String>)>,
+}
+
+fn index_labels(
+    indices: &[Vec<u8>],
+    sample_indices: Vec<(&[u8], &str)>,
+) -> Vec<(String, Vec<String>)> {
+    indices
+        .iter()
+        .map(|index| {
+            let samples = sample_indices
+                .iter()
+                .filter(|(i, _)| i == index)
+                .map(|(_, name)| name.to_string())
+                .collect();
+            (String::from_utf8_lossy(index).into_owned(), samples)
+        })
+        .collect()
+}
+
+impl DualIndexSummary {
+    pub fn new(demultiplexer: &DualIndexDemultiplexer) -> Self {
+        let samples = demultiplexer.samples();
+        DualIndexSummary {
+            total_reads: 0,
+            assigned_reads: 0,
+            exact_reads: 0,
+            corrected_reads: 0,
+            ambiguous_reads: 0,
+            index_hopped_reads: 0,
+            undetermined_reads: 0,
+            samples: samples
+                .iter()
+                .map(|s| SampleSummary {
+                    name: s.name.clone(),
+                    barcode: format!(
+                        "{}+{}",
+                        String::from_utf8_lossy(&s.i7),
+                        String::from_utf8_lossy(&s.i5)
+                    ),
+                    reads: 0,
+                    corrected_reads: 0,
+                })
+                .collect(),
+            index_hopping: Vec::new(),
+            hop_rows: FxHashMap::default(),
+            i7_labels: index_labels(
+                demultiplexer.i7_indices(),
+                samples
+                    .iter()
+                    .map(|s| (s.i7.as_slice(), s.name.as_str()))
+                    .collect(),
+            ),
+            i5_labels: index_labels(
+                demultiplexer.i5_indices(),
+                samples
+                    .iter()
+                    .map(|s| (s.i5.as_slice(), s.name.as_str()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn record(&mut self, assignment: DualAssignment) {
+        self.total_reads += 1;
+        match assignment {
+            DualAssignment::Sample {
+                sample_id,
+                i7_distance,
+                i5_distance,
+            } => {
+                self.assigned_reads += 1;
+                self.samples[sample_id].reads += 1;
+                if i7_distance == 0 && i5_distance == 0 {
+                    self.exact_reads += 1;
+                } else {
+                    self.corrected_reads += 1;
+                    self.samples[sample_id].corrected_reads += 1;
+                }
+            }
+            DualAssignment::IndexHopped { i7_id, i5_id } => {
+                self.index_hopped_reads += 1;
+                let row = match self.hop_rows.entry((i7_id, i5_id)) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        let (i7, i7_samples) = self.i7_labels[i7_id].clone();
+                        let (i5, i5_samples) = self.i5_labels[i5_id].clone();
+                        self.index_hopping.push(IndexHopCount {
+                            i7,
+                            i5,
+                            i7_samples,
+                            i5_samples,
+                            reads: 0,
+                        });
+                        *entry.insert(self.index_hopping.len() - 1)
+                    }
+                };
+                self.index_hopping[row].reads += 1;
+            }
+            DualAssignment::Ambiguous => self.ambiguous_reads += 1,
+            DualAssignment::Undetermined => self.undetermined_reads += 1,
+        }
+    }
+
+    // Hop rows sorted by read count, most frequent first
+    pub fn index_hopping_table(&self) -> Vec<&IndexHopCount> {
+        let mut rows: Vec<&IndexHopCount> = self.index_hopping.iter().collect();
+        rows.sort_by(|a, b| {
+            b.reads
+                .cmp(&a.reads)
+                .then_with(|| (&a.i7, &a.i5).cmp(&(&b.i7, &b.i5)))
+        });
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_parse_sample_sheet() {
+        let sheet = "# run 42\nsample,barcode\nS1,ACGTACGT\nS2\tttggccaa\n\n";
+        let samples = parse_sample_sheet(Cursor::new(sheet)).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[1].name, "S2");
+        assert_eq!(samples[1].barcode, b"TTGGCCAA");
+
+        assert!(parse_sample_sheet(Cursor::new("S1,ACGT\nS2,NOTDNA!\n")).is_err());
+        assert!(parse_sample_sheet(Cursor::new("S1\n")).is_err());
+
+        let error = parse_sample_sheet(Cursor::new("S1,ACGTACGT\nS2,ACGTAC\n")).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+        let error = parse_sample_sheet(Cursor::new("S1,ACGTACGT\nS2,acgtacgt\n")).unwrap_err();
+        assert!(error.to_string().contains("share barcode"), "{}", error);
+    }
+
+    #[test]
+    fn test_assign_and_summary() {
+        let samples = vec![
+            Sample {
+                name: "S1".to_string(),
+                barcode: b"AAAACCCC".to_vec(),
+            },
+            Sample {
+                name: "S2".to_string(),
+                barcode: b"AAAACCCG".to_vec(),
+            },
+            Sample {
+                name: "S3".to_string(),
+                barcode: b"GGTTGGTT".to_vec(),
+            },
+        ];
+        let demux = Demultiplexer::new(
+            samples.clone(),
+            DemuxConfig {
+                barcode_start: 2,
+                metric: MatchMetric::SequenceLevenshtein,
+                max_distance: 1,
+                n_policy: NPolicy::Mismatch,
+                min_posterior: None,
+            },
+        );
+
+        let mut summary = DemuxSummary::new(&samples);
+        for (read, expected) in [
+            (
+                &b"NNGGTTGGTTACGT"[..],
+                Assignment::Sample {
+                    sample_id: 2,
+                    distance: 0,
+                },
+            ),
+            (
+                &b"NNGGTTGCTTACGT"[..],
+                Assignment::Sample {
+                    sample_id: 2,
+                    distance: 1,
+                },
+            ),
+            (&b"NNAAAACCCAACGT"[..], Assignment::Ambiguous),
+            (&b"NNTTTTTTTTACGT"[..], Assignment::Undetermined),
+        ] {
+            let assignment = demux.assign(read);
+            assert_eq!(assignment, expected);
+            summary.record(assignment);
+        }
+
+        assert_eq!(summary.total_reads, 4);
+        assert_eq!(summary.assigned_reads, 2);
+        assert_eq!(summary.exact_reads, 1);
+        assert_eq!(summary.corrected_reads, 1);
+        assert_eq!(summary.ambiguous_reads, 1);
+        assert_eq!(summary.undetermined_reads, 1);
+        assert_eq!(summary.samples[2].reads, 2);
+        assert_eq!(summary.samples[2].corrected_reads, 1);
+    }
+
+    #[test]
+    fn test_quality_assignment() {
+        let samples = vec![
+            Sample {
+                name: "S1".to_string(),
+                barcode: b"AAAACCCC".to_vec(),
+            },
+            Sample {
+                name: "S2".to_string(),
+                barcode: b"TAAACCCG".to_vec(),
+            },
+        ];
+        let mut config = DemuxConfig {
+            barcode_start: 0,
+            metric: MatchMetric::Hamming,
+            max_distance: 1,
+            n_policy: NPolicy::Mismatch,
+            min_posterior: None,
+        };
+        // One mismatch away from both samples
+        let mut record = FastqRecord {
+            id: b"r1".to_vec(),
+            seq: b"TAAACCCC".to_vec(),
+            qual: b"#IIIIIII".to_vec(),
+        };
+        let plain = Demultiplexer::new(samples.clone(), config);
+        assert_eq!(plain.assign_record(&record), Assignment::Ambiguous);
+
+        config.min_posterior = Some(0.99);
+        let weighted = Demultiplexer::new(samples, config);
+        // The mismatch against S1 is on a Q2 base
+        assert_eq!(
+            weighted.assign_record(&record),
+            Assignment::Sample {
+                sample_id: 0,
+                distance: 1,
+            }
+        );
+        // Both mismatches at Q40, not confident enough for either
+        record.qual = b"IIIIIIII".to_vec();
+        assert_eq!(weighted.assign_record(&record), Assignment::Ambiguous);
+        record.seq = b"GGGGGGGG".to_vec();
+        assert_eq!(weighted.assign_record(&record), Assignment::Undetermined);
+    }
+
+    #[test]
+    fn test_dual_index_assignment_and_hopping() {
+        let sheet = "name,i7,i5\nA,AAAACCCC,GGGGTTTT\nB,CCCCGGGG,TTTTAAAA\nC,GGGGTTTT,ACACACAC\n";
+        let samples = parse_dual_index_sheet(Cursor::new(sheet)).unwrap();
+        assert_eq!(samples[2].i5, b"ACACACAC");
+        assert!(parse_dual_index_sheet(Cursor::new("A,AAAACCCC\n")).is_err());
+        let error = parse_dual_index_sheet(Cursor::new("A,AAAA,CCCC\nB,AAAA,CCCC\n")).unwrap_err();
+        assert!(error.to_string().contains("index pair"), "{}", error);
+        let error = parse_dual_index_sheet(Cursor::new("A,AAAA,CCCC\nB,GGGG,CCC\n")).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+
+        for metric in [MatchMetric::Hamming, MatchMetric::SequenceLevenshtein] {
+            let demux = DualIndexDemultiplexer::new(samples.clone(), metric, 1);
+            let mut summary = DualIndexSummary::new(&demux);
+            for (i7, i5, expected) in [
+                (
+                    &b"AAAACCCC"[..],
+                    &b"GGGGTTTT"[..],
+                    DualAssignment::Sample {
+                        sample_id: 0,
+                        i7_distance: 0,
+                        i5_distance: 0,
+                    },
+                ),
+                (
+                    b"CCCCGGGA",
+                    b"TTTTAAAA",
+                    DualAssignment::Sample {
+                        sample_id: 1,
+                        i7_distance: 1,
+                        i5_distance: 0,
+                    },
+                ),
+                // i7 of A with i5 of B, twice, and i7 of C with i5 of A once
+                (
+                    b"AAAACCCC",
+                    b"TTTTAAAA",
+                    DualAssignment::IndexHopped { i7_id: 0, i5_id: 1 },
+                ),
+                (
+                    b"AAAACCCC",
+                    b"TTTTAAAT",
+                    DualAssignment::IndexHopped { i7_id: 0, i5_id: 1 },
+                ),
+                (
+                    b"GGGGTTTT",
+                    b"GGGGTTTT",
+                    DualAssignment::IndexHopped { i7_id: 2, i5_id: 0 },
+                ),
+                // A valid i7 alone is not enough
+                (b"AAAACCCC", b"CGCGCGCG", DualAssignment::Undetermined),
+            ] {
+                let assignment = demux.assign(i7, i5);
+                assert_eq!(assignment, expected, "{:?}", metric);
+                summary.record(assignment);
+            }
+
+            assert_eq!(summary.assigned_reads, 2);
+            assert_eq!(summary.corrected_reads, 1);
+            assert_eq!(summary.index_hopped_reads, 3);
+            assert_eq!(summary.undetermined_reads, 1);
+            let table = summary.index_hopping_table();
+            assert_eq!(table.len(), 2);
+            assert_eq!(
+                (table[0].i7.as_str(), table[0].i5.as_str()),
+                ("AAAACCCC", "TTTTAAAA")
+            );
+            assert_eq!(table[0].reads, 2);
+            assert_eq!(
+                (&table[0].i7_samples[..], &table[0].i5_samples[..]),
+                (&["A".to_string()][..], &["B".to_string()][..])
+            );
+        }
+    }
+}
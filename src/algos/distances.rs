@@ -1,15 +1,12 @@
+use crate::algos::iupac::{self, NPolicy};
+use crate::algos::scoring::EditCosts;
 use serde::{Deserialize, Serialize};
-use std::ops::BitAnd;
-use std::simd::cmp::SimdOrd;
-use std::simd::num::SimdInt;
-use std::simd::num::SimdUint;
+use std::ops::{
+    Add, AddAssign, BitAnd, BitOr, BitXor, Not, RangeInclusive, Shl, Shr, Sub, SubAssign,
+};
+use std::simd::cmp::{SimdOrd, SimdPartialEq};
 use std::simd::*;
 
-const A: u8 = 0b011;
-const C: u8 = 0b110;
-const G: u8 = 0b101;
-const T: u8 = 0b000;
-
 // This is the size of the bit vector, corresponding to ascii characters
 pub const PEQ_SIZE: usize = 256;
 
@@ -17,60 +14,250 @@ pub trait Distance<T: ?Sized> {
     fn distance(&self, a: &T, b: &T) -> usize;
     // Input must be a byte slice for SIMD myers variant algorithms
     fn find_distance(&self, t: &[u8], p: &[u8]) -> usize;
+
+    // Lower bound on d(q, x) for every x whose distance to a pivot p lies in `keys`, given
+    //     d = d(q, p) and `spread`, the largest length difference between any two of q, x and p
+    // The BK-tree, VP-tree and pivot table prune with it, the default is the triangle inequality
+    //     |d(q, p) - d(x, p)| <= d(q, x) so any distance that isn't a metric must override it
+    fn lower_bound(&self, d: usize, keys: RangeInclusive<usize>, _spread: usize) -> usize {
+        let (low, high) = keys.into_inner();
+        low.saturating_sub(d).max(d.saturating_sub(high))
+    }
+
+    // Item length fed into `spread`, only distances whose lower bound uses it need one
+    fn length(&self, _item: &T) -> usize {
+        0
+    }
+}
+
+// Shortest and longest item an index holds, gives the `spread` of Distance::lower_bound
+#[derive(Debug, Clone, Copy)]
+pub struct LengthRange {
+    min: usize,
+    max: usize,
+}
+
+impl LengthRange {
+    pub fn new() -> Self {
+        LengthRange {
+            min: usize::MAX,
+            max: 0,
+        }
+    }
+
+    pub fn insert(&mut self, length: usize) {
+        self.min = self.min.min(length);
+        self.max = self.max.max(length);
+    }
+
+    // Largest length difference between a query of this length and any two items
+    pub fn spread(&self, length: usize) -> usize {
+        self.max.max(length) - self.min.min(length)
+    }
+}
+
+impl Default for LengthRange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+Seq-lev is not a metric, trailing overhang is free so the triangle inequality can fail:
+    ACGT is 0 from both ACGTAAAA and ACGTTTTT, which are 4 apart
+Levenshtein still sandwiches it, with n the length difference of a and b
+    seq-lev(a, b) <= lev(a, b) <= 2 seq-lev(a, b) + n
+    the best seq-lev alignment ends in the last row or column, finishing it costs the overhang
+    and the overhang is at most n plus the seq-lev distance (it is a length difference too)
+so the triangle inequality on lev gives a looser but exact bound
+IUPAC base sets break lev as well (R matches A and G, A and G don't match), nothing is pruned
+*/
+fn seq_lev_lower_bound(
+    d: usize,
+    keys: RangeInclusive<usize>,
+    spread: usize,
+    iupac: Option<NPolicy>,
+) -> usize {
+    if iupac.is_some() {
+        return 0;
+    }
+    let (low, high) = keys.into_inner();
+    // lev(q, p) is in [d, 2d + spread] and lev(x, p) in [low, 2 high + spread]
+    let query_high = d.saturating_mul(2).saturating_add(spread);
+    let item_high = high.saturating_mul(2).saturating_add(spread);
+    let gap = low
+        .saturating_sub(query_high)
+        .max(d.saturating_sub(item_high));
+    // lev(q, x) >= gap and seq-lev(q, x) >= (lev(q, x) - spread) / 2
+    gap.saturating_sub(spread).div_ceil(2)
 }
 
-pub struct SequenceLevenshteinDistanceSimd;
+// Verification only needs to know whether a distance is <= k
+// distance_within returns the distance when it is <= k and None as soon as it is provably
+//     larger, the k given here replaces any max distance the metric was built with
+pub trait BoundedDistance<T: ?Sized>: Distance<T> {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize>;
+}
+
+// Windowed seq-lev, `a` is the read and `b` the barcode
+// distance returns the best window, capped at max_distance + 1 when a cutoff is set
+// iupac: None compares bytes, Some matches intersecting IUPAC base sets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceLevenshteinDistanceSimd {
+    max_distance: usize,
+    iupac: Option<NPolicy>,
+}
+
+// The widest dispatch configuration, one u64 bit per barcode base
+pub const SIMD_MAX_BARCODE_LENGTH: usize = 64;
+
+// Unsigned lane types for the windowed kernel, each lane holds one bit per pattern base
+pub trait SimdLane: SimdElement + Copy + Default + BitOr<Output = Self> {
+    const BITS: usize;
+    fn bit(index: usize) -> Self;
+    fn from_usize(value: usize) -> Self;
+    fn to_usize(self) -> usize;
+}
+
+macro_rules! impl_simd_lane {
+    ($($t:ty),*) => {
+        $(
+            impl SimdLane for $t {
+                const BITS: usize = <$t>::BITS as usize;
+                #[inline(always)]
+                fn bit(index: usize) -> Self {
+                    1 << index
+                }
+                #[inline(always)]
+                fn from_usize(value: usize) -> Self {
+                    value as $t
+                }
+                #[inline(always)]
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_simd_lane!(u8, u16, u32, u64);
+
+// Vector operations the kernel needs, any Simd<SimdLane, N> has them
+pub trait SimdLaneOps:
+    Copy
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + SubAssign
+    + Shl<Output = Self>
+    + Shr<Output = Self>
+    + SimdOrd
+    + PartialEq
+{
+}
+
+impl<V> SimdLaneOps for V where
+    V: Copy
+        + BitAnd<Output = V>
+        + BitOr<Output = V>
+        + BitXor<Output = V>
+        + Not<Output = V>
+        + Add<Output = V>
+        + AddAssign
+        + Sub<Output = V>
+        + SubAssign
+        + Shl<Output = V>
+        + Shr<Output = V>
+        + SimdOrd
+        + PartialEq
+{
+}
 
 impl SequenceLevenshteinDistanceSimd {
     pub fn new() -> Self {
-        SequenceLevenshteinDistanceSimd
+        SequenceLevenshteinDistanceSimd {
+            max_distance: usize::MAX,
+            iupac: None,
+        }
+    }
+
+    pub fn with_max_distance(max_distance: usize) -> Self {
+        SequenceLevenshteinDistanceSimd {
+            max_distance,
+            iupac: None,
+        }
+    }
+
+    pub fn with_iupac(mut self, n_policy: NPolicy) -> Self {
+        self.iupac = Some(n_policy);
+        self
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
     }
 
     /*
     TODO: use array size 4 for DNA, use something like "ord(nucleotide) % 4" instead of re-encoding
-    Each element in simd peq is a size 8 element array of u16 values
+    Each element in simd peq is a N element array of T values (u8, u16, u32 or u64)
     These values correspond the the Ascii value of the character
     The simd peq array is size 256, one for each ascii character
-    We have 8 elements for every element in the 256 array
-    In my use cases I window sequence data, so this allows me to process 8 windows at a time
+    We have N elements for every element in the 256 array
+    In my use cases I window sequence data, so this allows me to process N windows at a time
         for each bitwise operation
     peq: [
-    u16x8 { ... },  // For ASCII character 0
-    u16x8 { ... },  // For ASCII character 1
+    Simd<T, N> { ... },  // For ASCII character 0
+    Simd<T, N> { ... },  // For ASCII character 1
     ...
-    u16x8 { ... },  // For ASCII character 255
+    Simd<T, N> { ... },  // For ASCII character 255
     ]
+    The windows are barcode length (or the whole read when it is shorter), window i starts at
+        read[i], and each lane runs Myers twice, like the scalar version:
+        barcode as the pattern over the window, the peq is shared by all lanes
+        window as the pattern over the barcode, every lane has its own peq bits
+    The minimum of the last row in each direction covers the last row and last column of the
+        seq-lev matrix, the distance is the smaller of the two
+    The score lives in the lane type as well, it never exceeds the pattern length, so no
+        signed lanes are needed and the carry bit is just (ph & hb) >> (m - 1)
+    With a limit set, a lane whose score is above limit + (columns left) can't bring its last
+        row minimum down to limit anymore (the row drops by at most 1 per column), once every
+        lane is there the chunk stops, like the scalar kernel. Distances above limit are then
+        only known to be above limit
+    dispatch picks the narrowest lane that fits the barcode:
+        <= 8 bases u8 x 32 (u8 x 64 once the read has 64 windows), <= 16 u16 x 16,
+        <= 32 u32 x 8, <= 64 u64 x 8
+    The u8, u16 and u32 configurations fill a 256 bit register, u8 x 64 and u64 x 8 are 512
+        bits (two AVX2 registers), u64 keeps 8 lanes so a chunk still covers 8 windows
+    With iupac set the read and barcode are re-encoded as base sets (0..16) first, a pattern
+        base then sets its bit in every slot its set intersects, see peq_slots
     */
     #[inline(always)]
-    fn sequence_levenshtein_simd(&self, read: &[u8], barcode: &[u8]) -> Vec<(usize, usize)> {
-        // TODO: make SIMD vector width configurable
-        // TODO: make simd type configurable (u8, u16, u32, u64, etc.)
-        const SIMD_WIDTH: usize = 8; // Assuming 8 windows processed simultaneously
-        let mut matches = vec![];
-
-        let windows: Vec<_> = read.windows(SIMD_WIDTH).collect();
-        //println!("windows: {:?}", windows);
-        let mut min_last_col = i16x8::splat(barcode.len() as i16);
-        let mut score = i16x8::splat(barcode.len() as i16);
-        let mut peq = [0u16; PEQ_SIZE];
-
-        // Fill bit vectors for each character in barcode
-        // Same peq used for all windows (this would need changed if we feed windows from different sequences)
-        for i in 0..barcode.len() {
-            peq[barcode[i] as usize] |= 1 << i;
-        }
-        //println!("peq: {:?}", peq);
-        let mut pv = u16x8::splat(!0);
-        let mut mv = u16x8::splat(0);
-        let hb = u16x8::splat(1 << (barcode.len() - 1) as u16);
-
-        for j in 0..barcode.len() {
-            let mut eq_values = [0; SIMD_WIDTH];
-            for i in 0..SIMD_WIDTH {
-                let window_char = windows[i][j] as usize;
-                eq_values[i] = peq[window_char];
-            }
-            let eq = u16x8::from(eq_values);
+    fn myers_last_row<T, const N: usize, F>(
+        pattern_len: usize,
+        text_len: usize,
+        limit: usize,
+        peq_at: F,
+    ) -> Simd<T, N>
+    where
+        T: SimdLane,
+        Simd<T, N>: SimdLaneOps,
+        F: Fn(usize) -> Simd<T, N>,
+    {
+        let mut min_last_row = Simd::<T, N>::splat(T::from_usize(pattern_len));
+        let mut score = min_last_row;
+        let mut pv = !Simd::<T, N>::splat(T::default());
+        let mut mv = Simd::<T, N>::splat(T::default());
+        let one = Simd::<T, N>::splat(T::from_usize(1));
+        let hb = Simd::<T, N>::splat(T::bit(pattern_len - 1));
+        let hb_shift = Simd::<T, N>::splat(T::from_usize(pattern_len - 1));
+
+        for j in 0..text_len {
+            let eq = peq_at(j);
 
             // Our data is in a SIMD vector, we don't need to do anything different
             //     for bitwise operations. The compiler will handle it
@@ -79,129 +266,423 @@ impl SequenceLevenshteinDistanceSimd {
             let ph = mv | !(xh | pv);
             let mh = pv & xh;
 
-            let ph_mask: i16x8 = ph.bitand(&hb).cast();
-            let mh_mask: i16x8 = mh.bitand(&hb).cast();
-            // This is hacky way to deal with bools which are poorly supported in simd
-            let ph_add = ph_mask.signum();
-            let mh_sub = mh_mask.signum();
+            // ph and mh never share a bit, so the score stays in 0..=pattern_len
+            score += (ph & hb) >> hb_shift;
+            score -= (mh & hb) >> hb_shift;
 
-            score = score + ph_add;
-            score = score - mh_sub;
-
-            let ph = ph << 1 | u16x8::splat(1);
-            let mh = mh << 1;
+            let ph = (ph << one) | one;
+            let mh = mh << one;
             pv = mh | !(xv | ph);
             mv = ph & xv;
 
-            min_last_col = min_last_col.simd_min(score);
+            min_last_row = min_last_row.simd_min(score);
+
+            // Scores never exceed pattern_len, a threshold at or above it can't be crossed
+            let threshold = limit.saturating_add(text_len - 1 - j);
+            if threshold < pattern_len {
+                let above = Simd::<T, N>::splat(T::from_usize(threshold + 1));
+                if score.simd_min(above) == above {
+                    break;
+                }
+            }
         }
+        min_last_row
+    }
 
-        let min_last_col_array = min_last_col.to_array();
+    // Seq-lev distance of every barcode-length window with N windows per instruction
+    // The barcode has to fit in T
+    pub fn window_distances_with<T, const N: usize>(
+        &self,
+        read: &[u8],
+        barcode: &[u8],
+    ) -> Vec<usize>
+    where
+        T: SimdLane,
+        Simd<T, N>: SimdLaneOps,
+    {
+        self.windows_within::<T, N>(read, barcode, usize::MAX, false)
+    }
 
-        for i in 0..SIMD_WIDTH {
-            if min_last_col_array[i] <= 1 {
-                let window_index = i;
-                matches.push((window_index, window_index + barcode.len() - 1));
+    // Window distances above limit are not exact, usize::MAX gives every window its distance
+    // With shrink set only the best window matters: the limit drops below the best window of
+    //     every chunk and the scan ends at the first exact window, later windows are skipped
+    fn windows_within<T, const N: usize>(
+        &self,
+        read: &[u8],
+        barcode: &[u8],
+        mut limit: usize,
+        shrink: bool,
+    ) -> Vec<usize>
+    where
+        T: SimdLane,
+        Simd<T, N>: SimdLaneOps,
+    {
+        assert!(
+            barcode.len() <= T::BITS,
+            "Barcode of {} bases does not fit in {} bit lanes",
+            barcode.len(),
+            T::BITS
+        );
+        if read.is_empty() || barcode.is_empty() {
+            // Every window is empty, seq-lev against an empty sequence is all overhang and free
+            return vec![0; read.len() + 1 - barcode.len().min(read.len())];
+        }
+        let encoded;
+        let (read, barcode) = match self.iupac {
+            Some(n_policy) => {
+                encoded = (
+                    iupac::encode_base_sets(read, n_policy),
+                    iupac::encode_base_sets(barcode, n_policy),
+                );
+                (encoded.0.as_slice(), encoded.1.as_slice())
+            }
+            None => (read, barcode),
+        };
+        let sets = self.iupac.is_some();
+        let window_len = barcode.len().min(read.len());
+        let num_windows = read.len() - window_len + 1;
+        let mut distances = Vec::with_capacity(num_windows);
+
+        // Same peq used for all windows
+        let mut peq = [T::default(); PEQ_SIZE];
+        for (i, &base) in barcode.iter().enumerate() {
+            for slot in peq_slots(base, sets) {
+                peq[slot] = peq[slot] | T::bit(i);
             }
         }
+        let zero = Simd::<T, N>::splat(T::default());
+        let mut window_peq = [zero; PEQ_SIZE];
+
+        for first in (0..num_windows).step_by(N) {
+            // The last chunk repeats its final window in the unused lanes
+            let starts: [usize; N] =
+                std::array::from_fn(|lane| (first + lane).min(num_windows - 1));
+
+            let barcode_pattern = Self::myers_last_row(barcode.len(), window_len, limit, |j| {
+                Simd::from_array(std::array::from_fn(|lane| {
+                    peq[read[starts[lane] + j] as usize]
+                }))
+            });
+
+            for (lane, &start) in starts.iter().enumerate() {
+                for (k, &base) in read[start..start + window_len].iter().enumerate() {
+                    for slot in peq_slots(base, sets) {
+                        let lanes = &mut window_peq[slot];
+                        lanes[lane] = lanes[lane] | T::bit(k);
+                    }
+                }
+            }
+            let window_pattern = Self::myers_last_row(window_len, barcode.len(), limit, |j| {
+                window_peq[barcode[j] as usize]
+            });
+            for &start in &starts {
+                for &base in &read[start..start + window_len] {
+                    for slot in peq_slots(base, sets) {
+                        window_peq[slot] = zero;
+                    }
+                }
+            }
+
+            let scores = barcode_pattern.simd_min(window_pattern).to_array();
+            let lanes = N.min(num_windows - first);
+            distances.extend(scores[..lanes].iter().map(|&d| d.to_usize()));
 
-        matches
+            if shrink {
+                let best = scores[..lanes].iter().map(|&d| d.to_usize()).min().unwrap();
+                if best == 0 {
+                    break;
+                }
+                limit = limit.min(best - 1);
+            }
+        }
+
+        distances
+    }
+
+    fn sequence_levenshtein_simd(
+        &self,
+        read: &[u8],
+        barcode: &[u8],
+        limit: usize,
+        shrink: bool,
+    ) -> Vec<usize> {
+        // Wider chunks only pay off when they are full, short reads keep 32 lanes
+        let num_windows = read.len() + 1 - barcode.len().min(read.len());
+        match barcode.len() {
+            0..=8 if num_windows >= 64 => {
+                self.windows_within::<u8, 64>(read, barcode, limit, shrink)
+            }
+            0..=8 => self.windows_within::<u8, 32>(read, barcode, limit, shrink),
+            9..=16 => self.windows_within::<u16, 16>(read, barcode, limit, shrink),
+            17..=32 => self.windows_within::<u32, 8>(read, barcode, limit, shrink),
+            33..=SIMD_MAX_BARCODE_LENGTH => {
+                self.windows_within::<u64, 8>(read, barcode, limit, shrink)
+            }
+            _ => panic!(
+                "SIMD seq-lev supports barcodes up to {} bases",
+                SIMD_MAX_BARCODE_LENGTH
+            ),
+        }
+    }
+
+    // Actual seq-lev distance of every window, window i starts at read[i]
+    pub fn window_distances(&self, read: &[u8], barcode: &[u8]) -> Vec<usize> {
+        self.sequence_levenshtein_simd(read, barcode, usize::MAX, false)
+    }
+
+    // (window start, distance) of every window within max distance
+    pub fn matches(&self, read: &[u8], barcode: &[u8]) -> Vec<(usize, usize)> {
+        self.sequence_levenshtein_simd(read, barcode, self.max_distance, false)
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, d)| d <= self.max_distance)
+            .collect()
+    }
+
+    // Closest window within max distance, ties go to the leftmost window
+    pub fn best_window(&self, read: &[u8], barcode: &[u8]) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize)> = None;
+        for (start, d) in self
+            .sequence_levenshtein_simd(read, barcode, self.max_distance, true)
+            .into_iter()
+            .enumerate()
+        {
+            if d <= self.max_distance && best.is_none_or(|(_, b)| d < b) {
+                best = Some((start, d));
+            }
+        }
+        best
+    }
+
+    fn capped_distance(&self, read: &[u8], barcode: &[u8]) -> usize {
+        match self.best_window(read, barcode) {
+            Some((_, d)) => d,
+            None => self.max_distance.saturating_add(1),
+        }
     }
 }
 
 impl<T: AsRef<[u8]> + ?Sized> Distance<T> for SequenceLevenshteinDistanceSimd {
     #[inline(always)]
     fn distance(&self, a: &T, b: &T) -> usize {
-        let read = a.as_ref();
-        let barcode = b.as_ref();
-        let matches = self.sequence_levenshtein_simd(read, barcode);
-        if matches.is_empty() {
-            read.len()
-        } else {
-            matches[0].0
-        }
+        self.capped_distance(a.as_ref(), b.as_ref())
     }
 
     #[inline(always)]
     fn find_distance(&self, read: &[u8], barcode: &[u8]) -> usize {
-        let matches = self.sequence_levenshtein_simd(read, barcode);
-        if matches.is_empty() {
-            read.len()
-        } else {
-            matches[0].0
+        self.capped_distance(read, barcode)
+    }
+
+    // Best window of a read against a barcode, not symmetric so no bound holds
+    fn lower_bound(&self, _d: usize, _keys: RangeInclusive<usize>, _spread: usize) -> usize {
+        0
+    }
+}
+
+// Chunks stop once every lane is provably above k, later chunks only look for a better window
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for SequenceLevenshteinDistanceSimd {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        self.sequence_levenshtein_simd(a.as_ref(), b.as_ref(), k, true)
+            .into_iter()
+            .min()
+            .filter(|&d| d <= k)
+    }
+}
+
+// Longest pattern a single u128 word holds
+pub const MYERS_MAX_PATTERN_LENGTH: usize = 128;
+
+// Bit vector words for the scalar Myers kernel
+trait MyersWord:
+    Copy
+    + PartialEq
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + Shl<u32, Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+    fn wrapping_add(self, other: Self) -> Self;
+}
+
+macro_rules! impl_myers_word {
+    ($($t:ty),*) => {
+        $(
+            impl MyersWord for $t {
+                const ZERO: Self = 0;
+                const ONE: Self = 1;
+                #[inline(always)]
+                fn wrapping_add(self, other: Self) -> Self {
+                    <$t>::wrapping_add(self, other)
+                }
+            }
+        )*
+    };
+}
+
+impl_myers_word!(u32, u64, u128);
+
+// Peq entries a pattern byte sets its bit in, itself for plain bytes and every intersecting
+//     set for base sets (a set of 0 matches nothing)
+#[inline(always)]
+fn peq_slots(base: u8, sets: bool) -> impl Iterator<Item = usize> {
+    let slots = if sets {
+        1..16
+    } else {
+        base as usize..base as usize + 1
+    };
+    slots.filter(move |&slot| !sets || slot & base as usize != 0)
+}
+
+// Bit i of peq[c] is set when text byte c matches pattern[i]
+// Exact bytes without an IUPAC policy, intersecting base sets with one
+#[inline(always)]
+fn fill_peq<W: MyersWord>(pattern: &[u8], iupac: Option<NPolicy>, peq: &mut [W; PEQ_SIZE]) {
+    match iupac {
+        None => {
+            for (i, &base) in pattern.iter().enumerate() {
+                peq[base as usize] = peq[base as usize] | (W::ONE << i as u32);
+            }
+        }
+        Some(n_policy) => {
+            // Collect by base set first, every text code then takes the bits of its set
+            let mut by_set = [W::ZERO; 16];
+            for (i, &base) in pattern.iter().enumerate() {
+                let set = iupac::base_set(base, n_policy);
+                for (other, bits) in by_set.iter_mut().enumerate() {
+                    if set & other as u8 != 0 {
+                        *bits = *bits | (W::ONE << i as u32);
+                    }
+                }
+            }
+            for &code in iupac::IUPAC_CODES {
+                peq[code as usize] = by_set[iupac::base_set(code, n_policy) as usize];
+            }
         }
     }
 }
 
+// iupac: None compares bytes, Some matches intersecting IUPAC base sets
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SequenceLevenshteinDistance;
+pub struct SequenceLevenshteinDistance {
+    iupac: Option<NPolicy>,
+}
 
 impl SequenceLevenshteinDistance {
     pub fn new() -> Self {
-        SequenceLevenshteinDistance
+        SequenceLevenshteinDistance { iupac: None }
+    }
+
+    pub fn with_iupac(mut self, n_policy: NPolicy) -> Self {
+        self.iupac = Some(n_policy);
+        self
     }
 
     // This is sequence levenshtein distance modified myers algorithm
+    // t is the pattern (one bit per base), the word type is picked from its length
+    // Scores of the last row above limit are not exact, see myers
     #[inline(always)]
-    fn sequence_levenshtein(&self, t: &[u8], n: usize, p: &[u8], m: usize) -> usize {
-        let mut min_last_col = n as i16;
-        let mut score = n as i16;
-        let mut peq = [0u16; PEQ_SIZE];
-        // Fill bit vector
-        for i in 0..n {
-            peq[t[i] as usize] |= 1u16 << i;
+    fn sequence_levenshtein(&self, t: &[u8], n: usize, p: &[u8], m: usize, limit: usize) -> usize {
+        match n {
+            // The last row of an empty pattern is row 0, which starts at 0
+            0 => 0,
+            1..=32 => self.myers::<u32>(t, n, p, m, limit),
+            33..=64 => self.myers::<u64>(t, n, p, m, limit),
+            65..=MYERS_MAX_PATTERN_LENGTH => self.myers::<u128>(t, n, p, m, limit),
+            _ => panic!(
+                "Myers seq-lev supports patterns up to {} bases",
+                MYERS_MAX_PATTERN_LENGTH
+            ),
         }
-        let mut pv = !0u16;
-        let mut mv = 0u16;
-        let hb = 1u16 << (n - 1);
+    }
+
+    // The last row drops by at most 1 per column, once score - (columns left) is above limit
+    //     the minimum can't reach limit anymore and the scan stops
+    #[inline(always)]
+    fn myers<W: MyersWord>(&self, t: &[u8], n: usize, p: &[u8], m: usize, limit: usize) -> usize {
+        let mut min_last_col = n;
+        let mut score = n;
+        let mut peq = [W::ZERO; PEQ_SIZE];
+        // Fill bit vector
+        fill_peq(&t[..n], self.iupac, &mut peq);
+        let mut pv = !W::ZERO;
+        let mut mv = W::ZERO;
+        let hb = W::ONE << (n - 1) as u32;
         for j in 0..m {
             let eq = peq[p[j] as usize];
             let xv = eq | mv;
-            let xh = (((eq & pv).wrapping_add(pv)) ^ pv) | eq;
+            let xh = ((eq & pv).wrapping_add(pv) ^ pv) | eq;
             let ph = mv | !(xh | pv);
             let mh = pv & xh;
-            if ph & hb != 0 {
+            if ph & hb != W::ZERO {
                 score += 1;
             }
-            if mh & hb != 0 {
+            if mh & hb != W::ZERO {
                 score -= 1;
             }
-            let ph = ph << 1 | 1;
+            let ph = (ph << 1) | W::ONE;
             let mh = mh << 1;
             pv = mh | !(xv | ph);
             mv = ph & xv;
             if score < min_last_col {
                 min_last_col = score;
             }
+            if score > limit.saturating_add(m - 1 - j) {
+                break;
+            }
         }
-        min_last_col as usize
+        min_last_col
     }
-}
 
-impl<T: AsRef<[u8]> + ?Sized> Distance<T> for SequenceLevenshteinDistance {
+    // Both directions, patterns too long for a single word go through the blocked kernel
+    // Distances above limit are not exact, usize::MAX gives the full distance
     #[inline(always)]
-    fn distance(&self, a: &T, b: &T) -> usize {
-        let t = a.as_ref();
-        let p = b.as_ref();
-
+    fn seq_lev(&self, t: &[u8], p: &[u8], limit: usize) -> usize {
         let n = t.len();
         let m = p.len();
+        if n > MYERS_MAX_PATTERN_LENGTH || m > MYERS_MAX_PATTERN_LENGTH {
+            let cutoff = (limit != usize::MAX).then_some(limit);
+            let blocked = SequenceLevenshteinDistanceBlocked {
+                max_distance: None,
+                iupac: self.iupac,
+            };
+            return blocked.blocked_distance(t, p, cutoff);
+        }
 
         // Instead of calculating twice, this would be easy to adapt with SIMD
         // We can collect the windows of each sequence and process them in parallel
-        let score_t = self.sequence_levenshtein(t, n, p, m);
-        let score_p = self.sequence_levenshtein(p, m, t, n);
+        let score_t = self.sequence_levenshtein(t, n, p, m, limit);
+        let score_p = self.sequence_levenshtein(p, m, t, n, limit);
         std::cmp::min(score_t, score_p)
     }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Distance<T> for SequenceLevenshteinDistance {
+    #[inline(always)]
+    fn distance(&self, a: &T, b: &T) -> usize {
+        self.seq_lev(a.as_ref(), b.as_ref(), usize::MAX)
+    }
 
     #[inline(always)]
     fn find_distance(&self, t: &[u8], p: &[u8]) -> usize {
-        let n = t.len();
-        let m = p.len();
+        self.seq_lev(t, p, usize::MAX)
+    }
 
-        let score_t = self.sequence_levenshtein(t, n, p, m);
-        let score_p = self.sequence_levenshtein(p, m, t, n);
-        std::cmp::min(score_t, score_p)
+    fn lower_bound(&self, d: usize, keys: RangeInclusive<usize>, spread: usize) -> usize {
+        seq_lev_lower_bound(d, keys, spread, self.iupac)
+    }
+
+    fn length(&self, item: &T) -> usize {
+        item.as_ref().len()
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for SequenceLevenshteinDistance {
+    #[inline(always)]
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        Some(self.seq_lev(a.as_ref(), b.as_ref(), k)).filter(|&d| d <= k)
     }
 }
 
@@ -228,60 +709,87 @@ impl<T: AsRef<str> + ?Sized> Distance<T> for HammingDistance {
     }
 }
 
-pub struct HammingDistanceSimd;
+impl<T: AsRef<str> + ?Sized> BoundedDistance<T> for HammingDistance {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        let mut distance = 0;
+        for (a, b) in a.as_ref().chars().zip(b.as_ref().chars()) {
+            if a != b {
+                distance += 1;
+                if distance > k {
+                    return None;
+                }
+            }
+        }
+        Some(distance)
+    }
+}
+
+// Bases are IUPAC base sets, a position is a mismatch when the sets don't intersect
+// N is a mismatch unless built with NPolicy::Match
+// Ambiguity codes (and N under NPolicy::Match) break the triangle inequality, indexes pruning
+//     on the default Distance::lower_bound are only exact for ACGT and mismatching N
+#[derive(Debug, Clone)]
+pub struct HammingDistanceSimd {
+    n_policy: NPolicy,
+}
 
 impl HammingDistanceSimd {
     pub fn new() -> Self {
-        HammingDistanceSimd
+        HammingDistanceSimd {
+            n_policy: NPolicy::Mismatch,
+        }
     }
 
-    fn encode_dna(sequence: &[u8]) -> Vec<u8> {
-        sequence
-            .iter()
-            .map(|&b| match b {
-                b'A' => A,
-                b'C' => C,
-                b'G' => G,
-                b'T' | b'U' => T,
-                _ => panic!("Invalid DNA base"),
-            })
-            .collect()
+    pub fn with_n_policy(mut self, n_policy: NPolicy) -> Self {
+        self.n_policy = n_policy;
+        self
     }
 
     #[inline(always)]
     fn hamming_distance_simd(&self, a: &[u8], b: &[u8]) -> usize {
-        let encoded_a = Self::encode_dna(a);
-        let encoded_b = Self::encode_dna(b);
+        self.hamming_within(a, b, usize::MAX).unwrap()
+    }
+
+    // Mismatches are the lanes where the AND of the base sets is empty, the count so far is a
+    //     lower bound and is checked after each chunk
+    #[inline(always)]
+    fn hamming_within(&self, a: &[u8], b: &[u8], k: usize) -> Option<usize> {
+        let encoded_a = iupac::encode_base_sets(a, self.n_policy);
+        let encoded_b = iupac::encode_base_sets(b, self.n_policy);
 
         let min_len = encoded_a.len().min(encoded_b.len());
         let max_len = encoded_a.len().max(encoded_b.len());
+        // The difference in length is known up front
+        let bound = k.saturating_sub(max_len - min_len);
+        if max_len - min_len > k {
+            return None;
+        }
 
-        // Process 64 bytes (128 nucleotides) at a time
+        // Process 64 bases at a time
         let chunks = min_len / 64;
         let mut distance = 0usize;
+        let empty = u8x64::splat(0);
 
         for i in 0..chunks {
             let start = i * 64;
             let a_chunk = u8x64::from_slice(&encoded_a[start..start + 64]);
             let b_chunk = u8x64::from_slice(&encoded_b[start..start + 64]);
-            let xor = a_chunk ^ b_chunk;
-            distance += xor
-                .to_array()
-                .iter()
-                .map(|&x| x.count_ones() as usize)
-                .sum::<usize>();
+            distance += (a_chunk & b_chunk).simd_eq(empty).to_bitmask().count_ones() as usize;
+            if distance > bound {
+                return None;
+            }
         }
 
         // Process remaining bytes
         for i in (chunks * 64)..min_len {
-            distance += (encoded_a[i] ^ encoded_b[i]).count_ones() as usize;
+            distance += (encoded_a[i] & encoded_b[i] == 0) as usize;
+        }
+        if distance > bound {
+            return None;
         }
 
         // Add the difference in length to the distance
-        distance += 2 * (max_len - min_len);
-
-        // Divide by 2 because each difference contributes 2 to the XOR count
-        distance / 2
+        Some(distance + max_len - min_len)
     }
 }
 
@@ -297,14 +805,310 @@ impl Distance<[u8]> for HammingDistanceSimd {
     }
 }
 
+impl BoundedDistance<[u8]> for HammingDistanceSimd {
+    #[inline(always)]
+    fn distance_within(&self, a: &[u8], b: &[u8], k: usize) -> Option<usize> {
+        self.hamming_within(a, b, k)
+    }
+}
+
+/*
+Blocked Myers (Hyyrö / Myers 1999 section 4) for patterns of any length
+The pattern is split into 64 bit blocks, every block runs the single word recurrence and
+    passes the horizontal delta of its bottom row (-1, 0, +1) to the next block as carry
+    The top row of the DP is j, so block 0 always gets a +1 carry in
+One pass covers both seq-lev ends:
+    Last row   score of the last block, tracked over every column
+    Last column   rebuilt at the end from the vertical deltas left in Pv/Mv, starting at m
+With a max distance k, Ukkonen's cutoff only computes blocks down to the last one that can
+    still hold a value <= k:
+    A block is switched on when its first cell can be <= k, diagonally from the bottom of the
+        block above in the previous column or vertically in this one, it starts from the +1
+        per row upper bound, which never undercuts the real values
+    Blocks whose bottom value is >= k + block length only hold values > k and are dropped
+    Once only block 0 is left and it is > k as well, every cell of the column is > k and so
+        is every later column, the scan stops with whatever the last row reached so far
+Values above k are never exact with the cutoff, the distance is capped at k + 1
+*/
+const BLOCK_BITS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceLevenshteinDistanceBlocked {
+    max_distance: Option<usize>,
+    iupac: Option<NPolicy>,
+}
+
+impl SequenceLevenshteinDistanceBlocked {
+    pub fn new() -> Self {
+        SequenceLevenshteinDistanceBlocked {
+            max_distance: None,
+            iupac: None,
+        }
+    }
+
+    pub fn with_max_distance(max_distance: usize) -> Self {
+        SequenceLevenshteinDistanceBlocked {
+            max_distance: Some(max_distance),
+            iupac: None,
+        }
+    }
+
+    pub fn with_iupac(mut self, n_policy: NPolicy) -> Self {
+        self.iupac = Some(n_policy);
+        self
+    }
+
+    pub fn max_distance(&self) -> Option<usize> {
+        self.max_distance
+    }
+
+    // t is the pattern (split into blocks), p is the text, k is the cutoff
+    fn blocked_distance(&self, t: &[u8], p: &[u8], max_distance: Option<usize>) -> usize {
+        let n = t.len();
+        let m = p.len();
+        // Same as the single word kernel, an empty side has an empty last row or column
+        if n == 0 || m == 0 {
+            return 0;
+        }
+
+        let blocks = n.div_ceil(BLOCK_BITS);
+        let last = blocks - 1;
+        let block_len = |b: usize| {
+            if b == last {
+                n - b * BLOCK_BITS
+            } else {
+                BLOCK_BITS
+            }
+        };
+        let mut peq = vec![[0u64; PEQ_SIZE]; blocks];
+        for (block, pattern) in peq.iter_mut().zip(t.chunks(BLOCK_BITS)) {
+            fill_peq(pattern, self.iupac, block);
+        }
+        let mut pv = vec![!0u64; blocks];
+        let mut mv = vec![0u64; blocks];
+        // Value of the bottom cell of every block in the current column
+        let mut score: Vec<isize> = (0..blocks)
+            .map(|b| (b * BLOCK_BITS + block_len(b)) as isize)
+            .collect();
+
+        // One column step of block b, returns the horizontal delta of its bottom row
+        let advance = |b: usize, eq: u64, hin: isize, pv: &mut [u64], mv: &mut [u64]| -> isize {
+            let hb = 1u64 << (block_len(b) - 1);
+            let (p, m) = (pv[b], mv[b]);
+            let xv = eq | m;
+            // A -1 carry in acts like a match on the first row of the block
+            let eq = if hin < 0 { eq | 1 } else { eq };
+            let xh = ((eq & p).wrapping_add(p) ^ p) | eq;
+            let mut ph = m | !(xh | p);
+            let mut mh = p & xh;
+            let hout = if ph & hb != 0 {
+                1
+            } else if mh & hb != 0 {
+                -1
+            } else {
+                0
+            };
+            ph <<= 1;
+            mh <<= 1;
+            if hin < 0 {
+                mh |= 1;
+            } else if hin > 0 {
+                ph |= 1;
+            }
+            pv[b] = mh | !(xv | ph);
+            mv[b] = ph & xv;
+            hout
+        };
+
+        let k = max_distance.map(|k| k as isize);
+        let mut y = match k {
+            Some(k) => ((k as usize).div_ceil(BLOCK_BITS).max(1) - 1).min(last),
+            None => last,
+        };
+        let mut min_last_row = n as isize;
+        // Set when a whole column is > k, the rest of the last row and the last column are too
+        let mut exhausted = false;
+        for &base in p {
+            let mut carry = 1;
+            for b in 0..=y {
+                carry = advance(b, peq[b][base as usize], carry, &mut pv, &mut mv);
+                score[b] += carry;
+            }
+
+            if let Some(k) = k {
+                // The top cell of the next block is reached diagonally from the previous
+                // column or vertically from this one
+                while y < last {
+                    let diagonal = score[y] - carry + (peq[y + 1][base as usize] & 1 == 0) as isize;
+                    if diagonal.min(score[y] + 1) > k {
+                        break;
+                    }
+                    y += 1;
+                    pv[y] = !0;
+                    mv[y] = 0;
+                    score[y] = score[y - 1] - carry + block_len(y) as isize;
+                    carry = advance(y, peq[y][base as usize], carry, &mut pv, &mut mv);
+                    score[y] += carry;
+                }
+                while y > 0 && score[y] >= k + block_len(y) as isize {
+                    y -= 1;
+                }
+                if y == 0 && score[0] >= k + block_len(0) as isize {
+                    exhausted = true;
+                    break;
+                }
+            }
+
+            if y == last {
+                min_last_row = min_last_row.min(score[last]);
+            }
+        }
+
+        // Walk the last column down through the active blocks
+        let mut value = m as isize;
+        let mut min_last_col = value;
+        for b in (0..=y).filter(|_| !exhausted) {
+            for bit in 0..block_len(b) {
+                value += ((pv[b] >> bit) & 1) as isize - ((mv[b] >> bit) & 1) as isize;
+                min_last_col = min_last_col.min(value);
+            }
+        }
+
+        let distance = min_last_row.min(min_last_col) as usize;
+        match max_distance {
+            Some(k) => distance.min(k + 1),
+            None => distance,
+        }
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Distance<T> for SequenceLevenshteinDistanceBlocked {
+    fn distance(&self, a: &T, b: &T) -> usize {
+        self.blocked_distance(a.as_ref(), b.as_ref(), self.max_distance)
+    }
+
+    fn find_distance(&self, t: &[u8], p: &[u8]) -> usize {
+        self.blocked_distance(t, p, self.max_distance)
+    }
+
+    // Capped distances only say "more than max distance", which bounds nothing
+    fn lower_bound(&self, d: usize, keys: RangeInclusive<usize>, spread: usize) -> usize {
+        match self.max_distance {
+            Some(_) => 0,
+            None => seq_lev_lower_bound(d, keys, spread, self.iupac),
+        }
+    }
+
+    fn length(&self, item: &T) -> usize {
+        item.as_ref().len()
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for SequenceLevenshteinDistanceBlocked {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        Some(self.blocked_distance(a.as_ref(), b.as_ref(), Some(k))).filter(|&d| d <= k)
+    }
+}
+
+/*
+Banded Wagner-Fischer shared by the bounded Wagner and Levenshtein distances
+Every cell (i, j) is at least |i - j|, so only the diagonal band |i - j| <= k can hold a
+    value <= k, the cells outside it are treated as k + 1
+Each row is built from the row above (+0/+1) and its own left cell (+1), the minimum of a
+    row never goes down, once a whole band row is > k every later cell is too
+With seq_lev the ends are free like SequenceLevenshteinDistanceWagner (minimum of the last row
+    and last column), otherwise the distance is the bottom right cell
+*/
+fn banded_distance<E, F>(s1: &[E], s2: &[E], k: usize, seq_lev: bool, matches: F) -> Option<usize>
+where
+    F: Fn(&E, &E) -> bool,
+{
+    let len1 = s1.len();
+    let len2 = s2.len();
+    // Every distance fits in the band once k covers the longer side
+    let k = k.min(len1.max(len2));
+    let outside = k + 1;
+    if !seq_lev && len1.abs_diff(len2) > k {
+        return None;
+    }
+
+    let mut previous_row = vec![outside; len2 + 1];
+    let mut current_row: Vec<usize> = (0..=len2)
+        .map(|j| if j <= k { j } else { outside })
+        .collect();
+    let mut min_last_col = current_row[len2];
+
+    for i in 1..=len1 {
+        std::mem::swap(&mut previous_row, &mut current_row);
+        let lo = i.saturating_sub(k).max(1);
+        let hi = (i + k).min(len2);
+        current_row[lo - 1] = if lo == 1 { i.min(outside) } else { outside };
+        let mut row_min = current_row[lo - 1];
+        for j in lo..=hi {
+            let value = if matches(&s1[i - 1], &s2[j - 1]) {
+                previous_row[j - 1]
+            } else {
+                previous_row[j - 1]
+                    .min(previous_row[j])
+                    .min(current_row[j - 1])
+                    + 1
+            };
+            current_row[j] = value.min(outside);
+            row_min = row_min.min(current_row[j]);
+        }
+        // The cell right of the band is read by the next row as its upper neighbor
+        if hi < len2 {
+            current_row[hi + 1] = outside;
+        }
+        if len2 <= hi {
+            min_last_col = min_last_col.min(current_row[len2]);
+        }
+
+        if row_min > k {
+            // Nothing below this row can come back under k, only the last column seen so far
+            return (seq_lev && min_last_col <= k).then_some(min_last_col);
+        }
+    }
+
+    let lo = len1.saturating_sub(k);
+    let hi = (len1 + k).min(len2);
+    let distance = if seq_lev {
+        let min_last_row = current_row[lo..=hi]
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or(outside);
+        min_last_row.min(min_last_col)
+    } else {
+        current_row[len2]
+    };
+    (distance <= k).then_some(distance)
+}
+
 // Wagner-Fischer algorithm
-// This is what you want if the sub-string you want to identify is large (>64 characters)
+// Kept as the reference implementation, SequenceLevenshteinDistanceBlocked is faster for long
+//     patterns
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SequenceLevenshteinDistanceWagner;
+pub struct SequenceLevenshteinDistanceWagner {
+    iupac: Option<NPolicy>,
+}
 
 impl SequenceLevenshteinDistanceWagner {
     pub fn new() -> Self {
-        SequenceLevenshteinDistanceWagner
+        SequenceLevenshteinDistanceWagner { iupac: None }
+    }
+
+    pub fn with_iupac(mut self, n_policy: NPolicy) -> Self {
+        self.iupac = Some(n_policy);
+        self
+    }
+
+    #[inline(always)]
+    fn bases_match(&self, a: u8, b: u8) -> bool {
+        match self.iupac {
+            None => a == b,
+            Some(n_policy) => iupac::bases_match(a, b, n_policy),
+        }
     }
 
     // Its not needless, using clippy suggestion WILL BREAK IT
@@ -318,6 +1122,8 @@ impl SequenceLevenshteinDistanceWagner {
         for j in 1..=len2 {
             current_row[j] = j;
         }
+        // The last column has to be tracked over every row, it is gone once the rows rotate
+        let mut min_last_col = current_row[len2];
 
         for i in 1..=len1 {
             std::mem::swap(&mut previous_row, &mut current_row);
@@ -325,7 +1131,7 @@ impl SequenceLevenshteinDistanceWagner {
             // We can't use enumerate in this case
             // b/c we need to mutate current_row inside the loop
             for j in 1..=len2 {
-                if s1[i - 1] == s2[j - 1] {
+                if self.bases_match(s1[i - 1], s2[j - 1]) {
                     current_row[j] = previous_row[j - 1];
                 } else {
                     current_row[j] = std::cmp::min(
@@ -334,11 +1140,11 @@ impl SequenceLevenshteinDistanceWagner {
                     );
                 }
             }
+            min_last_col = min_last_col.min(current_row[len2]);
         }
 
         // Find the minimum value in the last row and the last column
         let min_last_row = *current_row.iter().min().unwrap();
-        let min_last_col = previous_row[len2];
 
         std::cmp::min(min_last_row, min_last_col)
     }
@@ -354,6 +1160,22 @@ impl<T: AsRef<[u8]> + ?Sized> Distance<T> for SequenceLevenshteinDistanceWagner
     fn find_distance(&self, s1: &[u8], s2: &[u8]) -> usize {
         self.wagner_distance(s1, s2)
     }
+
+    fn lower_bound(&self, d: usize, keys: RangeInclusive<usize>, spread: usize) -> usize {
+        seq_lev_lower_bound(d, keys, spread, self.iupac)
+    }
+
+    fn length(&self, item: &T) -> usize {
+        item.as_ref().len()
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for SequenceLevenshteinDistanceWagner {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        banded_distance(a.as_ref(), b.as_ref(), k, true, |&x, &y| {
+            self.bases_match(x, y)
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -427,6 +1249,136 @@ impl<T: AsRef<str> + Clone> Distance<T> for LevenshteinDistance {
     }
 }
 
+impl<T: AsRef<str> + Clone> BoundedDistance<T> for LevenshteinDistance {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        let a: Vec<char> = a.as_ref().chars().collect();
+        let b: Vec<char> = b.as_ref().chars().collect();
+        banded_distance(&a, &b, k, false, |x, y| x == y)
+    }
+}
+
+/*
+Weighted Wagner-Fischer shared by the weighted seq-lev and Levenshtein distances
+s1 is a (rows) and s2 is b (columns): moving down skips a base of a (deletion), moving right a
+    base of b (insertion), the diagonal pays the substitution cost
+The first row and column are paid like in the unit cost versions, with seq_lev the end is free
+    (minimum of the last row and last column), otherwise it is the bottom right cell
+Costs are non-negative, so every cell is at least the minimum of the row above and the minimum
+    of a row never goes down, limit stops as soon as a whole row is above it
+*/
+fn weighted_distance(
+    costs: &EditCosts,
+    s1: &[u8],
+    s2: &[u8],
+    seq_lev: bool,
+    limit: Option<usize>,
+) -> Option<usize> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let insertion = costs.insertion();
+    let deletion = costs.deletion();
+    let len2 = s2.len();
+
+    let mut previous_row = vec![0; len2 + 1];
+    let mut current_row: Vec<usize> = (0..=len2).map(|j| j * insertion).collect();
+    let mut min_last_col = current_row[len2];
+
+    for (i, &base) in s1.iter().enumerate() {
+        std::mem::swap(&mut previous_row, &mut current_row);
+        current_row[0] = (i + 1) * deletion;
+        let mut row_min = current_row[0];
+        for (j, &other) in s2.iter().enumerate() {
+            let value = (previous_row[j] + costs.substitution(base, other))
+                .min(previous_row[j + 1] + deletion)
+                .min(current_row[j] + insertion);
+            current_row[j + 1] = value;
+            row_min = row_min.min(value);
+        }
+        min_last_col = min_last_col.min(current_row[len2]);
+
+        if row_min > limit {
+            return (seq_lev && min_last_col <= limit).then_some(min_last_col);
+        }
+    }
+
+    let distance = if seq_lev {
+        current_row.iter().copied().min().unwrap().min(min_last_col)
+    } else {
+        current_row[len2]
+    };
+    (distance <= limit).then_some(distance)
+}
+
+// Seq-lev with an EditCosts matrix, the free end overhang of SequenceLevenshteinDistanceWagner
+//     is kept, with EditCosts::unit() both give the same distance
+// Distances are in fixed point units, see EditCosts::to_f64
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedSequenceLevenshteinDistance {
+    costs: EditCosts,
+}
+
+impl WeightedSequenceLevenshteinDistance {
+    pub fn new(costs: EditCosts) -> Self {
+        WeightedSequenceLevenshteinDistance { costs }
+    }
+
+    pub fn costs(&self) -> &EditCosts {
+        &self.costs
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Distance<T> for WeightedSequenceLevenshteinDistance {
+    fn distance(&self, a: &T, b: &T) -> usize {
+        weighted_distance(&self.costs, a.as_ref(), b.as_ref(), true, None).unwrap()
+    }
+
+    fn find_distance(&self, t: &[u8], p: &[u8]) -> usize {
+        weighted_distance(&self.costs, t, p, true, None).unwrap()
+    }
+
+    // Free overhang and arbitrary costs, no bound holds
+    fn lower_bound(&self, _d: usize, _keys: RangeInclusive<usize>, _spread: usize) -> usize {
+        0
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for WeightedSequenceLevenshteinDistance {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        weighted_distance(&self.costs, a.as_ref(), b.as_ref(), true, Some(k))
+    }
+}
+
+// Levenshtein with an EditCosts matrix, compares bytes where LevenshteinDistance compares chars
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedLevenshteinDistance {
+    costs: EditCosts,
+}
+
+impl WeightedLevenshteinDistance {
+    pub fn new(costs: EditCosts) -> Self {
+        WeightedLevenshteinDistance { costs }
+    }
+
+    pub fn costs(&self) -> &EditCosts {
+        &self.costs
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> Distance<T> for WeightedLevenshteinDistance {
+    fn distance(&self, a: &T, b: &T) -> usize {
+        weighted_distance(&self.costs, a.as_ref(), b.as_ref(), false, None).unwrap()
+    }
+
+    fn find_distance(&self, a: &[u8], b: &[u8]) -> usize {
+        weighted_distance(&self.costs, a, b, false, None).unwrap()
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> BoundedDistance<T> for WeightedLevenshteinDistance {
+    fn distance_within(&self, a: &T, b: &T, k: usize) -> Option<usize> {
+        weighted_distance(&self.costs, a.as_ref(), b.as_ref(), false, Some(k))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // TODO: Re-add example from papers as unit tests
@@ -434,14 +1386,132 @@ mod tests {
 
     #[test]
     fn test_sequence_levenshtein_simd() {
-        let dist = SequenceLevenshteinDistanceSimd::new();
+        let dist = SequenceLevenshteinDistanceSimd::with_max_distance(1);
 
         // Test case
         let read = b"ACGTACGTGGGGGGG";
         let barcode = b"ACGTACGT";
-        let expected_matches = vec![(0, 7), (1, 8)];
-        let matches = dist.sequence_levenshtein_simd(read, barcode);
+        let expected_matches = vec![(0, 0), (1, 1)];
+        let matches = dist.matches(read, barcode);
         assert_eq!(matches, expected_matches);
+        assert_eq!(dist.window_distances(read, barcode).len(), 8);
+        assert_eq!(dist.best_window(read, barcode), Some((0, 0)));
+        assert_eq!(dist.best_window(b"TTTTTTTTTT", barcode), None);
+        assert_eq!(dist.distance(&b"TTTTTTTT"[..], &barcode[..]), 2);
+    }
+
+    #[test]
+    fn test_sequence_levenshtein_simd_matches_scalar() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(16);
+        let scalar = SequenceLevenshteinDistance::new();
+        let simd = SequenceLevenshteinDistanceSimd::new();
+        let random = |rng: &mut StdRng, len: usize| -> Vec<u8> {
+            (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect()
+        };
+        for _ in 0..300 {
+            let (barcode_len, read_len) = (rng.gen_range(1..=16), rng.gen_range(1..=40));
+            let barcode = random(&mut rng, barcode_len);
+            let read = random(&mut rng, read_len);
+            let distances = simd.window_distances(&read, &barcode);
+            let window_len = barcode.len().min(read.len());
+            assert_eq!(distances.len(), read.len() - window_len + 1);
+            for (start, &d) in distances.iter().enumerate() {
+                let window = &read[start..start + window_len];
+                assert_eq!(d, scalar.distance(window, &barcode[..]), "{:?}", window);
+            }
+            assert_eq!(
+                simd.distance(&read, &barcode),
+                *distances.iter().min().unwrap()
+            );
+
+            // Same length, the SIMD kernel is a drop-in for the scalar one
+            let other = random(&mut rng, barcode.len());
+            assert_eq!(
+                simd.distance(&other, &barcode),
+                scalar.distance(&other, &barcode)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sequence_levenshtein_simd_empty_inputs() {
+        let simd = SequenceLevenshteinDistanceSimd::new();
+        let scalar = SequenceLevenshteinDistance::new();
+        let blocked = SequenceLevenshteinDistanceBlocked::new();
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        for (read, barcode) in [(&b""[..], &b"ACGT"[..]), (b"ACG", b""), (b"", b"")] {
+            let expected = scalar.distance(read, barcode);
+            assert_eq!(blocked.distance(read, barcode), expected);
+            assert_eq!(wagner.distance(read, barcode), expected);
+            assert_eq!(simd.distance(read, barcode), expected);
+            assert_eq!(simd.best_window(read, barcode), Some((0, expected)));
+            // One empty window per read offset, the same as the scalar kernel on each
+            let windows = simd.window_distances(read, barcode);
+            assert_eq!(
+                windows.len(),
+                read.len() - barcode.len().min(read.len()) + 1
+            );
+            assert!(windows.iter().all(|&d| d == expected), "{:?}", windows);
+        }
+    }
+
+    #[test]
+    fn test_sequence_levenshtein_simd_lane_configurations() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(17);
+        let simd = SequenceLevenshteinDistanceSimd::new();
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        for barcode_len in [1, 5, 8, 9, 16, 17, 30, 32, 33, 64] {
+            let barcode: Vec<u8> = (0..barcode_len)
+                .map(|_| b"ACGT"[rng.gen_range(0..4)])
+                .collect();
+            // Mutated copy of the barcode embedded in random flanks
+            let mut read: Vec<u8> = (0..7).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            read.extend(
+                barcode
+                    .iter()
+                    .map(|&b| if rng.gen_bool(0.1) { b'A' } else { b }),
+            );
+            // Long enough for the 64 lane u8 dispatch on short barcodes
+            read.extend((0..80).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+
+            let expected: Vec<usize> = read
+                .windows(barcode_len)
+                .map(|w| wagner.distance(w, &barcode[..]))
+                .collect();
+            assert_eq!(simd.window_distances(&read, &barcode), expected);
+            // The bounded scan stops early but has to land on the same leftmost best window
+            let min = *expected.iter().min().unwrap();
+            let leftmost = expected.iter().position(|&d| d == min).unwrap();
+            for max_distance in [0, 1, 3] {
+                assert_eq!(
+                    SequenceLevenshteinDistanceSimd::with_max_distance(max_distance)
+                        .best_window(&read, &barcode),
+                    (min <= max_distance).then_some((leftmost, min))
+                );
+            }
+            assert_eq!(
+                simd.window_distances_with::<u64, 4>(&read, &barcode),
+                expected
+            );
+            if barcode_len <= 32 {
+                assert_eq!(
+                    simd.window_distances_with::<u32, 16>(&read, &barcode),
+                    expected
+                );
+            }
+            if barcode_len <= 8 {
+                assert_eq!(
+                    simd.window_distances_with::<u8, 64>(&read, &barcode),
+                    expected
+                );
+            }
+        }
     }
 
     #[test]
@@ -452,10 +1522,164 @@ mod tests {
         let window1 = b"ACGTACGT";
         let barcode = b"ACGTACGT";
         let expected_distance = 0;
-        let matches = dist.sequence_levenshtein(window1, 8, barcode, 8);
+        let matches = dist.sequence_levenshtein(window1, 8, barcode, 8, usize::MAX);
         assert_eq!(matches, expected_distance);
     }
 
+    #[test]
+    fn test_wagner_last_column() {
+        let dist = SequenceLevenshteinDistanceWagner::new();
+        // The barcode is a prefix of the read, the match ends in the last column at row 3
+        assert_eq!(dist.distance(&b"ACG"[..], &b"ACGTAC"[..]), 0);
+        assert_eq!(dist.distance(&b"ACGTAC"[..], &b"ACG"[..]), 0);
+        assert_eq!(dist.distance(&b"ACGT"[..], &b"TTTT"[..]), 3);
+    }
+
+    #[test]
+    fn test_sequence_levenshtein_word_sizes() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(18);
+        let myers = SequenceLevenshteinDistance::new();
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        for _ in 0..400 {
+            let len = rng.gen_range(1..=150);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            // A mutated copy keeps the distance small enough to exercise the carries
+            let mut b = Vec::with_capacity(len + 4);
+            for &base in &a {
+                match rng.gen_range(0..20) {
+                    0 => {}
+                    1 | 2 => b.push(b"ACGT"[rng.gen_range(0..4)]),
+                    _ => b.push(base),
+                }
+            }
+            b.extend((0..rng.gen_range(0..4)).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+            assert_eq!(
+                myers.distance(&a, &b),
+                wagner.distance(&a, &b),
+                "{} vs {} bases",
+                a.len(),
+                b.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_blocked_sequence_levenshtein() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(19);
+        let blocked = SequenceLevenshteinDistanceBlocked::new();
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        for _ in 0..300 {
+            let len = rng.gen_range(1..=300);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let mut b = Vec::with_capacity(len + 8);
+            for &base in &a {
+                match rng.gen_range(0..25) {
+                    0 => {}
+                    1 => b.extend([base, b"ACGT"[rng.gen_range(0..4)]]),
+                    2 | 3 => b.push(b"ACGT"[rng.gen_range(0..4)]),
+                    _ => b.push(base),
+                }
+            }
+            // Random amounts of overhang on either side of the pair
+            let cut = rng.gen_range(0..=b.len() / 4);
+            b.truncate(b.len() - cut);
+            b.extend((0..rng.gen_range(0..8)).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+
+            let expected = wagner.distance(&a, &b);
+            assert_eq!(blocked.distance(&a, &b), expected);
+            assert_eq!(blocked.distance(&b, &a), expected);
+            for k in [0, 1, 3, 10, 40, 70] {
+                let cutoff = SequenceLevenshteinDistanceBlocked::with_max_distance(k);
+                assert_eq!(cutoff.distance(&a, &b), expected.min(k + 1), "k = {}", k);
+            }
+        }
+
+        // Patterns past a single u128 word go through the blocked kernel
+        let long = b"ACGTTGCA".repeat(20);
+        let mut shifted = long[3..].to_vec();
+        shifted.extend(b"GGG");
+        assert_eq!(
+            SequenceLevenshteinDistance::new().distance(&long, &shifted),
+            wagner.distance(&long, &shifted)
+        );
+    }
+
+    #[test]
+    fn test_distance_within() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(20);
+        let myers = SequenceLevenshteinDistance::new();
+        let blocked = SequenceLevenshteinDistanceBlocked::with_max_distance(2);
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        let simd = SequenceLevenshteinDistanceSimd::with_max_distance(1);
+        let hamming = HammingDistance::new();
+        let hamming_simd = HammingDistanceSimd::new();
+        let levenshtein = LevenshteinDistance::new();
+        // Expected is distance <= k, the metric's own max distance plays no part
+        let within = |d: usize, k: usize| (d <= k).then_some(d);
+
+        for round in 0..120 {
+            let len = rng.gen_range(0..=160);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let mut b = Vec::with_capacity(len + 8);
+            for &base in &a {
+                // Every third pair is unrelated, far past any k
+                match rng.gen_range(0..if round % 3 == 0 { 1 } else { 20 }) {
+                    0 => b.push(b"ACGT"[rng.gen_range(0..4)]),
+                    1 => {}
+                    2 => b.extend([base, b"ACGT"[rng.gen_range(0..4)]]),
+                    _ => b.push(base),
+                }
+            }
+            b.extend((0..rng.gen_range(0..4)).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+            let a_str = String::from_utf8(a.clone()).unwrap();
+            let b_str = String::from_utf8(b.clone()).unwrap();
+
+            let seq_lev = wagner.distance(&a, &b);
+            let ham = hamming_simd.distance(a.as_slice(), b.as_slice());
+            let lev = levenshtein.distance(&a_str, &b_str);
+            for k in [0, 1, 2, 5, 17, 70, 300] {
+                assert_eq!(myers.distance_within(&a, &b, k), within(seq_lev, k));
+                assert_eq!(blocked.distance_within(&a, &b, k), within(seq_lev, k));
+                assert_eq!(wagner.distance_within(&a, &b, k), within(seq_lev, k));
+                assert_eq!(wagner.distance_within(&b, &a, k), within(seq_lev, k));
+                assert_eq!(
+                    hamming_simd.distance_within(a.as_slice(), b.as_slice(), k),
+                    within(ham, k)
+                );
+                assert_eq!(
+                    hamming.distance_within(&a_str, &b_str, k),
+                    within(hamming.distance(&a_str, &b_str), k)
+                );
+                assert_eq!(
+                    levenshtein.distance_within(&a_str, &b_str, k),
+                    within(lev, k)
+                );
+                // The SIMD kernel wants a barcode, cut the pair down to a read and a barcode
+                if !a.is_empty() && b.len() >= 4 {
+                    let barcode = &b[..b.len().min(24)];
+                    let best = simd
+                        .window_distances(&a, barcode)
+                        .into_iter()
+                        .min()
+                        .unwrap();
+                    assert_eq!(
+                        simd.distance_within(a.as_slice(), barcode, k),
+                        within(best, k)
+                    );
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_hamming_distance_simd() {
         let dist = HammingDistanceSimd::new();
@@ -485,4 +1709,144 @@ mod tests {
         let b = b"ACGTATGT".repeat(20);
         assert_eq!(dist.distance(a.as_slice(), b.as_slice()), 20);
     }
+
+    #[test]
+    fn test_iupac_distances() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let hamming = HammingDistanceSimd::new();
+        assert_eq!(hamming.distance(b"ACGTRY", b"ACGTGC"), 0);
+        assert_eq!(hamming.distance(b"ACGTRY", b"ACGTCC"), 1);
+        assert_eq!(hamming.distance(b"ACNT", b"ACGT"), 1);
+        assert_eq!(hamming.distance(b"ACNT", b"ACNT"), 1);
+        let hamming = HammingDistanceSimd::new().with_n_policy(NPolicy::Match);
+        assert_eq!(hamming.distance(b"ACNT", b"ACGT"), 0);
+        let long_a = b"ACGTNRYS".repeat(20);
+        let long_b = b"ACGTAAAA".repeat(20);
+        assert_eq!(hamming.distance(long_a.as_slice(), long_b.as_slice()), 40);
+
+        // Every kernel has to agree with Wagner on the same IUPAC rules
+        let mut rng = StdRng::seed_from_u64(24);
+        for n_policy in [NPolicy::Mismatch, NPolicy::Match] {
+            let wagner = SequenceLevenshteinDistanceWagner::new().with_iupac(n_policy);
+            let myers = SequenceLevenshteinDistance::new().with_iupac(n_policy);
+            let blocked = SequenceLevenshteinDistanceBlocked::new().with_iupac(n_policy);
+            for _ in 0..150 {
+                let len = rng.gen_range(1..=200);
+                let a: Vec<u8> = (0..len)
+                    .map(|_| b"ACGTACGTACGTRYNn"[rng.gen_range(0..16)])
+                    .collect();
+                let b: Vec<u8> = (0..rng.gen_range(1..=200))
+                    .map(|_| b"ACGTACGTACGTSWNu"[rng.gen_range(0..16)])
+                    .collect();
+                let expected = wagner.distance(&a, &b);
+                assert_eq!(myers.distance(&a, &b), expected);
+                assert_eq!(blocked.distance(&a, &b), expected);
+            }
+
+            // Windowed kernel, every window against Wagner on the same window
+            let simd = SequenceLevenshteinDistanceSimd::new().with_iupac(n_policy);
+            for _ in 0..100 {
+                let barcode: Vec<u8> = (0..rng.gen_range(1..=40))
+                    .map(|_| b"ACGTACGTACGTRYNn"[rng.gen_range(0..16)])
+                    .collect();
+                let read: Vec<u8> = (0..rng.gen_range(1..=80))
+                    .map(|_| b"ACGTACGTACGTSWNu"[rng.gen_range(0..16)])
+                    .collect();
+                let window_len = barcode.len().min(read.len());
+                for (start, d) in simd
+                    .window_distances(&read, &barcode)
+                    .into_iter()
+                    .enumerate()
+                {
+                    let window = &read[start..start + window_len];
+                    assert_eq!(d, wagner.distance(window, &barcode[..]), "{:?}", window);
+                }
+            }
+        }
+
+        // Without a policy an ambiguity code is just another byte
+        let exact = SequenceLevenshteinDistanceWagner::new();
+        let iupac = SequenceLevenshteinDistanceWagner::new().with_iupac(NPolicy::Match);
+        assert_eq!(exact.distance(b"ACGRT", b"ACGAT"), 1);
+        assert_eq!(iupac.distance(b"ACGRT", b"ACGAT"), 0);
+        assert_eq!(iupac.distance(b"ACGNT", b"ACGCT"), 0);
+    }
+
+    #[test]
+    fn test_weighted_distances() {
+        use crate::algos::scoring::SubstitutionMatrix;
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        // Unit costs are the plain distances
+        let mut rng = StdRng::seed_from_u64(25);
+        let wagner = SequenceLevenshteinDistanceWagner::new();
+        let weighted_seq_lev = WeightedSequenceLevenshteinDistance::new(EditCosts::unit());
+        let weighted_lev = WeightedLevenshteinDistance::new(EditCosts::unit());
+        for _ in 0..200 {
+            let a: Vec<u8> = (0..rng.gen_range(0..=40))
+                .map(|_| b"ACGT"[rng.gen_range(0..4)])
+                .collect();
+            let b: Vec<u8> = (0..rng.gen_range(0..=40))
+                .map(|_| b"ACGT"[rng.gen_range(0..4)])
+                .collect();
+            let seq_lev = wagner.distance(&a, &b);
+            assert_eq!(weighted_seq_lev.distance(&a, &b), seq_lev);
+            let lev = LevenshteinDistance::new().distance(
+                &String::from_utf8(a.clone()).unwrap(),
+                &String::from_utf8(b.clone()).unwrap(),
+            );
+            assert_eq!(weighted_lev.distance(&a, &b), lev);
+
+            for k in 0..6 {
+                assert_eq!(
+                    weighted_seq_lev.distance_within(&a, &b, k),
+                    Some(seq_lev).filter(|&d| d <= k)
+                );
+                assert_eq!(
+                    weighted_lev.distance_within(&a, &b, k),
+                    Some(lev).filter(|&d| d <= k)
+                );
+            }
+        }
+
+        // A transition is cheaper than a transversion
+        let costs = EditCosts::transition_transversion(1, 3, 2);
+        let seq_lev = WeightedSequenceLevenshteinDistance::new(costs.clone());
+        let lev = WeightedLevenshteinDistance::new(costs);
+        assert_eq!(lev.distance(&b"ACGTACGT"[..], &b"ACGTGCGT"[..]), 1);
+        assert_eq!(lev.distance(&b"ACGTACGT"[..], &b"ACGTCCGT"[..]), 3);
+        assert_eq!(lev.distance(&b"ACGT"[..], &b"ACGTT"[..]), 2);
+        // The extra base runs off the end, seq-lev doesn't pay for it
+        assert_eq!(seq_lev.distance(&b"ACGT"[..], &b"ACGTT"[..]), 0);
+        // A base missing from the read costs one deletion, the read's last base is overhang
+        assert_eq!(seq_lev.distance(&b"ACGTAC"[..], &b"ACTACG"[..]), 2);
+
+        // Separate insertion and deletion costs
+        let costs = EditCosts::new(SubstitutionMatrix::match_mismatch(0, 10), 1, 5);
+        let lev = WeightedLevenshteinDistance::new(costs);
+        assert_eq!(lev.distance(&b"ACGT"[..], &b"ACGGT"[..]), 1);
+        assert_eq!(lev.distance(&b"ACGGT"[..], &b"ACGT"[..]), 5);
+
+        // Fixed point costs, 0.5 per transition on a scale of 100
+        let costs = EditCosts::fixed_point(
+            b"ACGT",
+            &[
+                vec![0.0, 1.0, 0.5, 1.0],
+                vec![1.0, 0.0, 1.0, 0.5],
+                vec![0.5, 1.0, 0.0, 1.0],
+                vec![1.0, 0.5, 1.0, 0.0],
+            ],
+            1.0,
+            1.25,
+            1.25,
+            100,
+        );
+        let lev = WeightedLevenshteinDistance::new(costs);
+        let distance = lev.distance(&b"AACC"[..], &b"GATCA"[..]);
+        assert_eq!(distance, 225);
+        assert_eq!(lev.costs().to_f64(distance), 2.25);
+    }
 }
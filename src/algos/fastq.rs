@@ -0,0 +1,250 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/*
+Minimal FASTQ reader/writer
+Records are the classic 4 line layout (no multi-line sequences)
+Gzip input is detected from the magic bytes rather than the file extension, and uses the
+    multi-member decoder so bgzip and concatenated .gz files read as one stream
+*/
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FastqRecord {
+    // Header line without the leading '@'
+    pub id: Vec<u8>,
+    pub seq: Vec<u8>,
+    pub qual: Vec<u8>,
+}
+
+impl FastqRecord {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(b"@")?;
+        writer.write_all(&self.id)?;
+        writer.write_all(b"\n")?;
+        writer.write_all(&self.seq)?;
+        writer.write_all(b"\n+\n")?;
+        writer.write_all(&self.qual)?;
+        writer.write_all(b"\n")
+    }
+}
+
+// Index sequences from an Illumina style header, "<read id> 1:N:0:ACGTACGT+TTGGCCAA" -> (i7, i5)
+pub fn header_indices(id: &[u8]) -> Option<(&[u8], &[u8])> {
+    let comment = id.split(|b| b.is_ascii_whitespace()).nth(1)?;
+    let indices = comment.rsplit(|&b| b == b':').next()?;
+    let plus = indices.iter().position(|&b| b == b'+')?;
+    Some((&indices[..plus], &indices[plus + 1..]))
+}
+
+pub struct FastqReader<R: BufRead> {
+    reader: R,
+    line: Vec<u8>,
+    line_number: usize,
+}
+
+impl<R: BufRead> FastqReader<R> {
+    pub fn new(reader: R) -> Self {
+        FastqReader {
+            reader,
+            line: Vec::new(),
+            line_number: 0,
+        }
+    }
+
+    // Reads one line into self.line without the line ending, false on EOF
+    fn next_line(&mut self) -> io::Result<bool> {
+        self.line.clear();
+        if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+            return Ok(false);
+        }
+        self.line_number += 1;
+        while matches!(self.line.last(), Some(b'\n') | Some(b'\r')) {
+            self.line.pop();
+        }
+        Ok(true)
+    }
+
+    fn invalid(&self, message: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("FASTQ line {}: {}", self.line_number, message),
+        )
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<FastqRecord>> {
+        // Skip blank lines between records (trailing newline at EOF)
+        loop {
+            if !self.next_line()? {
+                return Ok(None);
+            }
+            if !self.line.is_empty() {
+                break;
+            }
+        }
+        if self.line[0] != b'@' {
+            return Err(self.invalid("expected '@' header"));
+        }
+        let id = self.line[1..].to_vec();
+
+        if !self.next_line()? {
+            return Err(self.invalid("truncated record, missing sequence"));
+        }
+        let seq = self.line.clone();
+
+        if !self.next_line()? || self.line.first() != Some(&b'+') {
+            return Err(self.invalid("expected '+' separator"));
+        }
+
+        if !self.next_line()? {
+            return Err(self.invalid("truncated record, missing qualities"));
+        }
+        let qual = self.line.clone();
+        if qual.len() != seq.len() {
+            return Err(self.invalid("sequence and quality lengths differ"));
+        }
+
+        Ok(Some(FastqRecord { id, seq, qual }))
+    }
+}
+
+impl<R: BufRead> Iterator for FastqReader<R> {
+    type Item = io::Result<FastqRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+// Wraps any reader, transparently decompressing gzip
+pub fn fastq_reader<R: Read + 'static>(reader: R) -> io::Result<FastqReader<Box<dyn BufRead>>> {
+    let mut buffered = BufReader::new(reader);
+    let is_gzip = buffered.fill_buf()?.starts_with(&GZIP_MAGIC);
+    let inner: Box<dyn BufRead> = if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(buffered)))
+    } else {
+        Box::new(buffered)
+    };
+    Ok(FastqReader::new(inner))
+}
+
+pub fn open_fastq<P: AsRef<Path>>(path: P) -> io::Result<FastqReader<Box<dyn BufRead>>> {
+    fastq_reader(File::open(path)?)
+}
+
+pub enum FastqWriter<W: Write> {
+    Plain(BufWriter<W>),
+    Gzip(GzEncoder<BufWriter<W>>),
+}
+
+impl<W: Write> FastqWriter<W> {
+    pub fn new(writer: W, gzip: bool) -> Self {
+        if gzip {
+            FastqWriter::Gzip(GzEncoder::new(
+                BufWriter::new(writer),
+                Compression::default(),
+            ))
+        } else {
+            FastqWriter::Plain(BufWriter::new(writer))
+        }
+    }
+
+    pub fn write_record(&mut self, record: &FastqRecord) -> io::Result<()> {
+        match self {
+            FastqWriter::Plain(writer) => record.write_to(writer),
+            FastqWriter::Gzip(writer) => record.write_to(writer),
+        }
+    }
+
+    // Flushes buffers and writes the gzip trailer, dropping without finish can lose data
+    pub fn finish(self) -> io::Result<W> {
+        let buffered = match self {
+            FastqWriter::Plain(writer) => writer,
+            FastqWriter::Gzip(writer) => writer.finish()?,
+        };
+        buffered.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+pub fn create_fastq<P: AsRef<Path>>(path: P, gzip: bool) -> io::Result<FastqWriter<File>> {
+    Ok(FastqWriter::new(File::create(path)?, gzip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn records() -> Vec<FastqRecord> {
+        vec![
+            FastqRecord {
+                id: b"read1 1:N:0".to_vec(),
+                seq: b"ACGTACGT".to_vec(),
+                qual: b"IIIIIIII".to_vec(),
+            },
+            FastqRecord {
+                id: b"read2".to_vec(),
+                seq: b"TTGCA".to_vec(),
+                qual: b"#####".to_vec(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip_plain_and_gzip() {
+        for gzip in [false, true] {
+            let mut writer = FastqWriter::new(Vec::new(), gzip);
+            for record in records() {
+                writer.write_record(&record).unwrap();
+            }
+            let bytes = writer.finish().unwrap();
+            assert_eq!(bytes.starts_with(&GZIP_MAGIC), gzip);
+
+            let parsed: Vec<FastqRecord> = fastq_reader(Cursor::new(bytes))
+                .unwrap()
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(parsed, records());
+        }
+    }
+
+    #[test]
+    fn test_malformed_records() {
+        let crlf = b"@r1\r\nACGT\r\n+\r\nIIII\r\n\n".to_vec();
+        let parsed: Vec<FastqRecord> = fastq_reader(Cursor::new(crlf))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(parsed[0].seq, b"ACGT");
+
+        let bad_header = b"r1\nACGT\n+\nIIII\n".to_vec();
+        assert!(fastq_reader(Cursor::new(bad_header))
+            .unwrap()
+            .next()
+            .unwrap()
+            .is_err());
+
+        let bad_qual = b"@r1\nACGT\n+\nIII\n".to_vec();
+        assert!(fastq_reader(Cursor::new(bad_qual))
+            .unwrap()
+            .next()
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_header_indices() {
+        let id = b"M00123:7:000000000-A1B2C:1:1101:15589:1333 1:N:0:ACGTACGT+TTGGCCAA";
+        assert_eq!(
+            header_indices(id),
+            Some((&b"ACGTACGT"[..], &b"TTGGCCAA"[..]))
+        );
+        assert_eq!(header_indices(b"read1 1:N:0:ACGTACGT"), None);
+        assert_eq!(header_indices(b"read1"), None);
+    }
+}
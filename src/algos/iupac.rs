@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+/*
+IUPAC nucleotide codes as base sets
+Every code is a 4 bit set of the bases it stands for (A = 0001, C = 0010, G = 0100, T = 1000),
+    R = A|G, Y = C|T, ..., N = ACGT, lower case and U (as T) are accepted
+Two positions match when their sets intersect, so A matches R and R matches S (both can be G)
+N is its own switch: with NPolicy::Mismatch N is the empty set and never matches anything,
+    not even another N, with NPolicy::Match it is the full set and matches everything
+Anything that is not an IUPAC code is the empty set
+The Myers kernels index their peq by byte, an IUPAC peq sets bit i of every code whose set
+    intersects pattern base i, see IUPAC_CODES
+*/
+
+pub const BASE_A: u8 = 0b0001;
+pub const BASE_C: u8 = 0b0010;
+pub const BASE_G: u8 = 0b0100;
+pub const BASE_T: u8 = 0b1000;
+pub const BASE_ANY: u8 = 0b1111;
+
+// Every byte with a non-empty base set (under NPolicy::Match)
+pub const IUPAC_CODES: &[u8] = b"ACGTURYSWKMBDHVNacgturyswkmbdhvn";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NPolicy {
+    // N is a mismatch against every base, including N
+    Mismatch,
+    // N matches every base
+    Match,
+}
+
+const fn build_sets() -> [u8; 256] {
+    let mut sets = [0u8; 256];
+    let codes: [(u8, u8); 16] = [
+        (b'A', BASE_A),
+        (b'C', BASE_C),
+        (b'G', BASE_G),
+        (b'T', BASE_T),
+        (b'U', BASE_T),
+        (b'R', BASE_A | BASE_G),
+        (b'Y', BASE_C | BASE_T),
+        (b'S', BASE_C | BASE_G),
+        (b'W', BASE_A | BASE_T),
+        (b'K', BASE_G | BASE_T),
+        (b'M', BASE_A | BASE_C),
+        (b'B', BASE_C | BASE_G | BASE_T),
+        (b'D', BASE_A | BASE_G | BASE_T),
+        (b'H', BASE_A | BASE_C | BASE_T),
+        (b'V', BASE_A | BASE_C | BASE_G),
+        (b'N', BASE_ANY),
+    ];
+    let mut i = 0;
+    while i < codes.len() {
+        let (code, set) = codes[i];
+        sets[code as usize] = set;
+        sets[code.to_ascii_lowercase() as usize] = set;
+        i += 1;
+    }
+    sets
+}
+
+const BASE_SETS: [u8; 256] = build_sets();
+
+#[inline(always)]
+pub fn base_set(base: u8, n_policy: NPolicy) -> u8 {
+    match (base, n_policy) {
+        (b'N' | b'n', NPolicy::Mismatch) => 0,
+        _ => BASE_SETS[base as usize],
+    }
+}
+
+#[inline(always)]
+pub fn bases_match(a: u8, b: u8, n_policy: NPolicy) -> bool {
+    base_set(a, n_policy) & base_set(b, n_policy) != 0
+}
+
+pub fn encode_base_sets(sequence: &[u8], n_policy: NPolicy) -> Vec<u8> {
+    sequence.iter().map(|&b| base_set(b, n_policy)).collect()
+}
+
+// Plain A, C, G or T (either case, no ambiguity)
+#[inline(always)]
+pub fn is_unambiguous(base: u8) -> bool {
+    matches!(base, b'A' | b'C' | b'G' | b'T' | b'a' | b'c' | b'g' | b't')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base_sets_intersect() {
+        assert!(bases_match(b'A', b'R', NPolicy::Mismatch));
+        assert!(bases_match(b'R', b'S', NPolicy::Mismatch));
+        assert!(!bases_match(b'R', b'Y', NPolicy::Mismatch));
+        assert!(bases_match(b'T', b'u', NPolicy::Mismatch));
+        assert!(!bases_match(b'A', b'B', NPolicy::Mismatch));
+        assert!(bases_match(b'a', b'A', NPolicy::Mismatch));
+
+        assert!(!bases_match(b'N', b'A', NPolicy::Mismatch));
+        assert!(!bases_match(b'N', b'N', NPolicy::Mismatch));
+        assert!(bases_match(b'N', b'A', NPolicy::Match));
+        assert!(bases_match(b'N', b'N', NPolicy::Match));
+
+        // Non IUPAC bytes never match
+        assert!(!bases_match(b'.', b'.', NPolicy::Match));
+        assert!(!bases_match(b'X', b'N', NPolicy::Match));
+
+        for &code in IUPAC_CODES {
+            assert_ne!(base_set(code, NPolicy::Match), 0);
+        }
+    }
+}
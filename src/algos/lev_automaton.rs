@@ -0,0 +1,394 @@
+use fxhash::FxHashMap;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+/*
+Levenshtein automaton (Schulz & Mihov, parametric form) walked against a trie of barcodes
+The automaton for a pattern p and bound k reads an input string one character at a time
+    and its state is the band of the Wagner-Fischer column that can still be <= k:
+    after j input chars, D[i] = lev(p[..i], input[..j]) for i in j-k..=j+k, capped at k+1
+The band update only depends on the band itself and on which of the 2k+1 pattern characters
+    under the band equal the input char (the characteristic vector), never on the pattern,
+    so the transition table is built once per k and shared by every automaton ("parametric")
+The only pattern specific information is how many band positions are still inside the
+    pattern, this is passed alongside the characteristic vector
+For k = 3 the table has a few hundred states, stepping is one table lookup per character
+
+Walking a trie of the whitelist with the automaton of a read visits every barcode prefix once
+    and drops a whole subtree as soon as the band is dead (every value > k)
+
+Sequence-Levenshtein mode matches SequenceLevenshteinDistance: the distance is the minimum of the
+    last row (read fully consumed, barcode overhang is free) and the last column
+    (barcode fully consumed, read overhang is free)
+*/
+
+pub const MAX_AUTOMATON_DISTANCE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutomatonMode {
+    Levenshtein,
+    SequenceLevenshtein,
+}
+
+// Universal (pattern independent) transition table for one k
+struct ParametricTable {
+    k: usize,
+    band: usize,
+    // band values per state, capped at k + 1
+    states: Vec<Vec<u8>>,
+    // transitions[state * inputs + valid * 2^band + chi]
+    transitions: Vec<u32>,
+}
+
+impl ParametricTable {
+    fn inputs(&self) -> usize {
+        (self.band + 1) << self.band
+    }
+
+    fn build(k: usize) -> Self {
+        let band = 2 * k + 1;
+        let inf = (k + 1) as u8;
+        let inputs = (band + 1) << band;
+
+        // Before reading anything D[i] = i for i >= 0, the band starts at i = -k
+        let initial: Vec<u8> = (0..band)
+            .map(|t| if t < k { inf } else { ((t - k) as u8).min(inf) })
+            .collect();
+
+        let mut ids: FxHashMap<Vec<u8>, u32> = FxHashMap::default();
+        let mut states = vec![initial.clone()];
+        ids.insert(initial, 0);
+        let mut transitions = Vec::new();
+
+        let mut current = 0;
+        while current < states.len() {
+            for input in 0..inputs {
+                let valid = input >> band;
+                let chi = input & ((1 << band) - 1);
+                let next = Self::step(&states[current], chi, valid, inf);
+                let id = match ids.get(&next) {
+                    Some(&id) => id,
+                    None => {
+                        let id = states.len() as u32;
+                        ids.insert(next.clone(), id);
+                        states.push(next);
+                        id
+                    }
+                };
+                transitions.push(id);
+            }
+            current += 1;
+        }
+
+        ParametricTable {
+            k,
+            band,
+            states,
+            transitions,
+        }
+    }
+
+    // new[t] = min(old[t] + mismatch, old[t + 1] + 1, new[t - 1] + 1)
+    //     old[t] is the diagonal, old[t + 1] the same pattern position one input char back
+    fn step(old: &[u8], chi: usize, valid: usize, inf: u8) -> Vec<u8> {
+        let band = old.len();
+        let mut new = vec![inf; band];
+        for t in 0..valid.min(band) {
+            let mismatch = u8::from(chi & (1 << t) == 0);
+            let mut value = old[t].saturating_add(mismatch);
+            if t + 1 < band {
+                value = value.min(old[t + 1].saturating_add(1));
+            }
+            if t > 0 {
+                value = value.min(new[t - 1].saturating_add(1));
+            }
+            new[t] = value.min(inf);
+        }
+        new
+    }
+
+    fn get(k: usize) -> &'static ParametricTable {
+        static TABLES: [OnceCell<ParametricTable>; MAX_AUTOMATON_DISTANCE] =
+            [OnceCell::new(), OnceCell::new(), OnceCell::new()];
+        TABLES[k - 1].get_or_init(|| ParametricTable::build(k))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutomatonState {
+    state: u32,
+    // Input characters consumed so far
+    offset: usize,
+    // Seq-lev only, min of D[n] over every input prefix so far (k + 1 if none are <= k)
+    best_last_row: usize,
+}
+
+pub struct LevenshteinAutomaton {
+    pattern: Vec<u8>,
+    k: usize,
+    mode: AutomatonMode,
+    table: &'static ParametricTable,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(pattern: &[u8], k: usize, mode: AutomatonMode) -> Self {
+        assert!(
+            (1..=MAX_AUTOMATON_DISTANCE).contains(&k),
+            "Levenshtein automaton supports k = 1..=3"
+        );
+        LevenshteinAutomaton {
+            pattern: pattern.to_vec(),
+            k,
+            mode,
+            table: ParametricTable::get(k),
+        }
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.k
+    }
+
+    pub fn mode(&self) -> AutomatonMode {
+        self.mode
+    }
+
+    pub fn start(&self) -> AutomatonState {
+        let state = AutomatonState {
+            state: 0,
+            offset: 0,
+            best_last_row: self.k + 1,
+        };
+        self.track_last_row(state)
+    }
+
+    pub fn step(&self, current: &AutomatonState, c: u8) -> AutomatonState {
+        let k = self.k;
+        let n = self.pattern.len();
+        let j = current.offset;
+
+        // Pattern char under band position t is p[j - k + t]
+        let mut chi = 0;
+        for t in 0..self.table.band {
+            let i = (j + t).wrapping_sub(k);
+            if i < n && self.pattern[i] == c {
+                chi |= 1 << t;
+            }
+        }
+        // Band position t covers D[j + 1 - k + t], which exists while j + 1 - k + t <= n
+        let valid = (n + k).saturating_sub(j).min(self.table.band);
+
+        let input = (valid << self.table.band) | chi;
+        let next = AutomatonState {
+            state: self.table.transitions[current.state as usize * self.table.inputs() + input],
+            offset: j + 1,
+            best_last_row: current.best_last_row,
+        };
+        self.track_last_row(next)
+    }
+
+    fn track_last_row(&self, mut current: AutomatonState) -> AutomatonState {
+        if self.mode == AutomatonMode::SequenceLevenshtein {
+            if let Some(d) = self.last_row(&current) {
+                current.best_last_row = current.best_last_row.min(d);
+            }
+        }
+        current
+    }
+
+    // D[n] for the input consumed so far, if it is <= k
+    fn last_row(&self, current: &AutomatonState) -> Option<usize> {
+        let t = (self.pattern.len() + self.k).checked_sub(current.offset)?;
+        let values = &self.table.states[current.state as usize];
+        values
+            .get(t)
+            .map(|&d| d as usize)
+            .filter(|&d| d <= self.table.k)
+    }
+
+    // Distance between the pattern and the input consumed so far, if it is <= k
+    pub fn distance(&self, current: &AutomatonState) -> Option<usize> {
+        match self.mode {
+            AutomatonMode::Levenshtein => self.last_row(current),
+            AutomatonMode::SequenceLevenshtein => {
+                let last_col = *self.table.states[current.state as usize].iter().min()? as usize;
+                let d = last_col.min(current.best_last_row);
+                (d <= self.k).then_some(d)
+            }
+        }
+    }
+
+    // False once no extension of the input can come back within k
+    pub fn can_match(&self, current: &AutomatonState) -> bool {
+        let alive = self.table.states[current.state as usize]
+            .iter()
+            .any(|&d| d as usize <= self.k);
+        alive || current.best_last_row <= self.k
+    }
+
+    pub fn accepts(&self, input: &[u8]) -> Option<usize> {
+        let mut current = self.start();
+        for &c in input {
+            current = self.step(&current, c);
+        }
+        self.distance(&current)
+    }
+}
+
+struct TrieNode {
+    children: Vec<(u8, u32)>,
+    // Whitelist ids ending at this node (duplicates share a node)
+    terminal: Vec<u32>,
+}
+
+pub struct BarcodeTrie {
+    nodes: Vec<TrieNode>,
+    len: usize,
+}
+
+impl BarcodeTrie {
+    pub fn build(whitelist: &[Vec<u8>]) -> Self {
+        let mut nodes = vec![TrieNode {
+            children: Vec::new(),
+            terminal: Vec::new(),
+        }];
+        for (id, barcode) in whitelist.iter().enumerate() {
+            let mut current = 0;
+            for &c in barcode.iter() {
+                current = match nodes[current].children.iter().find(|&&(b, _)| b == c) {
+                    Some(&(_, child)) => child as usize,
+                    None => {
+                        let child = nodes.len();
+                        nodes.push(TrieNode {
+                            children: Vec::new(),
+                            terminal: Vec::new(),
+                        });
+                        nodes[current].children.push((c, child as u32));
+                        child
+                    }
+                };
+            }
+            nodes[current].terminal.push(id as u32);
+        }
+
+        BarcodeTrie {
+            nodes,
+            len: whitelist.len(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Every whitelist entry accepted by the automaton as (id, distance), sorted by distance then id
+    pub fn search(&self, automaton: &LevenshteinAutomaton) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        let mut stack = vec![(0usize, automaton.start())];
+        while let Some((node, state)) = stack.pop() {
+            if !self.nodes[node].terminal.is_empty() {
+                if let Some(d) = automaton.distance(&state) {
+                    found.extend(self.nodes[node].terminal.iter().map(|&id| (id as usize, d)));
+                }
+            }
+            for &(c, child) in self.nodes[node].children.iter() {
+                let next = automaton.step(&state, c);
+                if automaton.can_match(&next) {
+                    stack.push((child as usize, next));
+                }
+            }
+        }
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        found
+    }
+
+    // Every whitelist entry within k of the read
+    pub fn find_within(&self, read: &[u8], k: usize, mode: AutomatonMode) -> Vec<(usize, usize)> {
+        self.search(&LevenshteinAutomaton::new(read, k, mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{Distance, LevenshteinDistance, SequenceLevenshteinDistance};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_dna(rng: &mut StdRng, length: usize) -> Vec<u8> {
+        (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect()
+    }
+
+    #[test]
+    fn test_automaton_matches_levenshtein() {
+        let mut rng = StdRng::seed_from_u64(41);
+        let metric = LevenshteinDistance::new();
+        for _ in 0..2000 {
+            let (n, m) = (rng.gen_range(0..10), rng.gen_range(0..10));
+            let pattern = random_dna(&mut rng, n);
+            let input = random_dna(&mut rng, m);
+            let d = metric.distance(
+                &String::from_utf8(pattern.clone()).unwrap(),
+                &String::from_utf8(input.clone()).unwrap(),
+            );
+            for k in 1..=3 {
+                let automaton = LevenshteinAutomaton::new(&pattern, k, AutomatonMode::Levenshtein);
+                assert_eq!(automaton.accepts(&input), (d <= k).then_some(d));
+            }
+        }
+    }
+
+    #[test]
+    fn test_automaton_matches_sequence_levenshtein() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let metric = SequenceLevenshteinDistance::new();
+        for _ in 0..2000 {
+            let (n, m) = (rng.gen_range(1..12), rng.gen_range(1..12));
+            let pattern = random_dna(&mut rng, n);
+            let input = random_dna(&mut rng, m);
+            let d = metric.distance(pattern.as_slice(), input.as_slice());
+            for k in 1..=3 {
+                let automaton =
+                    LevenshteinAutomaton::new(&pattern, k, AutomatonMode::SequenceLevenshtein);
+                assert_eq!(automaton.accepts(&input), (d <= k).then_some(d));
+            }
+        }
+    }
+
+    #[test]
+    fn test_trie_search_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(47);
+        let whitelist: Vec<Vec<u8>> = (0..300).map(|_| random_dna(&mut rng, 10)).collect();
+        let trie = BarcodeTrie::build(&whitelist);
+        assert_eq!(trie.len(), 300);
+        let metric = SequenceLevenshteinDistance::new();
+
+        for q in 0..50 {
+            let mut read = whitelist[q * 3].clone();
+            read[rng.gen_range(0..10)] = b'A';
+            read.remove(rng.gen_range(0..10));
+            read.push(b'G');
+
+            for k in 1..=3 {
+                let mut expected: Vec<(usize, usize)> = whitelist
+                    .iter()
+                    .enumerate()
+                    .map(|(id, b)| (id, metric.distance(read.as_slice(), b.as_slice())))
+                    .filter(|&(_, d)| d <= k)
+                    .collect();
+                expected.sort_unstable_by_key(|&(id, d)| (d, id));
+                assert_eq!(
+                    trie.find_within(&read, k, AutomatonMode::SequenceLevenshtein),
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,22 @@
+pub mod alignment;
+pub mod barcode_constraints;
+pub mod barcode_matcher;
+pub mod barcode_validation;
 pub mod bit_packed_ham;
+pub mod bktree;
 pub mod common;
+pub mod deletion_index;
+pub mod demux;
 pub mod distances;
+pub mod fastq;
+pub mod iupac;
+pub mod lev_automaton;
+pub mod needleman_wunsch;
+pub mod neighborhood;
+pub mod pivot_index;
+pub mod quality;
+pub mod scoring;
 pub mod seq_gen;
+pub mod smith_waterman;
+pub mod umi;
+pub mod vptree;
@@ -0,0 +1,499 @@
+use crate::algos::alignment::{Cigar, CigarOp};
+use crate::algos::scoring::Scoring;
+use serde::{Deserialize, Serialize};
+
+/*
+Needleman-Wunsch global alignment with a substitution matrix and linear or affine gaps
+The scores and the CIGAR are the same types the local aligners use: a is the reference and b
+    the query, = and X consume both, I is a base of b missing from a, D a base of a missing
+    from b, and the gap model is scoring::Scoring (gap of L bases = open + (L - 1) * extend)
+Full mode is Gotoh with traceback: H (best), E (ends in I) and F (ends in D) for every cell,
+    3 (n + 1) (m + 1) scores
+Linear memory mode is Myers-Miller (Hirschberg for affine gaps), the score of the middle row
+    of a is computed forward from the top and backward from the bottom in O(m) memory, and
+    the best crossing point splits the problem in two
+    The crossing is either a cell (type 1) or a D gap spanning the middle row (type 2), which
+        is only opened once, so the halves are told that their D gap is already open at the
+        boundary (tb / te = 0 instead of the open cost)
+    The recursion works on costs (negated scores), O(n m) time, O(n + m) memory
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GlobalAlignment {
+    pub score: i32,
+    pub cigar: Cigar,
+    // Both sequences with b'-' in the gaps
+    pub aligned_a: Vec<u8>,
+    pub aligned_b: Vec<u8>,
+}
+
+pub struct GlobalAligner {
+    scoring: Scoring,
+    linear_memory: bool,
+}
+
+impl GlobalAligner {
+    pub fn new(scoring: Scoring) -> Self {
+        GlobalAligner {
+            scoring,
+            linear_memory: false,
+        }
+    }
+
+    pub fn with_linear_memory(scoring: Scoring) -> Self {
+        GlobalAligner {
+            scoring,
+            linear_memory: true,
+        }
+    }
+
+    pub fn scoring(&self) -> &Scoring {
+        &self.scoring
+    }
+
+    pub fn align(&self, a: &[u8], b: &[u8]) -> GlobalAlignment {
+        let cigar = if self.linear_memory {
+            let mut cigar = Cigar::new();
+            let splitter = MyersMiller::new(&self.scoring, a, b);
+            splitter.split(0..a.len(), 0..b.len(), splitter.g, splitter.g, &mut cigar);
+            cigar
+        } else {
+            self.gotoh(a, b)
+        };
+
+        let (aligned_a, aligned_b) = aligned_strings(&cigar, a, b);
+        GlobalAlignment {
+            score: score_cigar(&self.scoring, &cigar, a, b),
+            cigar,
+            aligned_a,
+            aligned_b,
+        }
+    }
+
+    // Only the score, in O(m) memory
+    pub fn score(&self, a: &[u8], b: &[u8]) -> i32 {
+        let scorer = MyersMiller::new(&self.scoring, a, b);
+        let (cc, _) = scorer.forward(0..a.len(), 0..b.len(), scorer.g);
+        -cc[b.len()]
+    }
+
+    fn gotoh(&self, a: &[u8], b: &[u8]) -> Cigar {
+        let n = a.len();
+        let m = b.len();
+        let width = m + 1;
+        let neg_inf = i32::MIN / 4;
+        let (open, extend) = (self.scoring.gap_open, self.scoring.gap_extend);
+        let mut h = vec![0; (n + 1) * width];
+        let mut e = vec![neg_inf; (n + 1) * width];
+        let mut f = vec![neg_inf; (n + 1) * width];
+        for j in 1..=m {
+            h[j] = -self.scoring.gap(j);
+            e[j] = h[j];
+        }
+        for i in 1..=n {
+            h[i * width] = -self.scoring.gap(i);
+            f[i * width] = h[i * width];
+            for j in 1..=m {
+                let cell = i * width + j;
+                e[cell] = (e[cell - 1] - extend).max(h[cell - 1] - open);
+                f[cell] = (f[cell - width] - extend).max(h[cell - width] - open);
+                let diagonal = h[cell - width - 1] + self.scoring.substitution(a[i - 1], b[j - 1]);
+                h[cell] = diagonal.max(e[cell]).max(f[cell]);
+            }
+        }
+
+        // Walk back through the three matrices, H prefers the diagonal, then I, then D
+        #[derive(PartialEq)]
+        enum State {
+            H,
+            E,
+            F,
+        }
+        let mut ops = Vec::with_capacity(n + m);
+        let (mut i, mut j) = (n, m);
+        let mut state = State::H;
+        while i > 0 || j > 0 {
+            let cell = i * width + j;
+            if i == 0 {
+                ops.push(CigarOp::Insertion);
+                j -= 1;
+                continue;
+            }
+            if j == 0 {
+                ops.push(CigarOp::Deletion);
+                i -= 1;
+                continue;
+            }
+            match state {
+                State::H => {
+                    let diagonal =
+                        h[cell - width - 1] + self.scoring.substitution(a[i - 1], b[j - 1]);
+                    if h[cell] == diagonal {
+                        ops.push(if a[i - 1] == b[j - 1] {
+                            CigarOp::Match
+                        } else {
+                            CigarOp::Mismatch
+                        });
+                        i -= 1;
+                        j -= 1;
+                    } else if h[cell] == e[cell] {
+                        state = State::E;
+                    } else {
+                        state = State::F;
+                    }
+                }
+                State::E => {
+                    ops.push(CigarOp::Insertion);
+                    if e[cell] == h[cell - 1] - open {
+                        state = State::H;
+                    }
+                    j -= 1;
+                }
+                State::F => {
+                    ops.push(CigarOp::Deletion);
+                    if f[cell] == h[cell - width] - open {
+                        state = State::H;
+                    }
+                    i -= 1;
+                }
+            }
+        }
+
+        let mut cigar = Cigar::new();
+        for op in ops.into_iter().rev() {
+            cigar.push(op);
+        }
+        cigar
+    }
+}
+
+// Myers and Miller 1988 in costs, a gap of L bases costs g + h L
+struct MyersMiller<'a> {
+    scoring: &'a Scoring,
+    a: &'a [u8],
+    b: &'a [u8],
+    g: i32,
+    h: i32,
+}
+
+impl<'a> MyersMiller<'a> {
+    fn new(scoring: &'a Scoring, a: &'a [u8], b: &'a [u8]) -> Self {
+        MyersMiller {
+            scoring,
+            a,
+            b,
+            g: scoring.gap_open - scoring.gap_extend,
+            h: scoring.gap_extend,
+        }
+    }
+
+    fn cost(&self, i: usize, j: usize) -> i32 {
+        -self.scoring.substitution(self.a[i], self.b[j])
+    }
+
+    fn gap(&self, length: usize) -> i32 {
+        match length {
+            0 => 0,
+            _ => self.g + self.h * length as i32,
+        }
+    }
+
+    // Last row of a over b: (CC, DD), DD is the best cost ending in a D gap
+    // tb is what opening a D gap at the top costs
+    fn forward(
+        &self,
+        rows: std::ops::Range<usize>,
+        cols: std::ops::Range<usize>,
+        tb: i32,
+    ) -> (Vec<i32>, Vec<i32>) {
+        let (g, h) = (self.g, self.h);
+        let n = cols.len();
+        let mut cc = vec![0; n + 1];
+        let mut dd = vec![0; n + 1];
+        let mut t = g;
+        for j in 1..=n {
+            t += h;
+            cc[j] = t;
+            dd[j] = t + g;
+        }
+        let mut t = tb;
+        for i in rows {
+            let mut s = cc[0];
+            t += h;
+            let mut c = t;
+            cc[0] = c;
+            let mut e = t + g;
+            for j in 1..=n {
+                e = e.min(c + g) + h;
+                dd[j] = dd[j].min(cc[j] + g) + h;
+                c = dd[j].min(e).min(s + self.cost(i, cols.start + j - 1));
+                s = cc[j];
+                cc[j] = c;
+            }
+        }
+        dd[0] = cc[0];
+        (cc, dd)
+    }
+
+    // Same from the bottom right, RR[j] / SS[j] are the costs of b[cols.start + j..] against
+    //     the rows, te is what opening a D gap at the bottom costs
+    fn backward(
+        &self,
+        rows: std::ops::Range<usize>,
+        cols: std::ops::Range<usize>,
+        te: i32,
+    ) -> (Vec<i32>, Vec<i32>) {
+        let (g, h) = (self.g, self.h);
+        let n = cols.len();
+        let mut rr = vec![0; n + 1];
+        let mut ss = vec![0; n + 1];
+        let mut t = g;
+        for j in (0..n).rev() {
+            t += h;
+            rr[j] = t;
+            ss[j] = t + g;
+        }
+        let mut t = te;
+        for i in rows.rev() {
+            let mut s = rr[n];
+            t += h;
+            let mut c = t;
+            rr[n] = c;
+            let mut e = t + g;
+            for j in (0..n).rev() {
+                e = e.min(c + g) + h;
+                ss[j] = ss[j].min(rr[j] + g) + h;
+                c = ss[j].min(e).min(s + self.cost(i, cols.start + j));
+                s = rr[j];
+                rr[j] = c;
+            }
+        }
+        ss[n] = rr[n];
+        (rr, ss)
+    }
+
+    fn split(
+        &self,
+        rows: std::ops::Range<usize>,
+        cols: std::ops::Range<usize>,
+        tb: i32,
+        te: i32,
+        cigar: &mut Cigar,
+    ) {
+        let (m, n) = (rows.len(), cols.len());
+        if n == 0 {
+            (0..m).for_each(|_| cigar.push(CigarOp::Deletion));
+            return;
+        }
+        if m == 0 {
+            (0..n).for_each(|_| cigar.push(CigarOp::Insertion));
+            return;
+        }
+        if m == 1 {
+            // Delete the base and insert all of b, or align it to one base of b
+            let i = rows.start;
+            let mut best = tb.min(te) + self.h + self.gap(n);
+            let mut best_j = None;
+            for j in 0..n {
+                let cost = self.gap(j) + self.cost(i, cols.start + j) + self.gap(n - j - 1);
+                if cost < best {
+                    best = cost;
+                    best_j = Some(j);
+                }
+            }
+            match best_j {
+                Some(j) => {
+                    (0..j).for_each(|_| cigar.push(CigarOp::Insertion));
+                    cigar.push(if self.a[i] == self.b[cols.start + j] {
+                        CigarOp::Match
+                    } else {
+                        CigarOp::Mismatch
+                    });
+                    (j + 1..n).for_each(|_| cigar.push(CigarOp::Insertion));
+                }
+                // The D goes next to the side whose gap is already open
+                None if tb <= te => {
+                    cigar.push(CigarOp::Deletion);
+                    (0..n).for_each(|_| cigar.push(CigarOp::Insertion));
+                }
+                None => {
+                    (0..n).for_each(|_| cigar.push(CigarOp::Insertion));
+                    cigar.push(CigarOp::Deletion);
+                }
+            }
+            return;
+        }
+
+        let middle = rows.start + m / 2;
+        let (cc, dd) = self.forward(rows.start..middle, cols.clone(), tb);
+        let (rr, ss) = self.backward(middle..rows.end, cols.clone(), te);
+        let mut best = cc[0] + rr[0];
+        let mut best_j = 0;
+        let mut spans_gap = false;
+        for j in 0..=n {
+            if cc[j] + rr[j] < best {
+                best = cc[j] + rr[j];
+                best_j = j;
+                spans_gap = false;
+            }
+            if dd[j] + ss[j] - self.g < best {
+                best = dd[j] + ss[j] - self.g;
+                best_j = j;
+                spans_gap = true;
+            }
+        }
+
+        let col = cols.start + best_j;
+        if spans_gap {
+            // Rows middle - 1 and middle are both deleted inside one gap
+            self.split(rows.start..middle - 1, cols.start..col, tb, 0, cigar);
+            cigar.push(CigarOp::Deletion);
+            cigar.push(CigarOp::Deletion);
+            self.split(middle + 1..rows.end, col..cols.end, 0, te, cigar);
+        } else {
+            self.split(rows.start..middle, cols.start..col, tb, self.g, cigar);
+            self.split(middle..rows.end, col..cols.end, self.g, te, cigar);
+        }
+    }
+}
+
+// Score of an edit script, each I or D run is one gap
+pub fn score_cigar(scoring: &Scoring, cigar: &Cigar, a: &[u8], b: &[u8]) -> i32 {
+    let (mut i, mut j) = (0, 0);
+    let mut score = 0;
+    for &(op, count) in &cigar.ops {
+        match op {
+            CigarOp::Match | CigarOp::Mismatch => {
+                for offset in 0..count {
+                    score += scoring.substitution(a[i + offset], b[j + offset]);
+                }
+            }
+            CigarOp::Insertion | CigarOp::Deletion => score -= scoring.gap(count),
+        }
+        i += count * op.consumes_a() as usize;
+        j += count * op.consumes_b() as usize;
+    }
+    score
+}
+
+fn aligned_strings(cigar: &Cigar, a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut aligned_a = Vec::with_capacity(a.len() + b.len());
+    let mut aligned_b = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    for &(op, count) in &cigar.ops {
+        for _ in 0..count {
+            if op.consumes_a() {
+                aligned_a.push(a[i]);
+                i += 1;
+            } else {
+                aligned_a.push(b'-');
+            }
+            if op.consumes_b() {
+                aligned_b.push(b[j]);
+                j += 1;
+            } else {
+                aligned_b.push(b'-');
+            }
+        }
+    }
+    (aligned_a, aligned_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{Distance, LevenshteinDistance};
+    use crate::algos::scoring::SubstitutionMatrix;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn mutate(rng: &mut StdRng, sequence: &[u8]) -> Vec<u8> {
+        let mut mutated = Vec::with_capacity(sequence.len() + 8);
+        for &base in sequence {
+            match rng.gen_range(0..12) {
+                0 => {}
+                1 => mutated.extend([base, b"ACGT"[rng.gen_range(0..4)]]),
+                2 => mutated.push(b"ACGT"[rng.gen_range(0..4)]),
+                // Longer gaps make the affine model matter
+                3 => mutated.extend((0..rng.gen_range(2..6)).map(|_| b"ACGT"[rng.gen_range(0..4)])),
+                _ => mutated.push(base),
+            }
+        }
+        mutated
+    }
+
+    #[test]
+    fn test_global_alignment_example() {
+        let aligner = GlobalAligner::new(Scoring::linear(1, 1, 1));
+        let alignment = aligner.align(b"GATTACA", b"GCATGCT");
+        assert_eq!(alignment.score, 0);
+        assert_eq!(alignment.aligned_a.len(), alignment.aligned_b.len());
+        assert_eq!(
+            score_cigar(aligner.scoring(), &alignment.cigar, b"GATTACA", b"GCATGCT"),
+            0
+        );
+
+        // An affine gap keeps the missing bases together
+        let aligner = GlobalAligner::new(Scoring::new(2, 4, 6, 1));
+        let alignment = aligner.align(b"ACGTTTTTACGT", b"ACGTACGT");
+        // Equal scoring gaps are placed leftmost
+        assert_eq!(alignment.cigar.to_string(), "3=4D5=");
+        assert_eq!(alignment.score, 16 - 9);
+        assert_eq!(alignment.aligned_b, b"ACG----TACGT".to_vec());
+    }
+
+    #[test]
+    fn test_global_alignment_modes_agree() {
+        let mut rng = StdRng::seed_from_u64(24);
+        let scorings = [
+            Scoring::default(),
+            Scoring::linear(2, 3, 2),
+            Scoring::new(2, 3, 8, 1),
+            Scoring::with_matrix(SubstitutionMatrix::nuc44(), 10, 1),
+            Scoring::with_matrix(SubstitutionMatrix::dna(2, -1, -3), 5, 2),
+        ];
+        for round in 0..200 {
+            let len = rng.gen_range(0..=80);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGTN"[rng.gen_range(0..5)]).collect();
+            let b = mutate(&mut rng, &a);
+            let scoring = &scorings[round % scorings.len()];
+
+            let full = GlobalAligner::new(scoring.clone()).align(&a, &b);
+            let linear = GlobalAligner::with_linear_memory(scoring.clone()).align(&a, &b);
+            let score = GlobalAligner::new(scoring.clone()).score(&a, &b);
+            assert_eq!(full.score, score, "{:?}", scoring);
+            assert_eq!(linear.score, score, "{:?}", scoring);
+
+            for alignment in [&full, &linear] {
+                assert_eq!(alignment.cigar.a_len(), a.len());
+                assert_eq!(alignment.cigar.b_len(), b.len());
+                let gapless = |s: &[u8]| {
+                    s.iter()
+                        .copied()
+                        .filter(|&c| c != b'-')
+                        .collect::<Vec<u8>>()
+                };
+                assert_eq!(gapless(&alignment.aligned_a), a);
+                assert_eq!(gapless(&alignment.aligned_b), b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_global_alignment_matches_levenshtein() {
+        let mut rng = StdRng::seed_from_u64(25);
+        // Unit costs turn the score into minus the edit distance
+        let aligner = GlobalAligner::with_linear_memory(Scoring::linear(0, 1, 1));
+        let levenshtein = LevenshteinDistance::new();
+        for _ in 0..100 {
+            let len = rng.gen_range(0..=60);
+            let a: Vec<u8> = (0..len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let b = mutate(&mut rng, &a);
+            let alignment = aligner.align(&a, &b);
+            let a = String::from_utf8(a).unwrap();
+            let b = String::from_utf8(b).unwrap();
+            assert_eq!(-alignment.score as usize, levenshtein.distance(&a, &b));
+            assert_eq!(alignment.cigar.edits(), levenshtein.distance(&a, &b));
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use crate::algos::distances::{Distance, SequenceLevenshteinDistance};
+use fxhash::{FxHashMap, FxHashSet};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/*
+Precomputed neighborhood index
+Every whitelist barcode is expanded into all sequences within the radius, and each neighbor
+    is stored in a hash map pointing back to the barcode it came from
+Correcting a read is then a single hash lookup instead of a scan over the whitelist
+Collisions are resolved on distance: the closest barcode wins, a tie between two barcodes
+    at the same distance marks the neighbor as ambiguous
+Radius is capped at 2, the neighborhood grows as O((L * 4)^radius) per barcode
+    so anything past 2 is better served by the BK-tree or pivot index
+*/
+
+const BASES: [u8; 4] = *b"ACGT";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NeighborhoodMetric {
+    // Substitutions only
+    Hamming,
+    // Substitutions, insertions (truncated back to the barcode length) and
+    //     deletions (padded back with any base), this is a superset of the Hamming neighborhood
+    SequenceLevenshtein,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NeighborHit {
+    Unique { barcode_id: usize, distance: usize },
+    Ambiguous { distance: usize },
+}
+
+impl NeighborHit {
+    pub fn distance(&self) -> usize {
+        match *self {
+            NeighborHit::Unique { distance, .. } => distance,
+            NeighborHit::Ambiguous { distance } => distance,
+        }
+    }
+
+    // Closest wins, equal distance from a different barcode makes it ambiguous
+    fn merge(self, other: NeighborHit) -> NeighborHit {
+        match self.distance().cmp(&other.distance()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => match (self, other) {
+                (
+                    NeighborHit::Unique { barcode_id: a, .. },
+                    NeighborHit::Unique { barcode_id: b, .. },
+                ) if a == b => self,
+                _ => NeighborHit::Ambiguous {
+                    distance: self.distance(),
+                },
+            },
+        }
+    }
+}
+
+pub struct NeighborhoodIndex {
+    map: FxHashMap<Vec<u8>, NeighborHit>,
+    barcode_length: usize,
+    radius: usize,
+    metric: NeighborhoodMetric,
+}
+
+impl NeighborhoodIndex {
+    pub fn build(whitelist: &[Vec<u8>], radius: usize, metric: NeighborhoodMetric) -> Self {
+        assert!(
+            (1..=2).contains(&radius),
+            "Neighborhood radius must be 1 or 2"
+        );
+        let barcode_length = whitelist.first().map_or(0, |b| b.len());
+        assert!(
+            whitelist.iter().all(|b| b.len() == barcode_length),
+            "Whitelist barcodes must all have the same length"
+        );
+
+        // Expansion is the expensive part, merging stays sequential so results are deterministic
+        let expanded: Vec<Vec<(Vec<u8>, usize)>> = whitelist
+            .par_iter()
+            .map(|barcode| match metric {
+                NeighborhoodMetric::Hamming => hamming_neighbors(barcode, radius),
+                NeighborhoodMetric::SequenceLevenshtein => {
+                    sequence_levenshtein_neighbors(barcode, radius)
+                }
+            })
+            .collect();
+
+        let mut map: FxHashMap<Vec<u8>, NeighborHit> = FxHashMap::default();
+        for (barcode_id, neighbors) in expanded.into_iter().enumerate() {
+            for (neighbor, distance) in neighbors {
+                let hit = NeighborHit::Unique {
+                    barcode_id,
+                    distance,
+                };
+                map.entry(neighbor)
+                    .and_modify(|existing| *existing = existing.merge(hit))
+                    .or_insert(hit);
+            }
+        }
+
+        NeighborhoodIndex {
+            map,
+            barcode_length,
+            radius,
+            metric,
+        }
+    }
+
+    // Constant time correction, None when the read is not within the radius of any barcode
+    #[inline(always)]
+    pub fn lookup(&self, read: &[u8]) -> Option<NeighborHit> {
+        if read.len() != self.barcode_length {
+            return None;
+        }
+        self.map.get(read).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+
+    pub fn metric(&self) -> NeighborhoodMetric {
+        self.metric
+    }
+
+    pub fn ambiguous_count(&self) -> usize {
+        self.map
+            .values()
+            .filter(|hit| matches!(hit, NeighborHit::Ambiguous { .. }))
+            .count()
+    }
+}
+
+// Substituting strictly increasing positions to a different base gives each neighbor once,
+//     and the number of substitutions is the exact Hamming distance
+pub fn hamming_neighbors(barcode: &[u8], radius: usize) -> Vec<(Vec<u8>, usize)> {
+    fn expand(
+        current: &mut Vec<u8>,
+        start: usize,
+        depth: usize,
+        radius: usize,
+        out: &mut Vec<(Vec<u8>, usize)>,
+    ) {
+        out.push((current.clone(), depth));
+        if depth == radius {
+            return;
+        }
+        for i in start..current.len() {
+            let original = current[i];
+            for &base in BASES.iter().filter(|&&b| b != original) {
+                current[i] = base;
+                expand(current, i + 1, depth + 1, radius, out);
+            }
+            current[i] = original;
+        }
+    }
+
+    let mut out = Vec::new();
+    expand(&mut barcode.to_vec(), 0, 0, radius, &mut out);
+    out
+}
+
+// Fixed length seq-lev edits of a sequence: substitutions, insertions pushing the last base off
+//     the end, and deletions pulling a new base in at the end
+fn single_edits(sequence: &[u8], out: &mut FxHashSet<Vec<u8>>) {
+    let n = sequence.len();
+    for i in 0..n {
+        for &base in BASES.iter() {
+            if base != sequence[i] {
+                let mut substituted = sequence.to_vec();
+                substituted[i] = base;
+                out.insert(substituted);
+            }
+
+            let mut inserted = Vec::with_capacity(n);
+            inserted.extend_from_slice(&sequence[..i]);
+            inserted.push(base);
+            inserted.extend_from_slice(&sequence[i..n - 1]);
+            out.insert(inserted);
+
+            let mut deleted = Vec::with_capacity(n);
+            deleted.extend_from_slice(&sequence[..i]);
+            deleted.extend_from_slice(&sequence[i + 1..]);
+            deleted.push(base);
+            out.insert(deleted);
+        }
+    }
+}
+
+// Edits are applied radius times, the distance of each candidate is then computed exactly
+//     with the Myers kernel since a chain of edits can land closer than its length
+pub fn sequence_levenshtein_neighbors(barcode: &[u8], radius: usize) -> Vec<(Vec<u8>, usize)> {
+    let metric = SequenceLevenshteinDistance::new();
+    let mut seen: FxHashSet<Vec<u8>> = FxHashSet::default();
+    seen.insert(barcode.to_vec());
+    let mut frontier = vec![barcode.to_vec()];
+
+    for _ in 0..radius {
+        let mut next: FxHashSet<Vec<u8>> = FxHashSet::default();
+        for sequence in frontier.iter() {
+            single_edits(sequence, &mut next);
+        }
+        frontier = next
+            .into_iter()
+            .filter(|s| seen.insert(s.clone()))
+            .collect();
+    }
+
+    let mut out: Vec<(Vec<u8>, usize)> = seen
+        .into_iter()
+        .map(|neighbor| {
+            let d = metric.distance(neighbor.as_slice(), barcode);
+            (neighbor, d)
+        })
+        .filter(|&(_, d)| d <= radius)
+        .collect();
+    out.sort_unstable();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming_neighbor_counts() {
+        // 1 + 3L at radius 1, plus C(L, 2) * 9 at radius 2
+        assert_eq!(hamming_neighbors(b"ACGTACGT", 1).len(), 1 + 3 * 8);
+        assert_eq!(hamming_neighbors(b"ACGTACGT", 2).len(), 1 + 3 * 8 + 28 * 9);
+    }
+
+    #[test]
+    fn test_sequence_levenshtein_neighbors_agree_with_kernel() {
+        let metric = SequenceLevenshteinDistance::new();
+
+        // Brute force over every 8-mer, sequences and distances must both match
+        // The repeat makes many edit chains collapse onto the same neighbor
+        for barcode in [b"ACGTTGCA", b"AACCAACC"] {
+            for radius in 1..=2 {
+                let mut expected: Vec<(Vec<u8>, usize)> = (0..(1u32 << 16))
+                    .map(|code| {
+                        let candidate: Vec<u8> = (0..8)
+                            .map(|i| BASES[(code >> (2 * i)) as usize & 3])
+                            .collect();
+                        let d = metric.distance(candidate.as_slice(), barcode.as_slice());
+                        (candidate, d)
+                    })
+                    .filter(|&(_, d)| d <= radius)
+                    .collect();
+                expected.sort_unstable();
+                assert_eq!(
+                    sequence_levenshtein_neighbors(barcode, radius),
+                    expected,
+                    "{} at radius {}",
+                    String::from_utf8_lossy(barcode),
+                    radius
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_lookup_and_ambiguity() {
+        let whitelist = vec![
+            b"AAAAAAAA".to_vec(),
+            b"AAAAAATT".to_vec(),
+            b"CCCCGGGG".to_vec(),
+        ];
+        let index = NeighborhoodIndex::build(&whitelist, 1, NeighborhoodMetric::Hamming);
+
+        assert_eq!(
+            index.lookup(b"CCCCGGGG"),
+            Some(NeighborHit::Unique {
+                barcode_id: 2,
+                distance: 0
+            })
+        );
+        assert_eq!(
+            index.lookup(b"CCCAGGGG"),
+            Some(NeighborHit::Unique {
+                barcode_id: 2,
+                distance: 1
+            })
+        );
+        // One substitution away from both of the first two barcodes
+        assert_eq!(
+            index.lookup(b"AAAAAAAT"),
+            Some(NeighborHit::Ambiguous { distance: 1 })
+        );
+        assert_eq!(index.lookup(b"TTTTTTTT"), None);
+        assert_eq!(index.lookup(b"CCCCGGG"), None);
+
+        // A deletion is free to pull any base in at the end under seq-lev
+        let index =
+            NeighborhoodIndex::build(&whitelist, 1, NeighborhoodMetric::SequenceLevenshtein);
+        assert_eq!(
+            index.lookup(b"CCCGGGGA"),
+            Some(NeighborHit::Unique {
+                barcode_id: 2,
+                distance: 1
+            })
+        );
+    }
+}
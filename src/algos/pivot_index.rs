@@ -0,0 +1,306 @@
+use crate::algos::distances::{Distance, LengthRange};
+use std::borrow::Borrow;
+use std::collections::BinaryHeap;
+
+/*
+LAESA style pivot table
+A handful of pivots are picked from the stored items (farthest first traversal) and the
+    distance from every item to every pivot is precomputed at build time
+At query time we only pay for the query -> pivot distances, every other item gets a lower bound
+    lb(q, x) = max over pivots |d(q, p) - d(x, p)|
+    which is the triangle inequality, if lb > radius the item can't be a hit so we skip it
+    (each term comes from Distance::lower_bound, which is wider for seq-lev)
+The BK-tree only uses one "pivot" per level, here every pivot prunes every item
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    // Calls to Distance::distance made by the query (pivots included)
+    pub distance_evaluations: usize,
+    // Calls avoided compared to a linear scan over every item
+    pub distances_saved: usize,
+}
+
+impl QueryStats {
+    fn new(num_items: usize, distance_evaluations: usize) -> Self {
+        QueryStats {
+            distance_evaluations,
+            distances_saved: num_items.saturating_sub(distance_evaluations),
+        }
+    }
+}
+
+pub struct PivotIndex<T: ?Sized + ToOwned, D: Distance<T>> {
+    items: Vec<T::Owned>,
+    // Item ids used as pivots
+    pivots: Vec<usize>,
+    // Row major, table[item * pivots.len() + pivot]
+    table: Vec<usize>,
+    lengths: LengthRange,
+    metric: D,
+}
+
+impl<T: ?Sized + ToOwned, D: Distance<T>> PivotIndex<T, D> {
+    pub fn build(metric: D, items: Vec<T::Owned>, num_pivots: usize) -> Self {
+        let num_items = items.len();
+        let num_pivots = num_pivots.min(num_items);
+        let mut pivots = Vec::with_capacity(num_pivots);
+        let mut columns: Vec<Vec<usize>> = Vec::with_capacity(num_pivots);
+        // Distance from each item to its closest pivot so far
+        let mut min_to_pivots = vec![usize::MAX; num_items];
+
+        let mut next = 0;
+        while pivots.len() < num_pivots {
+            let column: Vec<usize> = items
+                .iter()
+                .map(|item| metric.distance(items[next].borrow(), item.borrow()))
+                .collect();
+            for (closest, &d) in min_to_pivots.iter_mut().zip(column.iter()) {
+                *closest = (*closest).min(d);
+            }
+            pivots.push(next);
+            columns.push(column);
+
+            // Farthest first, the next pivot is the item farthest from all current pivots
+            // Pivots have a min distance of 0 so they are never picked twice unless all items collapse
+            let (far, &far_d) = min_to_pivots
+                .iter()
+                .enumerate()
+                .max_by_key(|&(id, &d)| (d, std::cmp::Reverse(id)))
+                .unwrap();
+            if far_d == 0 {
+                break;
+            }
+            next = far;
+        }
+
+        let num_pivots = pivots.len();
+        let mut table = vec![0; num_items * num_pivots];
+        for (p, column) in columns.iter().enumerate() {
+            for (item, &d) in column.iter().enumerate() {
+                table[item * num_pivots + p] = d;
+            }
+        }
+
+        let mut lengths = LengthRange::new();
+        for item in items.iter() {
+            lengths.insert(metric.length(item.borrow()));
+        }
+
+        PivotIndex {
+            items,
+            pivots,
+            table,
+            lengths,
+            metric,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.items.get(id).map(|item| item.borrow())
+    }
+
+    pub fn pivots(&self) -> &[usize] {
+        &self.pivots
+    }
+
+    #[inline(always)]
+    fn lower_bound(&self, id: usize, query_to_pivots: &[usize], spread: usize) -> usize {
+        let row = &self.table[id * self.pivots.len()..(id + 1) * self.pivots.len()];
+        row.iter()
+            .zip(query_to_pivots.iter())
+            .map(|(&a, &b)| self.metric.lower_bound(b, a..=a, spread))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn query_to_pivots(&self, query: &T) -> Vec<usize> {
+        self.pivots
+            .iter()
+            .map(|&p| self.metric.distance(self.items[p].borrow(), query))
+            .collect()
+    }
+
+    // All items within distance k of the query as (id, distance), sorted by distance then id
+    pub fn find_within(&self, query: &T, k: usize) -> (Vec<(usize, usize)>, QueryStats) {
+        let query_to_pivots = self.query_to_pivots(query);
+        let spread = self.lengths.spread(self.metric.length(query));
+        let mut evaluations = self.pivots.len();
+        let mut found = Vec::new();
+
+        // Pivot distances are exact, no need to compute them again
+        let mut is_pivot = vec![false; self.items.len()];
+        for (&p, &d) in self.pivots.iter().zip(query_to_pivots.iter()) {
+            is_pivot[p] = true;
+            if d <= k {
+                found.push((p, d));
+            }
+        }
+
+        for id in 0..self.items.len() {
+            if is_pivot[id] || self.lower_bound(id, &query_to_pivots, spread) > k {
+                continue;
+            }
+            evaluations += 1;
+            let d = self.metric.distance(self.items[id].borrow(), query);
+            if d <= k {
+                found.push((id, d));
+            }
+        }
+
+        found.sort_unstable_by_key(|&(id, d)| (d, id));
+        (found, QueryStats::new(self.items.len(), evaluations))
+    }
+
+    // The n closest items as (id, distance), sorted by distance then id
+    // Candidates are visited in lower bound order so we can stop as soon as
+    //     the bound passes the current n-th best distance
+    pub fn nearest(&self, query: &T, n: usize) -> (Vec<(usize, usize)>, QueryStats) {
+        if n == 0 || self.items.is_empty() {
+            return (Vec::new(), QueryStats::new(self.items.len(), 0));
+        }
+
+        let query_to_pivots = self.query_to_pivots(query);
+        let spread = self.lengths.spread(self.metric.length(query));
+        let mut evaluations = self.pivots.len();
+
+        // Max heap on (distance, id), the top is the worst of the current best n
+        let mut best: BinaryHeap<(usize, usize)> = BinaryHeap::with_capacity(n + 1);
+        let mut is_pivot = vec![false; self.items.len()];
+        for (&p, &d) in self.pivots.iter().zip(query_to_pivots.iter()) {
+            is_pivot[p] = true;
+            best.push((d, p));
+            if best.len() > n {
+                best.pop();
+            }
+        }
+
+        let mut candidates: Vec<(usize, usize)> = (0..self.items.len())
+            .filter(|&id| !is_pivot[id])
+            .map(|id| (self.lower_bound(id, &query_to_pivots, spread), id))
+            .collect();
+        candidates.sort_unstable();
+
+        for (bound, id) in candidates {
+            if best.len() == n {
+                let &(worst, worst_id) = best.peek().unwrap();
+                if (bound, id) > (worst, worst_id) {
+                    break;
+                }
+            }
+            evaluations += 1;
+            let d = self.metric.distance(self.items[id].borrow(), query);
+            best.push((d, id));
+            if best.len() > n {
+                best.pop();
+            }
+        }
+
+        let found = best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(d, id)| (id, d))
+            .collect();
+        (found, QueryStats::new(self.items.len(), evaluations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{HammingDistanceSimd, SequenceLevenshteinDistance};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_barcodes(count: usize, length: usize, seed: u64) -> Vec<Vec<u8>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| (0..length).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect()
+    }
+
+    fn linear_scan(items: &[Vec<u8>], query: &[u8]) -> Vec<(usize, usize)> {
+        let metric = HammingDistanceSimd::new();
+        let mut all: Vec<(usize, usize)> = items
+            .iter()
+            .enumerate()
+            .map(|(id, item)| (id, metric.distance(item.as_slice(), query)))
+            .collect();
+        all.sort_unstable_by_key(|&(id, d)| (d, id));
+        all
+    }
+
+    #[test]
+    fn test_range_query_matches_linear_scan() {
+        let items = random_barcodes(300, 12, 7);
+        let index: PivotIndex<[u8], _> =
+            PivotIndex::build(HammingDistanceSimd::new(), items.clone(), 8);
+        assert_eq!(index.pivots().len(), 8);
+
+        for query in random_barcodes(20, 12, 11) {
+            let expected: Vec<(usize, usize)> = linear_scan(&items, &query)
+                .into_iter()
+                .filter(|&(_, d)| d <= 3)
+                .collect();
+            let (found, stats) = index.find_within(&query, 3);
+            assert_eq!(found, expected);
+            assert_eq!(stats.distance_evaluations + stats.distances_saved, 300);
+        }
+
+        // An exact hit at radius 0 should prune almost everything
+        let (found, stats) = index.find_within(&items[42], 0);
+        assert_eq!(found[0], (42, 0));
+        assert!(stats.distances_saved > 0);
+    }
+
+    #[test]
+    fn test_nearest_matches_linear_scan() {
+        let items = random_barcodes(200, 10, 3);
+        let index: PivotIndex<[u8], _> =
+            PivotIndex::build(HammingDistanceSimd::new(), items.clone(), 6);
+
+        for query in random_barcodes(20, 10, 5) {
+            let expected: Vec<(usize, usize)> =
+                linear_scan(&items, &query).into_iter().take(3).collect();
+            let (found, _) = index.nearest(&query, 3);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_seq_lev_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut random_sequence = |min: usize, max: usize| -> Vec<u8> {
+            let length = rng.gen_range(min..=max);
+            (0..length).map(|_| b"ACGT"[rng.gen_range(0..2)]).collect()
+        };
+        let items: Vec<Vec<u8>> = (0..200).map(|_| random_sequence(6, 10)).collect();
+        let queries: Vec<Vec<u8>> = (0..50).map(|_| random_sequence(3, 12)).collect();
+        let metric = SequenceLevenshteinDistance::new();
+        let index: PivotIndex<[u8], _> =
+            PivotIndex::build(SequenceLevenshteinDistance::new(), items.clone(), 6);
+
+        for query in queries {
+            let mut all: Vec<(usize, usize)> = items
+                .iter()
+                .enumerate()
+                .map(|(id, item)| (id, metric.distance(item.as_slice(), query.as_slice())))
+                .collect();
+            all.sort_unstable_by_key(|&(id, d)| (d, id));
+
+            let expected: Vec<(usize, usize)> =
+                all.iter().copied().filter(|&(_, d)| d <= 2).collect();
+            assert_eq!(index.find_within(&query, 2).0, expected);
+            let expected: Vec<(usize, usize)> = all.iter().copied().take(3).collect();
+            assert_eq!(index.nearest(&query, 3).0, expected);
+        }
+    }
+}
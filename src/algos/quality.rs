@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+
+/*
+Phred quality aware barcode likelihoods
+A read base with quality Q is wrong with probability e = 10^(-Q / 10), so observing the read
+    given a barcode has likelihood (1 - e) on a matching base and e / 3 on a mismatch
+    (the error is spread evenly over the three other bases)
+With indels enabled the likelihood is the best alignment under that model, each inserted or
+    deleted base costs the indel probability, same DP shape as the edit distances:
+    Sequence mode keeps the best of the last row and column (seq-lev, overhang is free)
+    Global mode ends in the corner (Levenshtein)
+Everything is kept in natural log space, posteriors over a candidate set assume a uniform
+    prior over the whitelist and are normalized with log-sum-exp
+Only the candidates near the read are scored, the rest goes into the denominator as background:
+    Every other whitelist barcode is more than max distance away, its likelihood is bounded by
+        max distance + 1 errors on the read bases where an error is cheapest
+    A barcode that is not on the whitelist is a uniformly random sequence (1/4 per base),
+        weighted by unlisted_probability against the per barcode prior
+So a lone distant candidate can't reach 1.0, while a close candidate on a large whitelist keeps
+    its posterior since the other barcodes are all several errors away
+*/
+
+pub const DEFAULT_PHRED_OFFSET: u8 = 33;
+
+// Roughly the oligo synthesis error rate, sequencer indels are rarer than that
+pub const DEFAULT_INDEL_PROBABILITY: f64 = 1e-3;
+
+// Prior share of reads whose barcode is not on the whitelist (contamination, adapter dimers)
+pub const DEFAULT_UNLISTED_PROBABILITY: f64 = 0.01;
+
+// Q0/Q1 would give error probabilities >= 0.75, which makes a mismatch as likely as a match
+const MAX_ERROR_PROBABILITY: f64 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QualityModel {
+    pub phred_offset: u8,
+    // None scores substitutions only
+    pub indel_probability: Option<f64>,
+    // Prior of a read coming from no whitelist barcode, the rest is spread evenly over the whitelist
+    pub unlisted_probability: f64,
+}
+
+impl QualityModel {
+    pub fn new() -> Self {
+        QualityModel {
+            phred_offset: DEFAULT_PHRED_OFFSET,
+            indel_probability: None,
+            unlisted_probability: DEFAULT_UNLISTED_PROBABILITY,
+        }
+    }
+
+    pub fn with_indels(indel_probability: f64) -> Self {
+        assert!(
+            indel_probability > 0.0 && indel_probability < 1.0,
+            "Indel probability must be in (0, 1)"
+        );
+        QualityModel {
+            phred_offset: DEFAULT_PHRED_OFFSET,
+            indel_probability: Some(indel_probability),
+            unlisted_probability: DEFAULT_UNLISTED_PROBABILITY,
+        }
+    }
+
+    pub fn with_unlisted_probability(mut self, unlisted_probability: f64) -> Self {
+        assert!(
+            (0.0..1.0).contains(&unlisted_probability),
+            "Unlisted probability must be in [0, 1)"
+        );
+        self.unlisted_probability = unlisted_probability;
+        self
+    }
+
+    pub fn error_probability(&self, quality: u8) -> f64 {
+        let phred = quality.saturating_sub(self.phred_offset) as f64;
+        10f64.powf(-phred / 10.0).min(MAX_ERROR_PROBABILITY)
+    }
+
+    // Log likelihood of observing read base `observed` with `quality` when the barcode has `expected`
+    pub fn base_log_likelihood(&self, expected: u8, observed: u8, quality: u8) -> f64 {
+        let error = self.error_probability(quality);
+        if expected == observed {
+            (1.0 - error).ln()
+        } else {
+            (error / 3.0).ln()
+        }
+    }
+
+    // Substitutions only, bases past the shorter sequence count as uniformly random (1/4)
+    pub fn substitution_log_likelihood(&self, barcode: &[u8], read: &[u8], qual: &[u8]) -> f64 {
+        let overlap = barcode.len().min(read.len());
+        let matched: f64 = (0..overlap)
+            .map(|i| self.base_log_likelihood(barcode[i], read[i], qual[i]))
+            .sum();
+        let unmatched = barcode.len().max(read.len()) - overlap;
+        matched + unmatched as f64 * 0.25f64.ln()
+    }
+
+    // Best alignment log likelihood, `sequence` selects seq-lev over global end conditions
+    pub fn alignment_log_likelihood(
+        &self,
+        barcode: &[u8],
+        read: &[u8],
+        qual: &[u8],
+        sequence: bool,
+    ) -> f64 {
+        let indel = match self.indel_probability {
+            Some(p) => p.ln(),
+            None => return self.substitution_log_likelihood(barcode, read, qual),
+        };
+        let (m, n) = (barcode.len(), read.len());
+        let mut previous: Vec<f64> = (0..=n).map(|j| j as f64 * indel).collect();
+        let mut current = vec![0.0; n + 1];
+        // Best end in the last column (read fully used, rest of the barcode pushed out)
+        let mut best_last_column = previous[n];
+
+        for i in 1..=m {
+            current[0] = i as f64 * indel;
+            for j in 1..=n {
+                let diagonal = previous[j - 1]
+                    + self.base_log_likelihood(barcode[i - 1], read[j - 1], qual[j - 1]);
+                let deletion = previous[j] + indel;
+                let insertion = current[j - 1] + indel;
+                current[j] = diagonal.max(deletion).max(insertion);
+            }
+            best_last_column = best_last_column.max(current[n]);
+            std::mem::swap(&mut previous, &mut current);
+        }
+
+        if sequence {
+            // Last row: barcode fully used, trailing read bases are overhang
+            let best_last_row = previous.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            best_last_row.max(best_last_column)
+        } else {
+            previous[n]
+        }
+    }
+
+    // Upper bound on the log likelihood of a barcode at least `errors` edits from the read:
+    //     every base matches except the `errors` bases where an error costs the least
+    // An indel is bounded by the indel probability, which ignores the shift it causes
+    pub fn error_bound_log_likelihood(&self, qual: &[u8], errors: usize) -> f64 {
+        let mut matched = 0.0;
+        let mut penalties: Vec<f64> = qual
+            .iter()
+            .map(|&q| {
+                let error = self.error_probability(q);
+                let mismatch = (error / 3.0).ln();
+                let cheapest = self
+                    .indel_probability
+                    .map_or(mismatch, |p| mismatch.max(p.ln()));
+                matched += (1.0 - error).ln();
+                cheapest - (1.0 - error).ln()
+            })
+            .collect();
+        penalties.sort_unstable_by(|a, b| b.total_cmp(a));
+        matched + penalties.iter().take(errors).sum::<f64>()
+    }
+
+    // Log likelihood of the hypotheses that were not scored, relative to the per barcode prior
+    // `others` whitelist barcodes are all more than max_distance away from the read
+    pub fn background_log_likelihood(
+        &self,
+        qual: &[u8],
+        others: usize,
+        whitelist_len: usize,
+        max_distance: usize,
+    ) -> f64 {
+        let others = (others as f64).ln()
+            + self.error_bound_log_likelihood(qual, max_distance.saturating_add(1));
+        let unlisted = (self.unlisted_probability * whitelist_len as f64
+            / (1.0 - self.unlisted_probability))
+            .ln()
+            + random_log_likelihood(qual.len());
+        log_add(others, unlisted)
+    }
+}
+
+// ln(e^a + e^b) without leaving log space
+fn log_add(a: f64, b: f64) -> f64 {
+    let max = a.max(b);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+impl Default for QualityModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Log likelihood of `length` read bases that don't come from any barcode
+pub fn random_log_likelihood(length: usize) -> f64 {
+    length as f64 * 0.25f64.ln()
+}
+
+// Normalizes log likelihoods into posteriors under a uniform prior
+pub fn posteriors(log_likelihoods: &[f64]) -> Vec<f64> {
+    posteriors_with_background(log_likelihoods, f64::NEG_INFINITY)
+}
+
+// Same as posteriors with extra (log) probability mass in the denominator for the hypotheses
+//     that were not scored, the posteriors then sum to less than 1
+pub fn posteriors_with_background(log_likelihoods: &[f64], background: f64) -> Vec<f64> {
+    let max = log_likelihoods.iter().copied().fold(background, f64::max);
+    if !max.is_finite() {
+        return vec![0.0; log_likelihoods.len()];
+    }
+    let total: f64 =
+        log_likelihoods.iter().map(|l| (l - max).exp()).sum::<f64>() + (background - max).exp();
+    log_likelihoods
+        .iter()
+        .map(|l| (l - max).exp() / total)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qual(scores: &[u8]) -> Vec<u8> {
+        scores.iter().map(|q| q + DEFAULT_PHRED_OFFSET).collect()
+    }
+
+    #[test]
+    fn test_error_probability() {
+        let model = QualityModel::new();
+        assert!((model.error_probability(b'+') - 0.1).abs() < 1e-12); // Q10
+        assert!((model.error_probability(b'I') - 1e-4).abs() < 1e-12); // Q40
+        assert_eq!(model.error_probability(b'!'), MAX_ERROR_PROBABILITY); // Q0
+    }
+
+    #[test]
+    fn test_low_quality_mismatch_costs_less() {
+        let model = QualityModel::new();
+        let barcode = b"ACGTACGT";
+        let read = b"ACGTACGA";
+        let low = model.substitution_log_likelihood(
+            barcode,
+            read,
+            &qual(&[40, 40, 40, 40, 40, 40, 40, 2]),
+        );
+        let high = model.substitution_log_likelihood(barcode, read, &qual(&[40; 8]));
+        assert!(low > high);
+
+        let post = posteriors(&[low, high]);
+        assert!((post.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(post[0] > post[1]);
+    }
+
+    #[test]
+    fn test_background_lowers_posteriors() {
+        let model = QualityModel::new();
+        let barcode = b"ACGTACGTACGT";
+        let q = qual(&[40; 12]);
+        // Four high quality mismatches
+        let distant = model.substitution_log_likelihood(barcode, b"ACGTACGTTGCA", &q);
+        assert_eq!(posteriors(&[distant]), vec![1.0]);
+
+        let post = posteriors_with_background(&[distant], random_log_likelihood(12));
+        assert!(post[0] < 0.01, "{:?}", post);
+
+        // A single mismatch barely notices the background
+        let close = model.substitution_log_likelihood(barcode, b"ACGTACGTACGA", &q);
+        let post = posteriors_with_background(&[close], random_log_likelihood(12));
+        assert!(post[0] > 0.99, "{:?}", post);
+    }
+
+    #[test]
+    fn test_error_bound() {
+        let model = QualityModel::new();
+        let q = qual(&[40, 40, 2, 40, 10, 40, 40, 40]);
+        let barcode = b"ACGTACGT";
+        // The bound puts the errors on the Q2 and Q10 bases, any real barcode does no better
+        let bound = model.error_bound_log_likelihood(&q, 2);
+        let cheapest = model.substitution_log_likelihood(barcode, b"ACCTCCGT", &q);
+        assert!((bound - cheapest).abs() < 1e-9);
+        assert!(bound > model.substitution_log_likelihood(barcode, b"TCGTACGA", &q));
+        let exact = model.substitution_log_likelihood(barcode, barcode, &q);
+        assert!((model.error_bound_log_likelihood(&q, 0) - exact).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_alignment_likelihood_handles_indels() {
+        let model = QualityModel::with_indels(1e-3);
+        let barcode = b"ACGTTGCA";
+        // One base deleted from the read, the window picks up the next read base
+        let read = b"ACGTGCAC";
+        let q = qual(&[30; 8]);
+
+        let substitutions = model.substitution_log_likelihood(barcode, read, &q);
+        let sequence = model.alignment_log_likelihood(barcode, read, &q, true);
+        let global = model.alignment_log_likelihood(barcode, read, &q, false);
+        assert!(sequence > substitutions);
+        // Global has to pay for the trailing base as well
+        assert!(sequence > global);
+
+        // Exact match, alignment and substitution models agree
+        let exact = model.alignment_log_likelihood(barcode, barcode, &q, true);
+        assert!((exact - model.substitution_log_likelihood(barcode, barcode, &q)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,319 @@
+use serde::{Deserialize, Serialize};
+
+/*
+Scores shared by the score based aligners (striped Smith-Waterman, Needleman-Wunsch), so
+    local and global reports are on the same scale
+Substitutions come from a SubstitutionMatrix: a square matrix over a small alphabet, bytes
+    outside the alphabet score by equality (match / mismatch), a plain match/mismatch scheme
+    is a matrix with an empty alphabet
+Affine gaps: a gap of length L costs gap_open + (L - 1) * gap_extend, so gap_open is the cost
+    of the first gapped base, not an extra charge on top of it
+    A linear gap model is gap_open == gap_extend
+Gap penalties are given as non-negative numbers, substitution scores are signed
+EditCosts turns it around for the weighted edit distances: the matrix holds non-negative
+    substitution costs (lower is better) next to separate insertion and deletion costs
+    Insertion and deletion follow the CIGAR convention, an insertion is a base of b missing
+        from a and a deletion a base of a missing from b
+    Costs are integers, fractional costs are fixed point: multiplied by scale and rounded, a
+        distance is divided by scale to get back to the original unit
+*/
+
+// Marks bytes without a row in the matrix
+const NOT_IN_ALPHABET: u8 = u8::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubstitutionMatrix {
+    alphabet: Vec<u8>,
+    // Row of every byte value, NOT_IN_ALPHABET for the rest
+    index: Vec<u8>,
+    // alphabet.len() x alphabet.len(), row major
+    scores: Vec<i32>,
+    match_score: i32,
+    mismatch_score: i32,
+}
+
+impl SubstitutionMatrix {
+    // Every byte scores by equality
+    pub fn match_mismatch(match_score: i32, mismatch_score: i32) -> Self {
+        SubstitutionMatrix::new(&[], &[], match_score, mismatch_score)
+    }
+
+    // scores[i][j] is the score of alphabet[i] against alphabet[j], bytes outside the
+    //     alphabet fall back to match_score / mismatch_score
+    pub fn new(
+        alphabet: &[u8],
+        scores: &[Vec<i32>],
+        match_score: i32,
+        mismatch_score: i32,
+    ) -> Self {
+        assert!(
+            alphabet.len() < NOT_IN_ALPHABET as usize,
+            "Alphabet of {} symbols is too large",
+            alphabet.len()
+        );
+        assert!(
+            scores.len() == alphabet.len() && scores.iter().all(|row| row.len() == alphabet.len()),
+            "Substitution matrix has to be {0} x {0}",
+            alphabet.len()
+        );
+        let mut index = vec![NOT_IN_ALPHABET; 256];
+        for (row, &symbol) in alphabet.iter().enumerate() {
+            assert!(
+                index[symbol as usize] == NOT_IN_ALPHABET,
+                "Symbol {} is repeated in the alphabet",
+                symbol as char
+            );
+            index[symbol as usize] = row as u8;
+        }
+        SubstitutionMatrix {
+            alphabet: alphabet.to_vec(),
+            index,
+            scores: scores.concat(),
+            match_score,
+            mismatch_score,
+        }
+    }
+
+    // ACGT with separate scores for transitions (A <-> G, C <-> T) and transversions
+    pub fn dna(match_score: i32, transition: i32, transversion: i32) -> Self {
+        let alphabet = b"ACGT";
+        let scores: Vec<Vec<i32>> = alphabet
+            .iter()
+            .map(|&a| {
+                alphabet
+                    .iter()
+                    .map(|&b| match (a, b) {
+                        _ if a == b => match_score,
+                        (b'A', b'G') | (b'G', b'A') | (b'C', b'T') | (b'T', b'C') => transition,
+                        _ => transversion,
+                    })
+                    .collect()
+            })
+            .collect();
+        SubstitutionMatrix::new(alphabet, &scores, match_score, transversion)
+    }
+
+    // NCBI's NUC.4.4 (EDNAFULL) restricted to ACGTN
+    pub fn nuc44() -> Self {
+        let scores = vec![
+            vec![5, -4, -4, -4, -2],
+            vec![-4, 5, -4, -4, -2],
+            vec![-4, -4, 5, -4, -2],
+            vec![-4, -4, -4, 5, -2],
+            vec![-2, -2, -2, -2, -1],
+        ];
+        SubstitutionMatrix::new(b"ACGTN", &scores, 5, -4)
+    }
+
+    pub fn alphabet(&self) -> &[u8] {
+        &self.alphabet
+    }
+
+    // Scores of bytes outside the alphabet
+    pub fn match_score(&self) -> i32 {
+        self.match_score
+    }
+
+    pub fn mismatch_score(&self) -> i32 {
+        self.mismatch_score
+    }
+
+    #[inline(always)]
+    pub fn score(&self, a: u8, b: u8) -> i32 {
+        let row = self.index[a as usize];
+        let col = self.index[b as usize];
+        if row != NOT_IN_ALPHABET && col != NOT_IN_ALPHABET {
+            self.scores[row as usize * self.alphabet.len() + col as usize]
+        } else if a == b {
+            self.match_score
+        } else {
+            self.mismatch_score
+        }
+    }
+
+    pub fn min_score(&self) -> i32 {
+        self.scores
+            .iter()
+            .copied()
+            .chain([self.match_score, self.mismatch_score])
+            .min()
+            .unwrap()
+    }
+
+    pub fn max_score(&self) -> i32 {
+        self.scores
+            .iter()
+            .copied()
+            .chain([self.match_score, self.mismatch_score])
+            .max()
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scoring {
+    pub substitution: SubstitutionMatrix,
+    pub gap_open: i32,
+    pub gap_extend: i32,
+}
+
+impl Scoring {
+    // Matches add match_score, mismatches subtract mismatch_penalty
+    pub fn new(match_score: i32, mismatch_penalty: i32, gap_open: i32, gap_extend: i32) -> Self {
+        assert!(
+            mismatch_penalty >= 0,
+            "Penalties are given as non-negative values"
+        );
+        Scoring::with_matrix(
+            SubstitutionMatrix::match_mismatch(match_score, -mismatch_penalty),
+            gap_open,
+            gap_extend,
+        )
+    }
+
+    // Every gapped base costs gap
+    pub fn linear(match_score: i32, mismatch_penalty: i32, gap: i32) -> Self {
+        Scoring::new(match_score, mismatch_penalty, gap, gap)
+    }
+
+    pub fn with_matrix(substitution: SubstitutionMatrix, gap_open: i32, gap_extend: i32) -> Self {
+        assert!(
+            gap_open >= 0 && gap_extend >= 0,
+            "Penalties are given as non-negative values"
+        );
+        assert!(
+            gap_open >= gap_extend,
+            "Opening a gap can't be cheaper than extending it"
+        );
+        Scoring {
+            substitution,
+            gap_open,
+            gap_extend,
+        }
+    }
+
+    pub fn is_linear(&self) -> bool {
+        self.gap_open == self.gap_extend
+    }
+
+    #[inline(always)]
+    pub fn substitution(&self, a: u8, b: u8) -> i32 {
+        self.substitution.score(a, b)
+    }
+
+    // Cost of a gap of the given length, 0 for no gap
+    pub fn gap(&self, length: usize) -> i32 {
+        match length {
+            0 => 0,
+            _ => self.gap_open + (length as i32 - 1) * self.gap_extend,
+        }
+    }
+}
+
+// BWA-MEM's defaults
+impl Default for Scoring {
+    fn default() -> Self {
+        Scoring::new(1, 4, 6, 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+// Fields are private so every cost goes through the non-negative check in new
+pub struct EditCosts {
+    substitution: SubstitutionMatrix,
+    insertion: i32,
+    deletion: i32,
+    // Fixed point scale, 1 for plain integer costs
+    scale: u32,
+}
+
+impl EditCosts {
+    // substitution holds costs, a match should cost 0 for the distance to be 0 between equal
+    //     sequences
+    pub fn new(substitution: SubstitutionMatrix, insertion: i32, deletion: i32) -> Self {
+        assert!(
+            substitution.min_score() >= 0 && insertion >= 0 && deletion >= 0,
+            "Costs are given as non-negative values"
+        );
+        EditCosts {
+            substitution,
+            insertion,
+            deletion,
+            scale: 1,
+        }
+    }
+
+    // Every edit costs 1, the plain (sequence) Levenshtein distance
+    pub fn unit() -> Self {
+        EditCosts::new(SubstitutionMatrix::match_mismatch(0, 1), 1, 1)
+    }
+
+    // Substitutions within purines (A <-> G) or pyrimidines (C <-> T) cost transition, any
+    //     other transversion, bases outside ACGT mismatch at the transversion cost
+    pub fn transition_transversion(transition: i32, transversion: i32, indel: i32) -> Self {
+        EditCosts::new(
+            SubstitutionMatrix::dna(0, transition, transversion),
+            indel,
+            indel,
+        )
+    }
+
+    // Fractional costs, every cost is multiplied by scale and rounded to the nearest integer
+    pub fn fixed_point(
+        alphabet: &[u8],
+        costs: &[Vec<f64>],
+        mismatch: f64,
+        insertion: f64,
+        deletion: f64,
+        scale: u32,
+    ) -> Self {
+        assert!(scale > 0, "Fixed point scale has to be positive");
+        let to_fixed = |cost: f64| {
+            assert!(
+                cost.is_finite() && cost >= 0.0,
+                "Costs are given as non-negative values"
+            );
+            (cost * scale as f64).round() as i32
+        };
+        let scaled: Vec<Vec<i32>> = costs
+            .iter()
+            .map(|row| row.iter().map(|&cost| to_fixed(cost)).collect())
+            .collect();
+        EditCosts {
+            scale,
+            ..EditCosts::new(
+                SubstitutionMatrix::new(alphabet, &scaled, 0, to_fixed(mismatch)),
+                to_fixed(insertion),
+                to_fixed(deletion),
+            )
+        }
+    }
+
+    #[inline(always)]
+    pub fn substitution(&self, a: u8, b: u8) -> usize {
+        self.substitution.score(a, b) as usize
+    }
+
+    #[inline(always)]
+    pub fn insertion(&self) -> usize {
+        self.insertion as usize
+    }
+
+    #[inline(always)]
+    pub fn deletion(&self) -> usize {
+        self.deletion as usize
+    }
+
+    pub fn substitution_matrix(&self) -> &SubstitutionMatrix {
+        &self.substitution
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    // A distance in the unit the costs were given in
+    pub fn to_f64(&self, distance: usize) -> f64 {
+        distance as f64 / self.scale as f64
+    }
+}
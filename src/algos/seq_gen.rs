@@ -3,7 +3,15 @@ Implement a custom bit vector type to avoid the overhead of Vec<u8>.
 Use a more compact representation for neighbors, such as storing only the changed index and new value.
 Parallelize the neighbor generation using Rayon or another parallel processing library.
 */
+use crate::algos::barcode_matcher::MatchMetric;
+use crate::algos::bit_packed_ham::BitHamProcessor;
+use crate::algos::distances::{
+    Distance, HammingDistanceSimd, LevenshteinDistance, SequenceLevenshteinDistance,
+};
 use fxhash::FxHashSet;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use std::simd::num::SimdUint;
 use std::simd::Simd;
 // use crate::algos::common::*;
@@ -58,3 +66,402 @@ pub fn neighbors_simd(sequence: &[u8]) -> FxHashSet<Vec<u8>> {
 
     neighbors
 }
+
+/*
+Barcode set design
+Builds a set of barcodes of a fixed length whose pairwise distance (Hamming, seq-lev or
+    Levenshtein) is at least min distance, and which pass every BarcodeConstraint
+Greedy pass: lexicode style, candidates are walked in lexicographic order (the base order is
+    shuffled by the seed) and kept when they are far enough from everything kept so far
+    Lengths with more than GREEDY_CANDIDATE_LIMIT words are sampled at random instead
+Evolutionary pass: a small population of valid sets, each child drops a few barcodes and then
+    tries to insert mutants of the removed barcodes (the only words that can have been freed
+    up), mutants of its members or fresh random words
+    Fitness is (set size, min pairwise distance), the best POPULATION_SIZE sets survive
+    Removals can break constraints that look at the whole set (TwoChannelBalance allows fewer
+        missing channels in a smaller set), a child that fails them after its removals is
+        dropped in favor of its parent
+    Every set in the population satisfies min distance and the constraints, so the best one
+    can be returned as soon as it reaches the size target, the search also stops when the
+    best set stalls for STALL_GENERATIONS
+Whole set scoring goes through BitHamProcessor for Hamming and SequenceLevenshteinDistance for
+    seq-lev, incremental checks against a growing set use the per pair distances
+*/
+
+const GREEDY_CANDIDATE_LIMIT: usize = 1 << 20;
+const POPULATION_SIZE: usize = 8;
+const OFFSPRING_PER_PARENT: usize = 4;
+const MAX_GENERATIONS: usize = 200;
+// Give up once the best set has not improved for this many generations
+const STALL_GENERATIONS: usize = 40;
+const MAX_REMOVALS: usize = 3;
+const INSERT_ATTEMPTS: usize = 64;
+
+const BASES: [u8; 4] = *b"ACGT";
+
+// A rule a barcode has to follow on top of the distance requirement
+// `accepted` is the set built so far, for rules that look at the whole set
+// Err carries the reason the candidate was rejected
+pub trait BarcodeConstraint {
+    fn check(&self, candidate: &[u8], accepted: &[Vec<u8>]) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesignedBarcodeSet {
+    pub barcodes: Vec<Vec<u8>>,
+    // None for sets with fewer than two barcodes
+    pub min_pairwise_distance: Option<usize>,
+    // Size after the greedy pass, before the evolutionary search
+    pub greedy_size: usize,
+    pub generations: usize,
+}
+
+struct SetDesigner<'a> {
+    length: usize,
+    min_distance: usize,
+    metric: MatchMetric,
+    constraints: &'a [Box<dyn BarcodeConstraint>],
+}
+
+impl SetDesigner<'_> {
+    fn distance(&self, a: &[u8], b: &[u8]) -> usize {
+        match self.metric {
+            MatchMetric::Hamming => HammingDistanceSimd::new().distance(a, b),
+            MatchMetric::SequenceLevenshtein => SequenceLevenshteinDistance::new().distance(a, b),
+            MatchMetric::Levenshtein => {
+                // Generated words are always ACGT
+                let a = String::from_utf8_lossy(a).into_owned();
+                let b = String::from_utf8_lossy(b).into_owned();
+                LevenshteinDistance.distance(&a, &b)
+            }
+        }
+    }
+
+    fn admissible(&self, candidate: &[u8], set: &[Vec<u8>]) -> bool {
+        set.iter()
+            .all(|barcode| self.distance(candidate, barcode) >= self.min_distance)
+            && self
+                .constraints
+                .iter()
+                .all(|c| c.check(candidate, set).is_ok())
+    }
+
+    // Every member passes the constraints against the rest of the set
+    // Members are swapped to the end in turn, so the set comes back in the same order
+    fn satisfies_constraints(&self, set: &mut [Vec<u8>]) -> bool {
+        if self.constraints.is_empty() || set.is_empty() {
+            return true;
+        }
+        let last = set.len() - 1;
+        (0..set.len()).all(|i| {
+            set.swap(i, last);
+            let (others, member) = set.split_at(last);
+            let passes = self
+                .constraints
+                .iter()
+                .all(|c| c.check(&member[0], others).is_ok());
+            set.swap(i, last);
+            passes
+        })
+    }
+
+    fn min_pairwise_distance(&self, set: &[Vec<u8>]) -> Option<usize> {
+        if set.len() < 2 {
+            return None;
+        }
+        if self.metric == MatchMetric::Hamming {
+            let processor = BitHamProcessor::new();
+            processor.initialize(set);
+            return processor.process_sequences().into_iter().min();
+        }
+        let mut min = usize::MAX;
+        for i in 0..set.len() {
+            for j in (i + 1)..set.len() {
+                min = min.min(self.distance(&set[i], &set[j]));
+            }
+        }
+        Some(min)
+    }
+
+    fn fitness(&self, set: &[Vec<u8>]) -> (usize, usize) {
+        (
+            set.len(),
+            self.min_pairwise_distance(set).unwrap_or(self.length),
+        )
+    }
+
+    fn random_word(&self, rng: &mut StdRng) -> Vec<u8> {
+        (0..self.length)
+            .map(|_| BASES[rng.gen_range(0..4)])
+            .collect()
+    }
+
+    fn greedy(&self, size_target: usize, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        let mut set = Vec::new();
+        let words = 4usize.checked_pow(self.length as u32);
+        match words.filter(|&w| w <= GREEDY_CANDIDATE_LIMIT) {
+            Some(words) => {
+                let mut bases = BASES;
+                bases.shuffle(rng);
+                for index in 0..words {
+                    if set.len() >= size_target {
+                        break;
+                    }
+                    // Most significant digit first so the walk is lexicographic
+                    let candidate: Vec<u8> = (0..self.length)
+                        .rev()
+                        .map(|digit| bases[(index >> (2 * digit)) & 3])
+                        .collect();
+                    if self.admissible(&candidate, &set) {
+                        set.push(candidate);
+                    }
+                }
+            }
+            None => {
+                for _ in 0..GREEDY_CANDIDATE_LIMIT {
+                    if set.len() >= size_target {
+                        break;
+                    }
+                    let candidate = self.random_word(rng);
+                    if self.admissible(&candidate, &set) {
+                        set.push(candidate);
+                    }
+                }
+            }
+        }
+        set
+    }
+
+    fn mutate(&self, word: &[u8], rng: &mut StdRng) -> Vec<u8> {
+        let mut mutant = word.to_vec();
+        for _ in 0..rng.gen_range(1..=self.min_distance) {
+            mutant[rng.gen_range(0..self.length)] = BASES[rng.gen_range(0..4)];
+        }
+        mutant
+    }
+
+    fn offspring(&self, parent: &[Vec<u8>], size_target: usize, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        let mut child = parent.to_vec();
+        let removals = rng.gen_range(1..=MAX_REMOVALS).min(child.len());
+        let mut removed = Vec::with_capacity(removals);
+        for _ in 0..removals {
+            let i = rng.gen_range(0..child.len());
+            removed.push(child.swap_remove(i));
+        }
+        if !self.satisfies_constraints(&mut child) {
+            return parent.to_vec();
+        }
+
+        for _ in 0..INSERT_ATTEMPTS {
+            if child.len() >= size_target {
+                break;
+            }
+            // Only words close to a removed barcode can have been freed up, the mutants of the
+            // remaining members and random words let the set drift elsewhere
+            let candidate = match rng.gen_range(0..4) {
+                0 | 1 if !removed.is_empty() => {
+                    self.mutate(&removed[rng.gen_range(0..removed.len())], rng)
+                }
+                2 if !child.is_empty() => self.mutate(&child[rng.gen_range(0..child.len())], rng),
+                _ => self.random_word(rng),
+            };
+            if self.admissible(&candidate, &child) {
+                child.push(candidate);
+            }
+        }
+        child
+    }
+
+    // Returns the best set found and the number of generations it took
+    fn evolve(
+        &self,
+        initial: Vec<Vec<u8>>,
+        size_target: usize,
+        rng: &mut StdRng,
+    ) -> (Vec<Vec<u8>>, usize) {
+        let mut population = vec![(self.fitness(&initial), initial)];
+        let mut generations = 0;
+        let mut last_improvement = 0;
+        while population[0].1.len() < size_target
+            && generations < MAX_GENERATIONS
+            && generations - last_improvement < STALL_GENERATIONS
+        {
+            generations += 1;
+            let best = population[0].0;
+            let mut children = Vec::with_capacity(population.len() * OFFSPRING_PER_PARENT);
+            for (_, parent) in &population {
+                for _ in 0..OFFSPRING_PER_PARENT {
+                    let child = self.offspring(parent, size_target, rng);
+                    children.push((self.fitness(&child), child));
+                }
+            }
+            population.extend(children);
+            // Stable sort keeps parents ahead of equally fit children
+            population.sort_by_key(|&(fitness, _)| std::cmp::Reverse(fitness));
+            population.truncate(POPULATION_SIZE);
+            if population[0].0 > best {
+                last_improvement = generations;
+            }
+        }
+
+        (population.swap_remove(0).1, generations)
+    }
+}
+
+// Designs up to size_target barcodes of `length` bases with pairwise distance >= min_distance
+// The result is deterministic for a given seed
+pub fn design_barcode_set(
+    length: usize,
+    min_distance: usize,
+    metric: MatchMetric,
+    size_target: usize,
+    constraints: &[Box<dyn BarcodeConstraint>],
+    seed: u64,
+) -> DesignedBarcodeSet {
+    assert!(length > 0, "Barcode length must be at least 1");
+    assert!(
+        min_distance > 0 && min_distance <= length,
+        "Minimum distance must be in 1..=length"
+    );
+
+    let designer = SetDesigner {
+        length,
+        min_distance,
+        metric,
+        constraints,
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let greedy = designer.greedy(size_target, &mut rng);
+    let greedy_size = greedy.len();
+
+    let (mut barcodes, generations) = designer.evolve(greedy, size_target, &mut rng);
+    barcodes.sort();
+    DesignedBarcodeSet {
+        min_pairwise_distance: designer.min_pairwise_distance(&barcodes),
+        barcodes,
+        greedy_size,
+        generations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoRepeats;
+
+    impl BarcodeConstraint for NoRepeats {
+        fn check(&self, candidate: &[u8], _accepted: &[Vec<u8>]) -> Result<(), String> {
+            match candidate.windows(2).position(|w| w[0] == w[1]) {
+                Some(i) => Err(format!("repeated base at {}", i)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn check_set(
+        set: &DesignedBarcodeSet,
+        length: usize,
+        min_distance: usize,
+        metric: MatchMetric,
+    ) {
+        let designer = SetDesigner {
+            length,
+            min_distance,
+            metric,
+            constraints: &[],
+        };
+        let mut min = usize::MAX;
+        for (i, a) in set.barcodes.iter().enumerate() {
+            assert_eq!(a.len(), length);
+            for b in &set.barcodes[i + 1..] {
+                min = min.min(designer.distance(a, b));
+            }
+        }
+        assert!(min >= min_distance);
+        // The whole set score has to agree with the per pair distances
+        assert_eq!(set.min_pairwise_distance, Some(min));
+    }
+
+    #[test]
+    fn test_design_barcode_set() {
+        for metric in [MatchMetric::Hamming, MatchMetric::SequenceLevenshtein] {
+            let set = design_barcode_set(6, 3, metric, 24, &[], 7);
+            check_set(&set, 6, 3, metric);
+            assert!(set.barcodes.len() >= set.greedy_size);
+            assert_eq!(set, design_barcode_set(6, 3, metric, 24, &[], 7));
+        }
+
+        // Lexicodes are hard to beat, a target above what the greedy pass reaches just has to
+        // keep the greedy set
+        let constraints: Vec<Box<dyn BarcodeConstraint>> = vec![Box::new(NoRepeats)];
+        let set = design_barcode_set(5, 3, MatchMetric::Hamming, 10_000, &constraints, 1);
+        check_set(&set, 5, 3, MatchMetric::Hamming);
+        assert!(set.generations > 0);
+        assert!(set.barcodes.len() >= set.greedy_size);
+        assert!(set.barcodes.iter().all(|b| NoRepeats.check(b, &[]).is_ok()));
+    }
+
+    #[test]
+    fn test_design_long_hamming_set() {
+        // Past 42 bases the packed words span more than two u64s, past 84 more than one u64x4
+        for length in [63, 84, 100] {
+            let set = design_barcode_set(length, length / 2, MatchMetric::Hamming, 16, &[], 5);
+            check_set(&set, length, length / 2, MatchMetric::Hamming);
+            assert_eq!(set.barcodes.len(), 16);
+        }
+    }
+
+    #[test]
+    fn test_set_constraints_hold_after_removals() {
+        use crate::algos::barcode_constraints::TwoChannelBalance;
+
+        let constraints: Vec<Box<dyn BarcodeConstraint>> =
+            vec![Box::new(TwoChannelBalance::new(0.5))];
+        let designer = SetDesigner {
+            length: 6,
+            min_distance: 2,
+            metric: MatchMetric::Hamming,
+            constraints: &constraints,
+        };
+        for seed in 0..5 {
+            let set = design_barcode_set(6, 2, MatchMetric::Hamming, 48, &constraints, seed);
+            let mut barcodes = set.barcodes.clone();
+            assert!(
+                designer.satisfies_constraints(&mut barcodes),
+                "seed {}",
+                seed
+            );
+            assert_eq!(barcodes, set.barcodes);
+        }
+
+        // Two dark barcodes are allowed in four, dropping an A one leaves two dark in three
+        let mut set = vec![
+            b"AAAA".to_vec(),
+            b"AAAA".to_vec(),
+            b"GGGG".to_vec(),
+            b"GGGG".to_vec(),
+        ];
+        assert!(designer.satisfies_constraints(&mut set));
+        assert!(!designer.satisfies_constraints(&mut set[1..]));
+    }
+
+    #[test]
+    fn test_evolve_grows_set() {
+        let designer = SetDesigner {
+            length: 6,
+            min_distance: 3,
+            metric: MatchMetric::SequenceLevenshtein,
+            constraints: &[],
+        };
+        let mut rng = StdRng::seed_from_u64(11);
+        let (set, generations) = designer.evolve(vec![b"ACGTAC".to_vec()], 12, &mut rng);
+        assert_eq!(set.len(), 12);
+        assert!(generations > 0);
+        for (i, a) in set.iter().enumerate() {
+            for b in &set[i + 1..] {
+                assert!(designer.distance(a, b) >= 3);
+            }
+        }
+    }
+}
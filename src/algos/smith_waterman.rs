@@ -0,0 +1,485 @@
+use crate::algos::scoring::Scoring;
+use serde::{Deserialize, Serialize};
+use std::simd::cmp::{SimdOrd, SimdPartialOrd};
+use std::simd::num::{SimdInt, SimdUint};
+use std::simd::*;
+
+/*
+Striped Smith-Waterman (Farrar 2007) with affine gaps
+The query (adapter, linker) is laid out in a striped profile: with L lanes and
+    seg_len = ceil(query_len / L), segment i holds query positions i, i + seg_len, i + 2 seg_len
+    in its lanes, so the vertical dependency inside a column only crosses lanes at the end
+    of the column
+One pass per target base updates H (best score ending here), E (gap in the query, horizontal)
+    and F (gap in the target, vertical) for every segment, F is first propagated inside each
+    lane and then fixed up by the lazy F loop, which shifts F one lane up and continues only
+    while it can still raise some H
+Two lane types, both fill a 256 bit register:
+    u8 x 32 for local alignment, scores are kept >= 0 by saturation and the profile is biased
+        by the lowest substitution score so it stays unsigned, the score overflows past ~255
+    i16 x 16 when the u8 pass overflows and for semi-global alignment
+Semi-global (glocal): the whole query has to align, the target ends are free, this is what
+    locating an adapter inside a read looks like, scores go negative so it only runs on i16
+Starts come from a second pass over the reversed query and target prefixes that end at the
+    best cell, as in SSW
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlignmentMode {
+    Local,
+    SemiGlobal,
+}
+
+// Aligned ranges are query[query_start..query_end] and target[target_start..target_end]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StripedAlignment {
+    pub score: i32,
+    pub query_start: usize,
+    pub query_end: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+}
+
+// Saturating lanes for the striped kernel
+trait StripedLanes: Copy {
+    const LANES: usize;
+    // Lowest and highest lane values, the lowest stands in for -inf
+    const MIN: i32;
+    const MAX: i32;
+    fn splat(value: i32) -> Self;
+    fn from_fn<F: FnMut(usize) -> i32>(f: F) -> Self;
+    fn adds(self, other: Self) -> Self;
+    fn subs(self, other: Self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn any_gt(self, other: Self) -> bool;
+    fn reduce_max(self) -> i32;
+    fn lane(self, lane: usize) -> i32;
+    // Lane l moves to lane l + 1, lane 0 gets value
+    fn shift_in(self, value: i32) -> Self;
+}
+
+macro_rules! impl_striped_lanes {
+    ($($t:ty, $n:expr, $num:ident);*) => {
+        $(
+            impl StripedLanes for Simd<$t, $n> {
+                const LANES: usize = $n;
+                const MIN: i32 = <$t>::MIN as i32;
+                const MAX: i32 = <$t>::MAX as i32;
+                #[inline(always)]
+                fn splat(value: i32) -> Self {
+                    Simd::splat(value.clamp(Self::MIN, Self::MAX) as $t)
+                }
+                fn from_fn<F: FnMut(usize) -> i32>(mut f: F) -> Self {
+                    Simd::from_array(std::array::from_fn(|lane| {
+                        f(lane).clamp(Self::MIN, Self::MAX) as $t
+                    }))
+                }
+                #[inline(always)]
+                fn adds(self, other: Self) -> Self {
+                    self.saturating_add(other)
+                }
+                #[inline(always)]
+                fn subs(self, other: Self) -> Self {
+                    self.saturating_sub(other)
+                }
+                #[inline(always)]
+                fn max(self, other: Self) -> Self {
+                    self.simd_max(other)
+                }
+                #[inline(always)]
+                fn any_gt(self, other: Self) -> bool {
+                    self.simd_gt(other).any()
+                }
+                #[inline(always)]
+                fn reduce_max(self) -> i32 {
+                    <Self as $num>::reduce_max(self) as i32
+                }
+                #[inline(always)]
+                fn lane(self, lane: usize) -> i32 {
+                    self[lane] as i32
+                }
+                #[inline(always)]
+                fn shift_in(self, value: i32) -> Self {
+                    let mut shifted = self.rotate_elements_right::<1>();
+                    shifted[0] = value.clamp(Self::MIN, Self::MAX) as $t;
+                    shifted
+                }
+            }
+        )*
+    };
+}
+
+impl_striped_lanes!(u8, 32, SimdUint; i16, 16, SimdInt);
+
+// Best score of one pass with its end, ends are exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ScanResult {
+    score: i32,
+    query_end: usize,
+    target_end: usize,
+}
+
+pub struct StripedAligner {
+    query: Vec<u8>,
+    scoring: Scoring,
+    mode: AlignmentMode,
+    // Profile index of every byte, 0 is the profile of bases that are not in the query
+    codes: [u8; 256],
+    profile_u8: Vec<u8x32>,
+    profile_i16: Vec<i16x16>,
+}
+
+impl StripedAligner {
+    pub fn new(query: &[u8], scoring: Scoring, mode: AlignmentMode) -> Self {
+        assert!(!query.is_empty(), "Query has to be non-empty");
+        assert!(
+            scoring.substitution.max_score() > 0,
+            "Some substitution has to score above 0"
+        );
+        let longest = query.len() as i64 + 1;
+        let widest = scoring.substitution.max_score().max(scoring.gap_open) as i64;
+        assert!(
+            longest * widest < i16::MAX as i64 / 2,
+            "Query of {} bases could overflow 16 bit scores",
+            query.len()
+        );
+
+        // Bytes in neither the query nor the matrix all score the fallback mismatch
+        let mut codes = [0u8; 256];
+        let mut alphabet = vec![None];
+        for &base in query.iter().chain(scoring.substitution.alphabet()) {
+            if codes[base as usize] == 0 {
+                codes[base as usize] = alphabet.len() as u8;
+                alphabet.push(Some(base));
+            }
+        }
+
+        let mut aligner = StripedAligner {
+            query: query.to_vec(),
+            scoring,
+            mode,
+            codes,
+            profile_u8: Vec::new(),
+            profile_i16: Vec::new(),
+        };
+        aligner.profile_u8 = aligner.build_profile(&alphabet, aligner.bias());
+        aligner.profile_i16 = aligner.build_profile(&alphabet, 0);
+        aligner
+    }
+
+    pub fn query(&self) -> &[u8] {
+        &self.query
+    }
+
+    pub fn mode(&self) -> AlignmentMode {
+        self.mode
+    }
+
+    // Lifts the lowest substitution score to 0 for the unsigned lanes
+    fn bias(&self) -> i32 {
+        (-self.scoring.substitution.min_score()).max(0)
+    }
+
+    fn seg_len<V: StripedLanes>(&self) -> usize {
+        self.query.len().div_ceil(V::LANES)
+    }
+
+    // One seg_len block per alphabet entry, padding rows past the query get the lowest score
+    fn build_profile<V: StripedLanes>(&self, alphabet: &[Option<u8>], bias: i32) -> Vec<V> {
+        let seg_len = self.seg_len::<V>();
+        let mut profile = Vec::with_capacity(alphabet.len() * seg_len);
+        for &base in alphabet {
+            for segment in 0..seg_len {
+                profile.push(V::from_fn(|lane| {
+                    let row = lane * seg_len + segment;
+                    match (self.query.get(row), base) {
+                        (Some(&q), Some(b)) => self.scoring.substitution(q, b) + bias,
+                        (Some(_), None) => self.scoring.substitution.mismatch_score() + bias,
+                        (None, _) => self.scoring.substitution.min_score() + bias,
+                    }
+                }));
+            }
+        }
+        profile
+    }
+
+    // Best alignment of the query in the target, None when nothing scores above 0 (local) or
+    //     the target is empty
+    pub fn align(&self, target: &[u8]) -> Option<StripedAlignment> {
+        let end = self.scan_best(target)?;
+
+        // Reverse pass over the prefixes ending at the best cell finds the start
+        let query_prefix: Vec<u8> = self.query[..end.query_end].iter().rev().copied().collect();
+        let target_prefix: Vec<u8> = target[..end.target_end].iter().rev().copied().collect();
+        let reverse = StripedAligner::new(&query_prefix, self.scoring.clone(), self.mode);
+        let start = reverse.scan_best(&target_prefix)?;
+
+        Some(StripedAlignment {
+            score: end.score,
+            query_start: end.query_end - start.query_end,
+            query_end: end.query_end,
+            target_start: end.target_end - start.target_end,
+            target_end: end.target_end,
+        })
+    }
+
+    // Local alignment tries u8 lanes first and reruns on i16 when they overflow
+    fn scan_best(&self, target: &[u8]) -> Option<ScanResult> {
+        if target.is_empty() {
+            return None;
+        }
+        let result = match self.mode {
+            AlignmentMode::Local => self
+                .scan(&self.profile_u8, self.bias(), target)
+                .or_else(|| self.scan(&self.profile_i16, 0, target)),
+            AlignmentMode::SemiGlobal => self.scan(&self.profile_i16, 0, target),
+        }
+        .expect("Scores are bounded to fit in 16 bit lanes");
+        match self.mode {
+            AlignmentMode::Local if result.score <= 0 => None,
+            _ => Some(result),
+        }
+    }
+
+    // The striped pass, None when the lanes overflow
+    fn scan<V: StripedLanes>(&self, profile: &[V], bias: i32, target: &[u8]) -> Option<ScanResult> {
+        let local = self.mode == AlignmentMode::Local;
+        let query_len = self.query.len();
+        let seg_len = self.seg_len::<V>();
+        let gap_open = V::splat(self.scoring.gap_open);
+        let gap_extend = V::splat(self.scoring.gap_extend);
+        let bias_lanes = V::splat(bias);
+        let zero = V::splat(0);
+        let neg_inf = V::splat(V::MIN);
+        // Any column max this close to the top could have saturated
+        let overflow = V::MAX - self.scoring.substitution.max_score() - bias;
+
+        // Column -1: empty target, semi-global pays for deleting the query prefix
+        let mut h_store: Vec<V> = (0..seg_len)
+            .map(|segment| match self.mode {
+                AlignmentMode::Local => zero,
+                AlignmentMode::SemiGlobal => {
+                    V::from_fn(|lane| -self.scoring.gap(lane * seg_len + segment + 1))
+                }
+            })
+            .collect();
+        let mut h_load = h_store.clone();
+        let mut e = vec![neg_inf; seg_len];
+        let mut best_column = h_store.clone();
+        let mut best = ScanResult {
+            score: V::MIN,
+            query_end: 0,
+            target_end: 0,
+        };
+        let last_segment = (query_len - 1) % seg_len;
+        let last_lane = (query_len - 1) / seg_len;
+
+        for (j, &base) in target.iter().enumerate() {
+            let code = self.codes[base as usize] as usize;
+            let column_profile = &profile[code * seg_len..(code + 1) * seg_len];
+
+            // Row -1 is 0 in every column, it feeds the diagonal of row 0 and a gap into it
+            let mut f = V::from_fn(|lane| match lane {
+                0 => -self.scoring.gap_open,
+                _ => V::MIN,
+            });
+            let mut h = h_store[seg_len - 1].shift_in(0);
+            std::mem::swap(&mut h_load, &mut h_store);
+            let mut column_max = neg_inf;
+            for segment in 0..seg_len {
+                h = h.adds(column_profile[segment]).subs(bias_lanes);
+                h = h.max(e[segment]).max(f);
+                if local {
+                    h = h.max(zero);
+                }
+                column_max = column_max.max(h);
+                h_store[segment] = h;
+                let open = h.subs(gap_open);
+                e[segment] = e[segment].subs(gap_extend).max(open);
+                f = f.subs(gap_extend).max(open);
+                h = h_load[segment];
+            }
+
+            // Lazy F, carry the gap from the bottom of each lane into the next one
+            f = f.shift_in(V::MIN);
+            let mut segment = 0;
+            while f.any_gt(h_store[segment].subs(gap_open)) {
+                h_store[segment] = h_store[segment].max(f);
+                column_max = column_max.max(h_store[segment]);
+                e[segment] = e[segment].max(h_store[segment].subs(gap_open));
+                f = f.subs(gap_extend);
+                segment += 1;
+                if segment == seg_len {
+                    segment = 0;
+                    f = f.shift_in(V::MIN);
+                }
+            }
+
+            let column_best = column_max.reduce_max();
+            if column_best >= overflow {
+                return None;
+            }
+            match self.mode {
+                AlignmentMode::Local => {
+                    if column_best > best.score {
+                        best.score = column_best;
+                        best.target_end = j + 1;
+                        best_column.copy_from_slice(&h_store);
+                    }
+                }
+                AlignmentMode::SemiGlobal => {
+                    let score = h_store[last_segment].lane(last_lane);
+                    if score > best.score {
+                        best = ScanResult {
+                            score,
+                            query_end: query_len,
+                            target_end: j + 1,
+                        };
+                    }
+                }
+            }
+        }
+
+        if local {
+            // First query position holding the best score in the best column
+            best.query_end = (0..query_len)
+                .find(|&row| best_column[row % seg_len].lane(row / seg_len) == best.score)
+                .map_or(0, |row| row + 1);
+        }
+        Some(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::scoring::SubstitutionMatrix;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // Gotoh with the same gap model, returns the best score
+    fn gotoh(query: &[u8], target: &[u8], scoring: &Scoring, mode: AlignmentMode) -> i32 {
+        let n = query.len();
+        let m = target.len();
+        let neg_inf = i32::MIN / 4;
+        let local = mode == AlignmentMode::Local;
+        // h[i][j], query on the rows
+        let mut h = vec![vec![0; m + 1]; n + 1];
+        let mut e = vec![vec![neg_inf; m + 1]; n + 1];
+        let mut f = vec![vec![neg_inf; m + 1]; n + 1];
+        for i in 1..=n {
+            h[i][0] = if local { 0 } else { -scoring.gap(i) };
+        }
+        let mut best = neg_inf;
+        for i in 1..=n {
+            for j in 1..=m {
+                e[i][j] = (e[i][j - 1] - scoring.gap_extend).max(h[i][j - 1] - scoring.gap_open);
+                f[i][j] = (f[i - 1][j] - scoring.gap_extend).max(h[i - 1][j] - scoring.gap_open);
+                let diagonal = h[i - 1][j - 1] + scoring.substitution(query[i - 1], target[j - 1]);
+                h[i][j] = diagonal.max(e[i][j]).max(f[i][j]);
+                if local {
+                    h[i][j] = h[i][j].max(0);
+                    best = best.max(h[i][j]);
+                }
+            }
+        }
+        if local {
+            best
+        } else {
+            *h[n][1..].iter().max().unwrap()
+        }
+    }
+
+    fn random_pair(rng: &mut StdRng, query_len: usize, target_len: usize) -> (Vec<u8>, Vec<u8>) {
+        let query: Vec<u8> = (0..query_len)
+            .map(|_| b"ACGT"[rng.gen_range(0..4)])
+            .collect();
+        let mut target: Vec<u8> = (0..target_len)
+            .map(|_| b"ACGT"[rng.gen_range(0..4)])
+            .collect();
+        // Plant a mutated copy of the query
+        if target_len > query_len {
+            let at = rng.gen_range(0..target_len - query_len);
+            for (offset, &base) in query.iter().enumerate() {
+                if rng.gen_range(0..8) != 0 {
+                    target[at + offset] = base;
+                }
+            }
+            if rng.gen_bool(0.5) {
+                target.remove(at + query_len / 2);
+            }
+        }
+        (query, target)
+    }
+
+    #[test]
+    fn test_striped_matches_gotoh() {
+        let mut rng = StdRng::seed_from_u64(22);
+        let scorings = [
+            Scoring::default(),
+            Scoring::new(2, 3, 5, 2),
+            Scoring::new(1, 1, 1, 1),
+            Scoring::with_matrix(SubstitutionMatrix::dna(2, -1, -3), 5, 2),
+        ];
+        for round in 0..200 {
+            let query_len = rng.gen_range(1..=70);
+            let target_len = rng.gen_range(1..=150);
+            let (query, target) = random_pair(&mut rng, query_len, target_len);
+            let scoring = &scorings[round % scorings.len()];
+
+            for mode in [AlignmentMode::Local, AlignmentMode::SemiGlobal] {
+                let aligner = StripedAligner::new(&query, scoring.clone(), mode);
+                let expected = gotoh(&query, &target, scoring, mode);
+                let alignment = aligner.align(&target);
+                if mode == AlignmentMode::Local && expected == 0 {
+                    assert_eq!(alignment, None);
+                    continue;
+                }
+                let alignment = alignment.unwrap();
+                assert_eq!(alignment.score, expected, "{:?} {:?}", mode, scoring);
+
+                // The reported ranges hold an alignment with the same score
+                let query_range = &query[alignment.query_start..alignment.query_end];
+                let target_range = &target[alignment.target_start..alignment.target_end];
+                assert_eq!(gotoh(query_range, target_range, scoring, mode), expected);
+                if mode == AlignmentMode::SemiGlobal {
+                    assert_eq!(
+                        (alignment.query_start, alignment.query_end),
+                        (0, query.len())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_striped_u8_overflow_falls_back_to_i16() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let scoring = Scoring::new(3, 4, 6, 1);
+        let query: Vec<u8> = (0..150).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+        let mut target: Vec<u8> = (0..40).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+        target.extend(&query);
+        target.extend((0..40).map(|_| b"ACGT"[rng.gen_range(0..4)]));
+
+        let aligner = StripedAligner::new(&query, scoring, AlignmentMode::Local);
+        assert_eq!(
+            aligner.scan(&aligner.profile_u8, aligner.bias(), &target),
+            None
+        );
+        let alignment = aligner.align(&target).unwrap();
+        assert_eq!(alignment.score, 450);
+        assert_eq!((alignment.query_start, alignment.query_end), (0, 150));
+        assert_eq!((alignment.target_start, alignment.target_end), (40, 190));
+    }
+
+    #[test]
+    fn test_semi_global_locates_adapter() {
+        let adapter = b"AGATCGGAAGAGC";
+        let read = b"TTGCAGGCATTACAGATCGGAAAGAGCTTTT";
+        let aligner = StripedAligner::new(adapter, Scoring::default(), AlignmentMode::SemiGlobal);
+        let alignment = aligner.align(read).unwrap();
+        // 13 matches and an extra A in the read
+        assert_eq!(alignment.score, 13 - 6);
+        assert_eq!((alignment.target_start, alignment.target_end), (13, 27));
+    }
+}
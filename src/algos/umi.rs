@@ -0,0 +1,405 @@
+use crate::algos::distances::{Distance, HammingDistanceSimd};
+use crate::algos::fastq::FastqRecord;
+use crate::algos::iupac::{NPolicy, IUPAC_CODES};
+use fxhash::{FxHashMap, FxHashSet};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/*
+UMI deduplication
+Reads are grouped by (cell barcode, mapping key), the mapping key is whatever identifies the
+    alignment position for the caller (gene, "chr1:12345:+", ...)
+Inside a group the distinct UMIs form a graph, two UMIs are connected when their Hamming
+    distance is within max distance (1 in practice), PCR and sequencing errors on a UMI show up
+    as low count satellites of the true UMI
+The graph is collapsed into molecules with one of three methods, same semantics as UMI-tools:
+    Cluster      connected components, one molecule per component
+    Adjacency    per component, the fewest top count UMIs whose neighborhoods cover it
+    Directional  edge a -> b only when count(a) >= 2 * count(b) - 1, components are walked
+                 from the highest count UMI along those edges
+Each molecule keeps the first read seen with its representative (highest count) UMI
+N and the other IUPAC codes in a UMI follow the NPolicy (see iupac.rs), with the default
+    NPolicy::Mismatch an N is one more mismatch and the UMI is still counted
+UMIs with bytes that are not IUPAC codes are skipped
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DedupMethod {
+    Cluster,
+    Adjacency,
+    Directional,
+}
+
+// UMI-tools style read names, "<read id>_<UMI> <comment>"
+pub fn header_umi(id: &[u8]) -> Option<&[u8]> {
+    let name = id.split(|b| b.is_ascii_whitespace()).next()?;
+    let underscore = name.iter().rposition(|&b| b == b'_')?;
+    Some(&name[underscore + 1..]).filter(|umi| !umi.is_empty())
+}
+
+// Cuts the UMI out of the read sequence and appends it to the read name, as header_umi expects
+pub fn extract_umi(record: &mut FastqRecord, start: usize, length: usize) -> Option<Vec<u8>> {
+    if start + length > record.seq.len() {
+        return None;
+    }
+    let umi: Vec<u8> = record.seq.drain(start..start + length).collect();
+    record.qual.drain(start..start + length);
+
+    let name_end = record
+        .id
+        .iter()
+        .position(|b| b.is_ascii_whitespace())
+        .unwrap_or(record.id.len());
+    let mut suffix = vec![b'_'];
+    suffix.extend_from_slice(&umi);
+    record.id.splice(name_end..name_end, suffix);
+    Some(umi)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UmiGroupCounts {
+    pub cell_barcode: String,
+    pub mapping_key: String,
+    pub reads: usize,
+    pub unique_umis: usize,
+    // Molecules after collapsing the UMI graph
+    pub molecules: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupResult {
+    // One read per molecule, groups in order of first appearance
+    pub kept_read_ids: Vec<String>,
+    pub groups: Vec<UmiGroupCounts>,
+    pub total_reads: usize,
+    // Reads dropped for an empty UMI or one with non IUPAC bytes
+    pub skipped_reads: usize,
+}
+
+struct UmiCount {
+    umi: Vec<u8>,
+    count: usize,
+    first_read_id: String,
+}
+
+struct UmiGroup {
+    cell_barcode: Vec<u8>,
+    mapping_key: String,
+    umi_ids: FxHashMap<Vec<u8>, usize>,
+    umis: Vec<UmiCount>,
+}
+
+pub struct UmiDeduplicator {
+    method: DedupMethod,
+    max_distance: usize,
+    n_policy: NPolicy,
+    group_ids: FxHashMap<(Vec<u8>, String), usize>,
+    groups: Vec<UmiGroup>,
+    total_reads: usize,
+    skipped_reads: usize,
+}
+
+impl UmiDeduplicator {
+    pub fn new(method: DedupMethod, max_distance: usize) -> Self {
+        UmiDeduplicator {
+            method,
+            max_distance,
+            n_policy: NPolicy::Mismatch,
+            group_ids: FxHashMap::default(),
+            groups: Vec::new(),
+            total_reads: 0,
+            skipped_reads: 0,
+        }
+    }
+
+    pub fn with_n_policy(mut self, n_policy: NPolicy) -> Self {
+        self.n_policy = n_policy;
+        self
+    }
+
+    pub fn method(&self) -> DedupMethod {
+        self.method
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    pub fn n_policy(&self) -> NPolicy {
+        self.n_policy
+    }
+
+    pub fn add(&mut self, read_id: &str, cell_barcode: &[u8], mapping_key: &str, umi: &[u8]) {
+        self.total_reads += 1;
+        if umi.is_empty() || !umi.iter().all(|b| IUPAC_CODES.contains(b)) {
+            self.skipped_reads += 1;
+            return;
+        }
+
+        let key = (cell_barcode.to_vec(), mapping_key.to_string());
+        let group_id = *self.group_ids.entry(key).or_insert_with(|| {
+            self.groups.push(UmiGroup {
+                cell_barcode: cell_barcode.to_vec(),
+                mapping_key: mapping_key.to_string(),
+                umi_ids: FxHashMap::default(),
+                umis: Vec::new(),
+            });
+            self.groups.len() - 1
+        });
+
+        let group = &mut self.groups[group_id];
+        let umi_id = *group.umi_ids.entry(umi.to_vec()).or_insert_with(|| {
+            group.umis.push(UmiCount {
+                umi: umi.to_vec(),
+                count: 0,
+                first_read_id: read_id.to_string(),
+            });
+            group.umis.len() - 1
+        });
+        group.umis[umi_id].count += 1;
+    }
+
+    pub fn finish(self) -> DedupResult {
+        let method = self.method;
+        let max_distance = self.max_distance;
+        let metric = HammingDistanceSimd::new().with_n_policy(self.n_policy);
+        let collapsed: Vec<(UmiGroupCounts, Vec<String>)> = self
+            .groups
+            .par_iter()
+            .map(|group| {
+                let molecules = collapse_group(&group.umis, method, max_distance, &metric);
+                let counts = UmiGroupCounts {
+                    cell_barcode: String::from_utf8_lossy(&group.cell_barcode).into_owned(),
+                    mapping_key: group.mapping_key.clone(),
+                    reads: group.umis.iter().map(|u| u.count).sum(),
+                    unique_umis: group.umis.len(),
+                    molecules: molecules.len(),
+                };
+                let kept = molecules
+                    .into_iter()
+                    .map(|id| group.umis[id].first_read_id.clone())
+                    .collect();
+                (counts, kept)
+            })
+            .collect();
+
+        let mut result = DedupResult {
+            kept_read_ids: Vec::new(),
+            groups: Vec::with_capacity(collapsed.len()),
+            total_reads: self.total_reads,
+            skipped_reads: self.skipped_reads,
+        };
+        for (counts, kept) in collapsed {
+            result.groups.push(counts);
+            result.kept_read_ids.extend(kept);
+        }
+        result
+    }
+}
+
+// Returns the representative UMI id of every molecule, highest count first
+fn collapse_group(
+    umis: &[UmiCount],
+    method: DedupMethod,
+    max_distance: usize,
+    metric: &HammingDistanceSimd,
+) -> Vec<usize> {
+    // Highest count first, ties on the UMI so the result never depends on read order
+    let mut order: Vec<usize> = (0..umis.len()).collect();
+    order.sort_by(|&a, &b| {
+        umis[b]
+            .count
+            .cmp(&umis[a].count)
+            .then_with(|| umis[a].umi.cmp(&umis[b].umi))
+    });
+
+    let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); umis.len()];
+    for a in 0..umis.len() {
+        for b in a + 1..umis.len() {
+            if metric.distance(umis[a].umi.as_slice(), umis[b].umi.as_slice()) <= max_distance {
+                neighbors[a].push(b);
+                neighbors[b].push(a);
+            }
+        }
+    }
+
+    match method {
+        DedupMethod::Cluster => components(&order, |_, _| true, &neighbors)
+            .into_iter()
+            .map(|component| component[0])
+            .collect(),
+        DedupMethod::Directional => components(
+            &order,
+            |from, to| umis[from].count >= 2 * umis[to].count - 1,
+            &neighbors,
+        )
+        .into_iter()
+        .map(|component| component[0])
+        .collect(),
+        DedupMethod::Adjacency => components(&order, |_, _| true, &neighbors)
+            .into_iter()
+            .flat_map(|component| min_cover(component, umis, &neighbors))
+            .collect(),
+    }
+}
+
+// Breadth first walks started from each unvisited UMI in count order, following only the
+// edges `follow` accepts, the first UMI of every component is its start
+fn components<F>(order: &[usize], follow: F, neighbors: &[Vec<usize>]) -> Vec<Vec<usize>>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut visited = vec![false; neighbors.len()];
+    let mut components = Vec::new();
+    for &start in order {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut component = vec![start];
+        let mut queue = VecDeque::from([start]);
+        while let Some(node) = queue.pop_front() {
+            for &next in &neighbors[node] {
+                if !visited[next] && follow(node, next) {
+                    visited[next] = true;
+                    component.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        components.push(component);
+    }
+    components
+}
+
+// Fewest UMIs, taken in count order, whose closed neighborhoods cover the whole component
+fn min_cover(mut component: Vec<usize>, umis: &[UmiCount], neighbors: &[Vec<usize>]) -> Vec<usize> {
+    component.sort_by(|&a, &b| {
+        umis[b]
+            .count
+            .cmp(&umis[a].count)
+            .then_with(|| umis[a].umi.cmp(&umis[b].umi))
+    });
+    let mut covered: FxHashSet<usize> = FxHashSet::default();
+    let mut selected = Vec::new();
+    for &umi in &component {
+        if covered.len() == component.len() {
+            break;
+        }
+        selected.push(umi);
+        covered.insert(umi);
+        for &next in &neighbors[umi] {
+            covered.insert(next);
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dedup(method: DedupMethod, umis: &[(&[u8], usize)]) -> DedupResult {
+        let mut deduplicator = UmiDeduplicator::new(method, 1);
+        for (i, &(umi, count)) in umis.iter().enumerate() {
+            for r in 0..count {
+                deduplicator.add(&format!("u{}r{}", i, r), b"CELL", "chr1:100:+", umi);
+            }
+        }
+        deduplicator.finish()
+    }
+
+    #[test]
+    fn test_methods_on_umi_tools_example() {
+        // The example network from the UMI-tools paper: a hub with two satellites, one of which
+        // has its own satellite, plus a separate pair
+        let umis: [(&[u8], usize); 6] = [
+            (b"ATAT", 456),
+            (b"ATTT", 90),
+            (b"ATAG", 72),
+            (b"TTTT", 2),
+            (b"CCCC", 10),
+            (b"CCCA", 2),
+        ];
+
+        let cluster = dedup(DedupMethod::Cluster, &umis);
+        assert_eq!(cluster.groups[0].molecules, 2);
+        assert_eq!(cluster.kept_read_ids, vec!["u0r0", "u4r0"]);
+
+        // ATAT covers ATTT and ATAG, TTTT needs its own pick
+        let adjacency = dedup(DedupMethod::Adjacency, &umis);
+        assert_eq!(adjacency.groups[0].molecules, 3);
+
+        // ATTT (90) vs TTTT (2): 90 >= 3 so TTTT folds in, CCCA (2) folds into CCCC (10)
+        let directional = dedup(DedupMethod::Directional, &umis);
+        assert_eq!(directional.groups[0].molecules, 2);
+        assert_eq!(directional.groups[0].unique_umis, 6);
+        assert_eq!(directional.groups[0].reads, 632);
+
+        // Comparable counts do not collapse under directional
+        let even = dedup(DedupMethod::Directional, &[(b"AAAA", 10), (b"AAAT", 8)]);
+        assert_eq!(even.groups[0].molecules, 2);
+        let even = dedup(DedupMethod::Cluster, &[(b"AAAA", 10), (b"AAAT", 8)]);
+        assert_eq!(even.groups[0].molecules, 1);
+    }
+
+    #[test]
+    fn test_groups_and_skipped_reads() {
+        let mut deduplicator = UmiDeduplicator::new(DedupMethod::Directional, 1);
+        deduplicator.add("r1", b"AAAA", "geneA", b"ACGT");
+        deduplicator.add("r2", b"AAAA", "geneA", b"ACGT");
+        deduplicator.add("r3", b"AAAA", "geneB", b"ACGT");
+        deduplicator.add("r4", b"CCCC", "geneA", b"ACGT");
+        deduplicator.add("r5", b"CCCC", "geneA", b"AC.T");
+        deduplicator.add("r6", b"CCCC", "geneA", b"");
+        let result = deduplicator.finish();
+
+        assert_eq!(result.kept_read_ids, vec!["r1", "r3", "r4"]);
+        assert_eq!(result.groups.len(), 3);
+        assert_eq!(result.groups[0].reads, 2);
+        assert_eq!(result.total_reads, 6);
+        assert_eq!(result.skipped_reads, 2);
+    }
+
+    #[test]
+    fn test_n_policy() {
+        let add_reads = |deduplicator: &mut UmiDeduplicator| {
+            for r in 0..10 {
+                deduplicator.add(&format!("a{}", r), b"CELL", "geneA", b"ACGTAC");
+            }
+            deduplicator.add("n1", b"CELL", "geneA", b"ACGNAC");
+            deduplicator.add("n2", b"CELL", "geneA", b"ACGNAT");
+        };
+
+        // N is a mismatch, ACGNAC folds into ACGTAC but ACGNAT is two away
+        let mut deduplicator = UmiDeduplicator::new(DedupMethod::Cluster, 1);
+        add_reads(&mut deduplicator);
+        let result = deduplicator.finish();
+        assert_eq!(result.skipped_reads, 0);
+        assert_eq!(result.kept_read_ids, vec!["a0", "n2"]);
+
+        let mut deduplicator =
+            UmiDeduplicator::new(DedupMethod::Cluster, 1).with_n_policy(NPolicy::Match);
+        add_reads(&mut deduplicator);
+        let result = deduplicator.finish();
+        assert_eq!(result.kept_read_ids, vec!["a0"]);
+        assert_eq!(result.groups[0].unique_umis, 3);
+    }
+
+    #[test]
+    fn test_extract_umi() {
+        let mut record = FastqRecord {
+            id: b"read1 1:N:0:ACGT".to_vec(),
+            seq: b"GGCCAAAATTTT".to_vec(),
+            qual: b"ABCDEFGHIJKL".to_vec(),
+        };
+        assert_eq!(extract_umi(&mut record, 0, 4), Some(b"GGCC".to_vec()));
+        assert_eq!(record.id, b"read1_GGCC 1:N:0:ACGT");
+        assert_eq!(record.seq, b"AAAATTTT");
+        assert_eq!(record.qual, b"EFGHIJKL");
+        assert_eq!(header_umi(&record.id), Some(&b"GGCC"[..]));
+        assert_eq!(header_umi(b"read1 comment_x"), None);
+        assert!(extract_umi(&mut record, 6, 4).is_none());
+    }
+}
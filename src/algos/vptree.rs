@@ -0,0 +1,254 @@
+use crate::algos::distances::{Distance, LengthRange};
+use std::borrow::Borrow;
+use std::collections::BinaryHeap;
+
+/*
+Vantage-point tree
+Each node picks a vantage point and splits the remaining items on the median distance mu:
+    inside holds items with d(vp, x) <= mu, outside holds items with d(vp, x) >= mu
+For a query at distance d from the vantage point and a current search radius tau
+    inside can only contain hits if d - tau <= mu
+    outside can only contain hits if d + tau >= mu
+tau starts at the caller's max radius and shrinks to the k-th best distance found so far,
+    so unlike the BK-tree we can answer "give me the 3 closest barcodes" directly
+Both tests go through Distance::lower_bound, which widens them for seq-lev
+Nodes are stored in a flat arena, items keep their input order as ids
+*/
+
+const NO_CHILD: usize = usize::MAX;
+
+struct VpNode {
+    item: usize,
+    mu: usize,
+    inside: usize,
+    outside: usize,
+}
+
+pub struct VpTree<T: ?Sized + ToOwned, D: Distance<T>> {
+    items: Vec<T::Owned>,
+    nodes: Vec<VpNode>,
+    root: usize,
+    lengths: LengthRange,
+    metric: D,
+}
+
+impl<T: ?Sized + ToOwned, D: Distance<T>> VpTree<T, D> {
+    pub fn build(metric: D, items: Vec<T::Owned>) -> Self {
+        let mut lengths = LengthRange::new();
+        for item in items.iter() {
+            lengths.insert(metric.length(item.borrow()));
+        }
+        let mut tree = VpTree {
+            nodes: Vec::with_capacity(items.len()),
+            items,
+            root: NO_CHILD,
+            lengths,
+            metric,
+        };
+        let mut ids: Vec<usize> = (0..tree.items.len()).collect();
+        tree.root = tree.build_node(&mut ids);
+        tree
+    }
+
+    // The first id of the partition is the vantage point, this keeps builds deterministic
+    fn build_node(&mut self, ids: &mut [usize]) -> usize {
+        if ids.is_empty() {
+            return NO_CHILD;
+        }
+
+        let vp = ids[0];
+        let rest = &mut ids[1..];
+        let mut by_distance: Vec<(usize, usize)> = rest
+            .iter()
+            .map(|&id| {
+                (
+                    self.metric
+                        .distance(self.items[vp].borrow(), self.items[id].borrow()),
+                    id,
+                )
+            })
+            .collect();
+        by_distance.sort_unstable();
+        for (slot, &(_, id)) in rest.iter_mut().zip(by_distance.iter()) {
+            *slot = id;
+        }
+
+        let split = rest.len() / 2;
+        let mu = by_distance.get(split).map_or(0, |&(d, _)| d);
+        let node = self.nodes.len();
+        self.nodes.push(VpNode {
+            item: vp,
+            mu,
+            inside: NO_CHILD,
+            outside: NO_CHILD,
+        });
+
+        let (inside_ids, outside_ids) = rest.split_at_mut(split);
+        let inside = self.build_node(inside_ids);
+        let outside = self.build_node(outside_ids);
+        self.nodes[node].inside = inside;
+        self.nodes[node].outside = outside;
+        node
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.items.get(id).map(|item| item.borrow())
+    }
+
+    // Up to k items within max_radius of the query as (id, distance)
+    // Sorted by distance then id, pass usize::MAX as max_radius for an unbounded search
+    pub fn nearest(&self, query: &T, k: usize, max_radius: usize) -> Vec<(usize, usize)> {
+        if k == 0 || self.root == NO_CHILD {
+            return Vec::new();
+        }
+
+        // Max heap on (distance, id), the top is the worst of the current best k
+        let spread = self.lengths.spread(self.metric.length(query));
+        let mut best: BinaryHeap<(usize, usize)> = BinaryHeap::with_capacity(k + 1);
+        let mut stack = vec![self.root];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let d = self.metric.distance(self.items[node.item].borrow(), query);
+            if d <= max_radius {
+                best.push((d, node.item));
+                if best.len() > k {
+                    best.pop();
+                }
+            }
+
+            // Inclusive bounds, an equal distance can still win the tie on id
+            let tau = if best.len() == k {
+                best.peek().unwrap().0
+            } else {
+                max_radius
+            };
+            let search_inside =
+                node.inside != NO_CHILD && self.metric.lower_bound(d, 0..=node.mu, spread) <= tau;
+            let search_outside = node.outside != NO_CHILD
+                && self.metric.lower_bound(d, node.mu..=usize::MAX, spread) <= tau;
+
+            // Push the less promising side first so the likely side is popped first
+            if d <= node.mu {
+                if search_outside {
+                    stack.push(node.outside);
+                }
+                if search_inside {
+                    stack.push(node.inside);
+                }
+            } else {
+                if search_inside {
+                    stack.push(node.inside);
+                }
+                if search_outside {
+                    stack.push(node.outside);
+                }
+            }
+        }
+
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|(d, id)| (id, d))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algos::distances::{
+        HammingDistanceSimd, LevenshteinDistance, SequenceLevenshteinDistance,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_nearest_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let random_barcode = |rng: &mut StdRng| -> Vec<u8> {
+            (0..12).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect()
+        };
+        let items: Vec<Vec<u8>> = (0..500).map(|_| random_barcode(&mut rng)).collect();
+        let tree: VpTree<[u8], _> = VpTree::build(HammingDistanceSimd::new(), items.clone());
+        assert_eq!(tree.len(), 500);
+
+        let metric = HammingDistanceSimd::new();
+        for _ in 0..25 {
+            let query = random_barcode(&mut rng);
+            let mut expected: Vec<(usize, usize)> = items
+                .iter()
+                .enumerate()
+                .map(|(id, item)| (id, metric.distance(item.as_slice(), query.as_slice())))
+                .collect();
+            expected.sort_unstable_by_key(|&(id, d)| (d, id));
+
+            let top: Vec<(usize, usize)> = expected.iter().copied().take(3).collect();
+            assert_eq!(tree.nearest(&query, 3, usize::MAX), top);
+
+            let bounded: Vec<(usize, usize)> = expected
+                .iter()
+                .copied()
+                .filter(|&(_, d)| d <= 5)
+                .take(10)
+                .collect();
+            assert_eq!(tree.nearest(&query, 10, 5), bounded);
+        }
+    }
+
+    #[test]
+    fn test_seq_lev_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(19);
+        let mut random_sequence = |min: usize, max: usize| -> Vec<u8> {
+            let length = rng.gen_range(min..=max);
+            (0..length).map(|_| b"ACGT"[rng.gen_range(0..2)]).collect()
+        };
+        let items: Vec<Vec<u8>> = (0..300).map(|_| random_sequence(6, 10)).collect();
+        let queries: Vec<Vec<u8>> = (0..50).map(|_| random_sequence(3, 12)).collect();
+        let metric = SequenceLevenshteinDistance::new();
+        let tree: VpTree<[u8], _> =
+            VpTree::build(SequenceLevenshteinDistance::new(), items.clone());
+
+        for query in queries {
+            let mut expected: Vec<(usize, usize)> = items
+                .iter()
+                .enumerate()
+                .map(|(id, item)| (id, metric.distance(item.as_slice(), query.as_slice())))
+                .collect();
+            expected.sort_unstable_by_key(|&(id, d)| (d, id));
+
+            let top: Vec<(usize, usize)> = expected.iter().copied().take(3).collect();
+            assert_eq!(tree.nearest(&query, 3, usize::MAX), top);
+            let bounded: Vec<(usize, usize)> = expected
+                .iter()
+                .copied()
+                .filter(|&(_, d)| d <= 2)
+                .take(10)
+                .collect();
+            assert_eq!(tree.nearest(&query, 10, 2), bounded);
+        }
+    }
+
+    #[test]
+    fn test_ambiguity_margin() {
+        let whitelist = vec![
+            "AAAACCCC".to_string(),
+            "AAAAGGGG".to_string(),
+            "TTTTCCCC".to_string(),
+            "ACACACAC".to_string(),
+        ];
+        let tree: VpTree<String, _> = VpTree::build(LevenshteinDistance::new(), whitelist);
+
+        let hits = tree.nearest(&"AAAACCCG".to_string(), 2, 3);
+        assert_eq!(hits, vec![(0, 1), (1, 3)]);
+        // Margin between best and runner up
+        assert_eq!(hits[1].1 - hits[0].1, 2);
+        assert!(tree.nearest(&"GGGGGGGG".to_string(), 3, 2).is_empty());
+    }
+}
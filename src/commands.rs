@@ -1 +1,843 @@
-// placeholder for later use in benchmarking & demos
+use algos_n_stuff::algos::barcode_matcher::MatchMetric;
+use algos_n_stuff::algos::barcode_validation::{
+    validate_barcode_set, DistanceStats, ValidationReport,
+};
+use algos_n_stuff::algos::demux::{
+    parse_dual_index_sheet, parse_sample_sheet, Assignment, Demultiplexer, DemuxConfig,
+    DemuxSummary, DualAssignment, DualIndexDemultiplexer, DualIndexSummary,
+};
+use algos_n_stuff::algos::fastq::{
+    create_fastq, header_indices, open_fastq, FastqReader, FastqRecord, FastqWriter,
+};
+use algos_n_stuff::algos::iupac::NPolicy;
+use algos_n_stuff::algos::umi::{header_umi, DedupMethod, DedupResult, UmiDeduplicator};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/*
+Command line entry points, each command parses its own flags and returns a message on error
+Kept dependency free on purpose, the flags are simple enough to parse by hand
+*/
+
+const USAGE: &str = "Usage: algos_n_stuff <command> [options]
+
+Commands:
+  demux    Demultiplex a FASTQ file by barcode
+  dedup    Deduplicate reads on UMIs per cell barcode and mapping position
+  validate Report pairwise distances, conflicts and base balance of a barcode whitelist
+
+Run `algos_n_stuff <command> --help` for command options";
+
+const DEMUX_USAGE: &str = "Usage: algos_n_stuff demux --input <reads.fastq[.gz]> --samples <sheet.csv> --out-dir <dir> [options]
+
+Options:
+  --input <path>          FASTQ input, plain or gzip
+  --samples <path>        Sample sheet, one `name,barcode` per line (`name,i7,i5` with --dual-index)
+  --out-dir <path>        Output directory for per-sample FASTQ files and summary.json
+  --barcode-start <n>     Offset of the barcode in the read (default 0)
+  --metric <name>         seqlev or hamming (default seqlev)
+  --max-distance <k>      Max corrected distance, per index with --dual-index (default 1)
+  --n-policy <name>       mismatch or match, whether an N in a read or sheet matches every base
+                          (default mismatch), other IUPAC codes always match their bases
+  --min-posterior <p>     Score candidates with base qualities and only assign reads whose best
+                          sample reaches this posterior (single index only)
+  --gzip                  Gzip the output FASTQ files
+
+Dual index:
+  --dual-index            Assign on (i7, i5) pairs and report index hopping in index_hopping.tsv
+  --i7 <path>             i7 index reads (I1), defaults to the indexes in the read header
+  --i5 <path>             i5 index reads (I2), required together with --i7";
+
+const DEDUP_USAGE: &str = "Usage: algos_n_stuff dedup --input <reads.tsv> --out-dir <dir> [options]
+
+Options:
+  --input <path>          Tab separated `read_id cell_barcode mapping_key [umi]` per line,
+                          without the umi column it is taken from the `<read id>_<UMI>` name
+  --out-dir <path>        Output directory for kept_reads.txt, umi_counts.tsv and summary.json
+  --method <name>         directional, adjacency or cluster (default directional)
+  --max-distance <k>      Hamming distance that links two UMIs (default 1)
+  --n-policy <name>       mismatch or match, whether an N in a UMI matches every base
+                          (default mismatch), UMIs with non IUPAC bytes are skipped";
+
+const VALIDATE_USAGE: &str = "Usage: algos_n_stuff validate --input <whitelist.txt> [options]
+
+Options:
+  --input <path>          One barcode per line, empty lines and `#` comments are skipped
+  --threshold <k>         List pairs closer than k in Hamming or seq-lev distance (default 3)
+  --out <path>            Also write the report as JSON
+  --json                  Print JSON instead of the table";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args.first().map(|s| s.as_str()) {
+        Some("demux") => demux(&args[1..]),
+        Some("dedup") => dedup(&args[1..]),
+        Some("validate") => validate(&args[1..]),
+        Some("-h") | Some("--help") | None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(format!("Unknown command {:?}\n\n{}", other, USAGE)),
+    }
+}
+
+// Splits `--flag value` pairs and bare `--switch` flags
+struct Flags {
+    values: Vec<(String, String)>,
+    switches: Vec<String>,
+}
+
+impl Flags {
+    fn parse(args: &[String], switches: &[&str]) -> Result<Self, String> {
+        let mut flags = Flags {
+            values: Vec::new(),
+            switches: Vec::new(),
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") && arg != "-h" {
+                return Err(format!("Unexpected argument {:?}", arg));
+            }
+            if switches.contains(&arg.as_str()) || arg == "-h" || arg == "--help" {
+                flags.switches.push(arg.clone());
+            } else {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))?;
+                flags.values.push((arg.clone(), value.clone()));
+            }
+        }
+        Ok(flags)
+    }
+
+    fn has(&self, switch: &str) -> bool {
+        self.switches.iter().any(|s| s == switch)
+    }
+
+    fn get(&self, flag: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(f, _)| f == flag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn required(&self, flag: &str) -> Result<&str, String> {
+        self.get(flag)
+            .ok_or_else(|| format!("Missing required option {}", flag))
+    }
+
+    fn number(&self, flag: &str, default: usize) -> Result<usize, String> {
+        match self.get(flag) {
+            Some(v) => v
+                .parse()
+                .map_err(|_| format!("{} expects a number, got {:?}", flag, v)),
+            None => Ok(default),
+        }
+    }
+}
+
+fn parse_metric(name: &str) -> Result<MatchMetric, String> {
+    match name {
+        "seqlev" | "sequence-levenshtein" => Ok(MatchMetric::SequenceLevenshtein),
+        "hamming" => Ok(MatchMetric::Hamming),
+        _ => Err(format!(
+            "Unknown metric {:?}, expected seqlev or hamming",
+            name
+        )),
+    }
+}
+
+fn parse_n_policy(name: &str) -> Result<NPolicy, String> {
+    match name {
+        "mismatch" => Ok(NPolicy::Mismatch),
+        "match" => Ok(NPolicy::Match),
+        _ => Err(format!(
+            "Unknown N policy {:?}, expected mismatch or match",
+            name
+        )),
+    }
+}
+
+fn fastq_path(out_dir: &Path, name: &str, gzip: bool) -> PathBuf {
+    let extension = if gzip { "fastq.gz" } else { "fastq" };
+    out_dir.join(format!("{}.{}", name, extension))
+}
+
+fn check_sample_names<'a>(
+    names: impl Iterator<Item = &'a str>,
+    sample_sheet: &str,
+) -> Result<(), String> {
+    // Names become output file names, so they must be unique and stay inside --out-dir
+    let mut seen = HashSet::new();
+    for name in names {
+        if name == "undetermined" {
+            return Err("\"undetermined\" is reserved for unassigned reads".to_string());
+        }
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+            return Err(format!(
+                "Sample name {:?} can't be used as an output file name",
+                name
+            ));
+        }
+        if !seen.insert(name) {
+            return Err(format!(
+                "Sample {} appears more than once in {}",
+                name, sample_sheet
+            ));
+        }
+    }
+    if seen.is_empty() {
+        return Err(format!("Sample sheet {} has no samples", sample_sheet));
+    }
+    Ok(())
+}
+
+fn demux(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, &["--gzip", "--dual-index"])?;
+    if flags.has("--help") || flags.has("-h") {
+        println!("{}", DEMUX_USAGE);
+        return Ok(());
+    }
+
+    let input = Path::new(flags.required("--input")?);
+    let sample_sheet = flags.required("--samples")?;
+    let out_dir = PathBuf::from(flags.required("--out-dir")?);
+    let metric = parse_metric(flags.get("--metric").unwrap_or("seqlev"))?;
+    let max_distance = flags.number("--max-distance", 1)?;
+    let n_policy = parse_n_policy(flags.get("--n-policy").unwrap_or("mismatch"))?;
+    let gzip = flags.has("--gzip");
+    let index_reads = match (flags.get("--i7"), flags.get("--i5")) {
+        (Some(i7), Some(i5)) => Some((Path::new(i7), Path::new(i5))),
+        (None, None) => None,
+        _ => return Err("--i7 and --i5 must be given together".to_string()),
+    };
+    if index_reads.is_some() && !flags.has("--dual-index") {
+        return Err("--i7 and --i5 require --dual-index".to_string());
+    }
+    let min_posterior = match flags.get("--min-posterior") {
+        Some(v) => match v.parse::<f64>() {
+            Ok(p) if (0.0..=1.0).contains(&p) => Some(p),
+            _ => {
+                return Err(format!(
+                    "--min-posterior expects a probability between 0 and 1, got {:?}",
+                    v
+                ))
+            }
+        },
+        None => None,
+    };
+    if min_posterior.is_some() && flags.has("--dual-index") {
+        return Err("--min-posterior is not supported with --dual-index".to_string());
+    }
+
+    let sheet = File::open(sample_sheet)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open sample sheet {}: {}", sample_sheet, e))?;
+    let failed = |e: io::Error| format!("Demultiplexing failed: {}", e);
+
+    let json = if flags.has("--dual-index") {
+        let samples = parse_dual_index_sheet(sheet).map_err(|e| e.to_string())?;
+        check_sample_names(samples.iter().map(|s| s.name.as_str()), sample_sheet)?;
+        let demultiplexer =
+            DualIndexDemultiplexer::with_n_policy(samples, metric, max_distance, n_policy);
+        let summary = run_dual_index_demux(input, index_reads, &demultiplexer, &out_dir, gzip)
+            .map_err(failed)?;
+        write_index_hopping(&summary, &out_dir.join("index_hopping.tsv")).map_err(failed)?;
+        serde_json::to_string_pretty(&summary)
+    } else {
+        let samples = parse_sample_sheet(sheet).map_err(|e| e.to_string())?;
+        check_sample_names(samples.iter().map(|s| s.name.as_str()), sample_sheet)?;
+        let config = DemuxConfig {
+            barcode_start: flags.number("--barcode-start", 0)?,
+            metric,
+            max_distance,
+            n_policy,
+            min_posterior,
+        };
+        let demultiplexer = Demultiplexer::new(samples, config);
+        let summary = run_demux(input, &demultiplexer, &out_dir, gzip).map_err(failed)?;
+        serde_json::to_string_pretty(&summary)
+    }
+    .map_err(|e| e.to_string())?;
+
+    fs::write(out_dir.join("summary.json"), &json).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+// Streams the input into one FASTQ per sample plus undetermined
+// `assign` returns the sample id of a record, None sends it to undetermined
+fn split_reads<F>(
+    input: &Path,
+    names: &[&str],
+    out_dir: &Path,
+    gzip: bool,
+    mut assign: F,
+) -> io::Result<()>
+where
+    F: FnMut(&FastqRecord) -> io::Result<Option<usize>>,
+{
+    fs::create_dir_all(out_dir)?;
+    let mut writers: Vec<FastqWriter<File>> = names
+        .iter()
+        .map(|name| create_fastq(fastq_path(out_dir, name, gzip), gzip))
+        .collect::<io::Result<_>>()?;
+    let mut undetermined = create_fastq(fastq_path(out_dir, "undetermined", gzip), gzip)?;
+
+    for record in open_fastq(input)? {
+        let record = record?;
+        match assign(&record)? {
+            Some(sample_id) => writers[sample_id].write_record(&record)?,
+            None => undetermined.write_record(&record)?,
+        }
+    }
+
+    for writer in writers {
+        writer.finish()?;
+    }
+    undetermined.finish()?;
+    Ok(())
+}
+
+fn run_demux(
+    input: &Path,
+    demultiplexer: &Demultiplexer,
+    out_dir: &Path,
+    gzip: bool,
+) -> io::Result<DemuxSummary> {
+    let samples = demultiplexer.samples();
+    let names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+    let mut summary = DemuxSummary::new(samples);
+
+    split_reads(input, &names, out_dir, gzip, |record| {
+        let assignment = demultiplexer.assign_record(record);
+        summary.record(assignment);
+        Ok(match assignment {
+            Assignment::Sample { sample_id, .. } => Some(sample_id),
+            Assignment::Ambiguous | Assignment::Undetermined => None,
+        })
+    })?;
+    Ok(summary)
+}
+
+fn next_index_read(
+    reader: &mut FastqReader<Box<dyn BufRead>>,
+    name: &str,
+) -> io::Result<FastqRecord> {
+    reader.next().unwrap_or_else(|| {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} index reads ended before the input reads", name),
+        ))
+    })
+}
+
+fn run_dual_index_demux(
+    input: &Path,
+    index_reads: Option<(&Path, &Path)>,
+    demultiplexer: &DualIndexDemultiplexer,
+    out_dir: &Path,
+    gzip: bool,
+) -> io::Result<DualIndexSummary> {
+    let names: Vec<&str> = demultiplexer
+        .samples()
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect();
+    let mut summary = DualIndexSummary::new(demultiplexer);
+    let mut index_readers = match index_reads {
+        Some((i7, i5)) => Some((open_fastq(i7)?, open_fastq(i5)?)),
+        None => None,
+    };
+
+    split_reads(input, &names, out_dir, gzip, |record| {
+        let assignment = match &mut index_readers {
+            Some((i7_reader, i5_reader)) => {
+                let i7 = next_index_read(i7_reader, "i7")?;
+                let i5 = next_index_read(i5_reader, "i5")?;
+                demultiplexer.assign(&i7.seq, &i5.seq)
+            }
+            None => match header_indices(&record.id) {
+                Some((i7, i5)) => demultiplexer.assign(i7, i5),
+                None => DualAssignment::Undetermined,
+            },
+        };
+        summary.record(assignment);
+        Ok(match assignment {
+            DualAssignment::Sample { sample_id, .. } => Some(sample_id),
+            _ => None,
+        })
+    })?;
+    Ok(summary)
+}
+
+fn write_index_hopping(summary: &DualIndexSummary, path: &Path) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    writeln!(writer, "i7\ti5\ti7_samples\ti5_samples\treads")?;
+    for row in summary.index_hopping_table() {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            row.i7,
+            row.i5,
+            row.i7_samples.join(","),
+            row.i5_samples.join(","),
+            row.reads
+        )?;
+    }
+    writer.flush()
+}
+
+fn parse_dedup_method(name: &str) -> Result<DedupMethod, String> {
+    match name {
+        "directional" => Ok(DedupMethod::Directional),
+        "adjacency" => Ok(DedupMethod::Adjacency),
+        "cluster" => Ok(DedupMethod::Cluster),
+        _ => Err(format!(
+            "Unknown method {:?}, expected directional, adjacency or cluster",
+            name
+        )),
+    }
+}
+
+fn dedup(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, &[])?;
+    if flags.has("--help") || flags.has("-h") {
+        println!("{}", DEDUP_USAGE);
+        return Ok(());
+    }
+
+    let input = flags.required("--input")?;
+    let out_dir = PathBuf::from(flags.required("--out-dir")?);
+    let method = parse_dedup_method(flags.get("--method").unwrap_or("directional"))?;
+    let max_distance = flags.number("--max-distance", 1)?;
+    let n_policy = parse_n_policy(flags.get("--n-policy").unwrap_or("mismatch"))?;
+
+    let reader = File::open(input)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open {}: {}", input, e))?;
+    let mut deduplicator = UmiDeduplicator::new(method, max_distance).with_n_policy(n_policy);
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let umi = match fields.len() {
+            3 => header_umi(fields[0].as_bytes()),
+            4 => Some(fields[3].as_bytes()),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            format!(
+                "{} line {}: expected read id, cell barcode, mapping key and UMI",
+                input,
+                line_number + 1
+            )
+        })?;
+        deduplicator.add(fields[0], fields[1].as_bytes(), fields[2], umi);
+    }
+    let result = deduplicator.finish();
+
+    write_dedup_outputs(&result, &out_dir)
+        .map_err(|e| format!("Could not write outputs: {}", e))?;
+    let summary = serde_json::json!({
+        "method": format!("{:?}", method).to_lowercase(),
+        "max_distance": max_distance,
+        "total_reads": result.total_reads,
+        "skipped_reads": result.skipped_reads,
+        "kept_reads": result.kept_read_ids.len(),
+        "groups": result.groups.len(),
+    });
+    let json = serde_json::to_string_pretty(&summary).map_err(|e| e.to_string())?;
+    fs::write(out_dir.join("summary.json"), &json).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn write_dedup_outputs(result: &DedupResult, out_dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let mut kept = io::BufWriter::new(File::create(out_dir.join("kept_reads.txt"))?);
+    for read_id in &result.kept_read_ids {
+        writeln!(kept, "{}", read_id)?;
+    }
+    kept.flush()?;
+
+    let mut counts = io::BufWriter::new(File::create(out_dir.join("umi_counts.tsv"))?);
+    writeln!(
+        counts,
+        "cell_barcode\tmapping_key\treads\tunique_umis\tmolecules"
+    )?;
+    for group in &result.groups {
+        writeln!(
+            counts,
+            "{}\t{}\t{}\t{}\t{}",
+            group.cell_barcode, group.mapping_key, group.reads, group.unique_umis, group.molecules
+        )?;
+    }
+    counts.flush()
+}
+
+fn read_whitelist(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let reader = File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open {}: {}", path, e))?;
+    let mut barcodes: Vec<Vec<u8>> = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        let barcode = line.trim();
+        if barcode.is_empty() || barcode.starts_with('#') {
+            continue;
+        }
+        let barcode = barcode.to_ascii_uppercase().into_bytes();
+        if !barcode.iter().all(|b| b"ACGT".contains(b)) {
+            return Err(format!(
+                "{} line {}: barcodes must only contain ACGT",
+                path,
+                line_number + 1
+            ));
+        }
+        if let Some(first) = barcodes.first() {
+            if first.len() != barcode.len() {
+                return Err(format!(
+                    "{} line {}: expected a {} base barcode, got {}",
+                    path,
+                    line_number + 1,
+                    first.len(),
+                    barcode.len()
+                ));
+            }
+        }
+        barcodes.push(barcode);
+    }
+    if barcodes.is_empty() {
+        return Err(format!("{} has no barcodes", path));
+    }
+    Ok(barcodes)
+}
+
+fn validate(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, &["--json"])?;
+    if flags.has("--help") || flags.has("-h") {
+        println!("{}", VALIDATE_USAGE);
+        return Ok(());
+    }
+
+    let input = flags.required("--input")?;
+    let threshold = flags.number("--threshold", 3)?;
+    let barcodes = read_whitelist(input)?;
+    let report = validate_barcode_set(&barcodes, threshold);
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    if let Some(out) = flags.get("--out") {
+        fs::write(out, &json).map_err(|e| format!("Could not write {}: {}", out, e))?;
+    }
+    if flags.has("--json") {
+        println!("{}", json);
+    } else {
+        print!("{}", validation_table(&report));
+    }
+    Ok(())
+}
+
+fn distance_rows(table: &mut String, name: &str, stats: &DistanceStats) {
+    let show = |v: Option<usize>| v.map_or("-".to_string(), |v| v.to_string());
+    table.push_str(&format!(
+        "{:<22}min {}, correction radius {}\n",
+        name,
+        show(stats.min),
+        show(stats.correction_radius)
+    ));
+    for (distance, &pairs) in stats.histogram.iter().enumerate() {
+        if pairs > 0 {
+            table.push_str(&format!("{:<22}{:>3}  {} pairs\n", "", distance, pairs));
+        }
+    }
+}
+
+fn validation_table(report: &ValidationReport) -> String {
+    let mut table = String::new();
+    table.push_str(&format!(
+        "{:<22}{} x {} bases\n",
+        "Barcodes", report.barcodes, report.length
+    ));
+    distance_rows(&mut table, "Hamming", &report.hamming);
+    distance_rows(&mut table, "Seq-lev", &report.sequence_levenshtein);
+    table.push_str(&format!(
+        "{:<22}{:.2} / {:.2} / {:.2}\n",
+        "GC min / mean / max", report.gc_min, report.gc_mean, report.gc_max
+    ));
+    table.push_str(&format!(
+        "{:<22}{}\n",
+        "Longest homopolymer", report.max_homopolymer
+    ));
+
+    table.push_str("\nPosition      A     C     G     T   red green\n");
+    for p in &report.position_balance {
+        table.push_str(&format!(
+            "{:>8} {:>5.2} {:>5.2} {:>5.2} {:>5.2} {:>5.2} {:>5.2}\n",
+            p.position + 1,
+            p.a,
+            p.c,
+            p.g,
+            p.t,
+            p.red,
+            p.green
+        ));
+    }
+
+    table.push_str(&format!(
+        "\n{} conflicting pairs below distance {}\n",
+        report.conflicts.len(),
+        report.conflict_threshold
+    ));
+    for c in &report.conflicts {
+        table.push_str(&format!(
+            "{:>6} {} {:>6} {}  hamming {} seq-lev {}\n",
+            c.first, c.first_barcode, c.second, c.second_barcode, c.hamming, c.sequence_levenshtein
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demux_end_to_end() {
+        let dir = std::env::temp_dir().join(format!("algos_n_stuff_demux_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let reads = dir.join("reads.fastq.gz");
+        let sheet = dir.join("samples.csv");
+        let out_dir = dir.join("out");
+
+        let mut writer = create_fastq(&reads, true).unwrap();
+        for (i, seq) in [
+            &b"ACGTACGTTTTTTTTT"[..],
+            b"ACGTACCTTTTTTTTT",
+            b"GGGGCCCCTTTTTTTT",
+            b"CATCATCATTTTTTTT",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let record = FastqRecord {
+                id: format!("read{}", i).into_bytes(),
+                seq: seq.to_vec(),
+                qual: vec![b'I'; seq.len()],
+            };
+            writer.write_record(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        fs::write(&sheet, "name,barcode\nS1,ACGTACGT\nS2,GGGGCCCC\n").unwrap();
+
+        let args: Vec<String> = [
+            "demux",
+            "--input",
+            reads.to_str().unwrap(),
+            "--samples",
+            sheet.to_str().unwrap(),
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+            "--metric",
+            "hamming",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        run(&args).unwrap();
+
+        let summary: DemuxSummary =
+            serde_json::from_str(&fs::read_to_string(out_dir.join("summary.json")).unwrap())
+                .unwrap();
+        assert_eq!(summary.total_reads, 4);
+        assert_eq!(summary.samples[0].reads, 2);
+        assert_eq!(summary.samples[0].corrected_reads, 1);
+        assert_eq!(summary.samples[1].reads, 1);
+        assert_eq!(summary.undetermined_reads, 1);
+
+        let s1 = open_fastq(out_dir.join("S1.fastq")).unwrap().count();
+        let undetermined = open_fastq(out_dir.join("undetermined.fastq"))
+            .unwrap()
+            .count();
+        assert_eq!((s1, undetermined), (2, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dual_index_demux_end_to_end() {
+        let dir =
+            std::env::temp_dir().join(format!("algos_n_stuff_dual_demux_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let reads = dir.join("reads.fastq");
+        let sheet = dir.join("samples.tsv");
+        let out_dir = dir.join("out");
+
+        let mut writer = create_fastq(&reads, false).unwrap();
+        for (i, indices) in [
+            "AAAACCCC+GGGGTTTT",
+            "AAAACCCA+GGGGTTTT",
+            "CCCCGGGG+TTTTAAAA",
+            "AAAACCCC+TTTTAAAA",
+            "TGTGTGTG+TTTTAAAA",
+        ]
+        .iter()
+        .enumerate()
+        {
+            let record = FastqRecord {
+                id: format!("read{} 1:N:0:{}", i, indices).into_bytes(),
+                seq: b"ACGTACGTAC".to_vec(),
+                qual: vec![b'I'; 10],
+            };
+            writer.write_record(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        fs::write(&sheet, "A\tAAAACCCC\tGGGGTTTT\nB\tCCCCGGGG\tTTTTAAAA\n").unwrap();
+
+        let args: Vec<String> = [
+            "demux",
+            "--dual-index",
+            "--input",
+            reads.to_str().unwrap(),
+            "--samples",
+            sheet.to_str().unwrap(),
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        run(&args).unwrap();
+
+        let summary: DualIndexSummary =
+            serde_json::from_str(&fs::read_to_string(out_dir.join("summary.json")).unwrap())
+                .unwrap();
+        assert_eq!(summary.samples[0].reads, 2);
+        assert_eq!(summary.samples[1].reads, 1);
+        assert_eq!(summary.index_hopped_reads, 1);
+        assert_eq!(summary.undetermined_reads, 1);
+
+        let table = fs::read_to_string(out_dir.join("index_hopping.tsv")).unwrap();
+        assert_eq!(table.lines().nth(1), Some("AAAACCCC\tTTTTAAAA\tA\tB\t1"));
+        let undetermined = open_fastq(out_dir.join("undetermined.fastq"))
+            .unwrap()
+            .count();
+        assert_eq!(undetermined, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dedup_end_to_end() {
+        let dir = std::env::temp_dir().join(format!("algos_n_stuff_dedup_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("reads.tsv");
+        let out_dir = dir.join("out");
+        fs::write(
+            &input,
+            "r1_ACGTAC\tCELL1\tchr1:100:+\n\
+             r2_ACGTAC\tCELL1\tchr1:100:+\n\
+             r3_ACGTAC\tCELL1\tchr1:100:+\n\
+             r4_ACGTAA\tCELL1\tchr1:100:+\n\
+             r5_TTTTTT\tCELL1\tchr1:100:+\n\
+             r6\tCELL2\tchr1:100:+\tACGTAC\n",
+        )
+        .unwrap();
+
+        let args: Vec<String> = [
+            "dedup",
+            "--input",
+            input.to_str().unwrap(),
+            "--out-dir",
+            out_dir.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        run(&args).unwrap();
+
+        let kept = fs::read_to_string(out_dir.join("kept_reads.txt")).unwrap();
+        assert_eq!(kept, "r1_ACGTAC\nr5_TTTTTT\nr6\n");
+        let counts = fs::read_to_string(out_dir.join("umi_counts.tsv")).unwrap();
+        assert_eq!(counts.lines().nth(1), Some("CELL1\tchr1:100:+\t5\t3\t2"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(run(&args(&["frobnicate"])).is_err());
+        assert!(run(&args(&["demux", "--input"])).is_err());
+        assert!(run(&args(&["demux", "--input", "x.fastq"])).is_err());
+        let error = run(&args(&[
+            "demux",
+            "--input",
+            "r.fastq",
+            "--samples",
+            "s.csv",
+            "--out-dir",
+            "out",
+            "--dual-index",
+            "--i7",
+            "i1.fastq",
+        ]))
+        .unwrap_err();
+        assert!(error.contains("--i7 and --i5"), "{}", error);
+        assert!(parse_metric("cosine").is_err());
+        assert!(parse_dedup_method("unique").is_err());
+        assert_eq!(parse_n_policy("match"), Ok(NPolicy::Match));
+        assert!(parse_n_policy("ignore").is_err());
+
+        let names = |n: &[&'static str]| check_sample_names(n.iter().copied(), "s.csv");
+        assert!(names(&["S1", "S2"]).is_ok());
+        assert!(names(&["S1", "S1"]).unwrap_err().contains("more than once"));
+        assert!(names(&["../S1"]).is_err());
+        assert!(names(&["a/b"]).is_err());
+        assert!(names(&[".."]).is_err());
+        assert!(names(&[]).is_err());
+    }
+
+    #[test]
+    fn test_validate_end_to_end() {
+        let dir =
+            std::env::temp_dir().join(format!("algos_n_stuff_validate_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("whitelist.txt");
+        let out = dir.join("report.json");
+        fs::write(&input, "# designed set\nAACCGGTT\naaccggta\n\nTTGGCCAA\n").unwrap();
+
+        let args: Vec<String> = [
+            "validate",
+            "--input",
+            input.to_str().unwrap(),
+            "--out",
+            out.to_str().unwrap(),
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        run(&args).unwrap();
+
+        let report: ValidationReport =
+            serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(report.barcodes, 3);
+        assert_eq!(report.hamming.min, Some(1));
+        assert_eq!(report.conflicts.len(), 1);
+        let table = validation_table(&report);
+        assert!(
+            table.contains("1 conflicting pairs below distance 3"),
+            "{}",
+            table
+        );
+
+        fs::write(&input, "AACCGGTT\nAACCGG\n").unwrap();
+        let error = run(&args).unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
-/*
-This is meant to be library, but I will end up using binary for benchmarking
-*/
+mod commands;
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(message) = commands::run(&args) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
 }